@@ -0,0 +1,448 @@
+// Copyright The pipewire-rs Contributors.
+// SPDX-License-Identifier: MIT
+
+use std::{fmt::Debug, mem};
+
+use crate::{
+    param::video::VideoFormat,
+    utils::{Point, Rectangle},
+};
+
+/// The type of a [`Meta`].
+///
+/// `SPA_META_SyncTimeline`, the explicit sync points of DMA-BUF buffers, has no constant or
+/// accessor: it was added in PipeWire 1.2, and the bindings are generated from 0.3 headers.
+/// Such metadata still shows up as a [`Meta`] with its raw [`data()`](Meta::data).
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct MetaType(spa_sys::spa_meta_type);
+
+#[allow(non_upper_case_globals)]
+impl MetaType {
+    pub const Invalid: Self = Self(spa_sys::SPA_META_Invalid);
+    /// Timing and sequence information, a [`MetaHeader`]
+    pub const Header: Self = Self(spa_sys::SPA_META_Header);
+    /// The valid area of a video frame, a [`MetaRegion`]
+    pub const VideoCrop: Self = Self(spa_sys::SPA_META_VideoCrop);
+    /// The areas of a video frame that changed, an array of [`MetaRegion`]s
+    pub const VideoDamage: Self = Self(spa_sys::SPA_META_VideoDamage);
+    /// A [`MetaBitmap`] followed by its pixels
+    pub const Bitmap: Self = Self(spa_sys::SPA_META_Bitmap);
+    /// The pointer of a screen, a [`MetaCursor`]
+    pub const Cursor: Self = Self(spa_sys::SPA_META_Cursor);
+    /// A sequence of control values
+    pub const Control: Self = Self(spa_sys::SPA_META_Control);
+    /// Whether the buffer is in use, a [`MetaBusy`]
+    pub const Busy: Self = Self(spa_sys::SPA_META_Busy);
+
+    pub fn from_raw(raw: spa_sys::spa_meta_type) -> Self {
+        Self(raw)
+    }
+
+    pub fn as_raw(&self) -> spa_sys::spa_meta_type {
+        self.0
+    }
+}
+
+impl Debug for MetaType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = format!(
+            "MetaType::{}",
+            match *self {
+                Self::Invalid => "Invalid",
+                Self::Header => "Header",
+                Self::VideoCrop => "VideoCrop",
+                Self::VideoDamage => "VideoDamage",
+                Self::Bitmap => "Bitmap",
+                Self::Cursor => "Cursor",
+                Self::Control => "Control",
+                Self::Busy => "Busy",
+                _ => "Unknown",
+            }
+        );
+        f.write_str(&name)
+    }
+}
+
+/// Metadata of a buffer, one of the types in [`MetaType`].
+#[repr(transparent)]
+pub struct Meta(spa_sys::spa_meta);
+
+impl Meta {
+    pub fn as_raw(&self) -> &spa_sys::spa_meta {
+        &self.0
+    }
+
+    pub fn type_(&self) -> MetaType {
+        MetaType::from_raw(self.0.type_)
+    }
+
+    /// The size of the metadata in bytes.
+    pub fn size(&self) -> u32 {
+        self.0.size
+    }
+
+    /// The metadata as raw bytes.
+    pub fn data(&self) -> &[u8] {
+        if self.0.data.is_null() {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(self.0.data as *const u8, self.0.size as usize) }
+        }
+    }
+
+    /// The metadata as a `T`, if it is of type `type_`, large enough and aligned.
+    fn get<T>(&self, type_: MetaType) -> Option<&T> {
+        if self.type_() != type_
+            || self.0.data.is_null()
+            || (self.0.size as usize) < mem::size_of::<T>()
+            || self.0.data.align_offset(mem::align_of::<T>()) != 0
+        {
+            return None;
+        }
+        unsafe { Some(&*(self.0.data as *const T)) }
+    }
+
+    fn get_mut<T>(&mut self, type_: MetaType) -> Option<&mut T> {
+        self.get::<T>(type_)?;
+        unsafe { Some(&mut *(self.0.data as *mut T)) }
+    }
+
+    /// The header, if this is [`MetaType::Header`] metadata.
+    pub fn header(&self) -> Option<&MetaHeader> {
+        self.get(MetaType::Header)
+    }
+
+    pub fn header_mut(&mut self) -> Option<&mut MetaHeader> {
+        self.get_mut(MetaType::Header)
+    }
+
+    /// The crop region, if this is [`MetaType::VideoCrop`] metadata.
+    pub fn video_crop(&self) -> Option<&MetaRegion> {
+        self.get(MetaType::VideoCrop)
+    }
+
+    pub fn video_crop_mut(&mut self) -> Option<&mut MetaRegion> {
+        self.get_mut(MetaType::VideoCrop)
+    }
+
+    /// The damaged regions, if this is [`MetaType::VideoDamage`] metadata.
+    ///
+    /// The array ends with the first invalid region.
+    pub fn video_damage(&self) -> Option<&[MetaRegion]> {
+        if self.type_() != MetaType::VideoDamage
+            || self.0.data.is_null()
+            || self.0.data.align_offset(mem::align_of::<MetaRegion>()) != 0
+        {
+            return None;
+        }
+        let regions = unsafe {
+            std::slice::from_raw_parts(
+                self.0.data as *const MetaRegion,
+                self.0.size as usize / mem::size_of::<MetaRegion>(),
+            )
+        };
+        let valid = regions
+            .iter()
+            .take_while(|region| region.is_valid())
+            .count();
+        Some(&regions[..valid])
+    }
+
+    /// The cursor, if this is [`MetaType::Cursor`] metadata.
+    pub fn cursor(&self) -> Option<&MetaCursor> {
+        self.get(MetaType::Cursor)
+    }
+
+    /// The bitmap of the cursor and its pixels, if this is [`MetaType::Cursor`] metadata with
+    /// a bitmap.
+    pub fn cursor_bitmap(&self) -> Option<(&MetaBitmap, &[u8])> {
+        let offset = self.cursor()?.bitmap_offset() as usize;
+        if offset < mem::size_of::<MetaCursor>() {
+            return None;
+        }
+        self.bitmap_at(offset)
+    }
+
+    /// The bitmap and its pixels, if this is [`MetaType::Bitmap`] metadata.
+    pub fn bitmap(&self) -> Option<(&MetaBitmap, &[u8])> {
+        if self.type_() != MetaType::Bitmap {
+            return None;
+        }
+        self.bitmap_at(0)
+    }
+
+    /// The bitmap `offset` bytes into the metadata, `None` if it has no pixels, does not fit or
+    /// is not aligned.
+    fn bitmap_at(&self, offset: usize) -> Option<(&MetaBitmap, &[u8])> {
+        let data = self.data();
+        let header = data.get(offset..offset.checked_add(mem::size_of::<MetaBitmap>())?)?;
+        if header.as_ptr().align_offset(mem::align_of::<MetaBitmap>()) != 0 {
+            return None;
+        }
+        let bitmap = unsafe { &*(header.as_ptr() as *const MetaBitmap) };
+        if bitmap.format() == VideoFormat::Unknown || bitmap.offset() == 0 {
+            return None;
+        }
+
+        let start = offset.checked_add(bitmap.offset() as usize)?;
+        let len = (bitmap.stride().unsigned_abs() as usize)
+            .checked_mul(bitmap.size().height as usize)?;
+        let pixels = data.get(start..start.checked_add(len)?)?;
+        Some((bitmap, pixels))
+    }
+
+    /// The busy counter, if this is [`MetaType::Busy`] metadata.
+    pub fn busy(&self) -> Option<&MetaBusy> {
+        self.get(MetaType::Busy)
+    }
+}
+
+impl Debug for Meta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Meta")
+            .field("type", &self.type_())
+            .field("size", &self.size())
+            .finish()
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub struct MetaHeaderFlags: u32 {
+        /// Data is not continuous with the previous buffer
+        const DISCONT = 1<<0;
+        /// Data might be corrupted
+        const CORRUPTED = 1<<1;
+        /// Media specific marker
+        const MARKER = 1<<2;
+        /// Data contains a codec specific header
+        const HEADER = 1<<3;
+        /// Data contains media neutral data
+        const GAP = 1<<4;
+        /// Cannot be decoded independently
+        const DELTA_UNIT = 1<<5;
+    }
+}
+
+#[repr(transparent)]
+pub struct MetaHeader(spa_sys::spa_meta_header);
+
+impl MetaHeader {
+    pub fn as_raw(&self) -> &spa_sys::spa_meta_header {
+        &self.0
+    }
+
+    pub fn flags(&self) -> MetaHeaderFlags {
+        MetaHeaderFlags::from_bits_retain(self.0.flags)
+    }
+
+    pub fn set_flags(&mut self, flags: MetaHeaderFlags) {
+        self.0.flags = flags.bits();
+    }
+
+    /// Offset in the current cycle
+    pub fn offset(&self) -> u32 {
+        self.0.offset
+    }
+
+    pub fn offset_mut(&mut self) -> &mut u32 {
+        &mut self.0.offset
+    }
+
+    /// Presentation timestamp in nanoseconds
+    pub fn pts(&self) -> i64 {
+        self.0.pts
+    }
+
+    pub fn pts_mut(&mut self) -> &mut i64 {
+        &mut self.0.pts
+    }
+
+    /// Decoding timestamp as a difference with the pts
+    pub fn dts_offset(&self) -> i64 {
+        self.0.dts_offset
+    }
+
+    pub fn dts_offset_mut(&mut self) -> &mut i64 {
+        &mut self.0.dts_offset
+    }
+
+    /// Sequence number, increments with a media specific frequency
+    pub fn seq(&self) -> u64 {
+        self.0.seq
+    }
+
+    pub fn seq_mut(&mut self) -> &mut u64 {
+        &mut self.0.seq
+    }
+}
+
+impl Debug for MetaHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MetaHeader")
+            .field("flags", &self.flags())
+            .field("offset", &self.offset())
+            .field("pts", &self.pts())
+            .field("dts_offset", &self.dts_offset())
+            .field("seq", &self.seq())
+            .finish()
+    }
+}
+
+/// A region of a video frame.
+#[repr(transparent)]
+pub struct MetaRegion(spa_sys::spa_meta_region);
+
+impl MetaRegion {
+    pub fn as_raw(&self) -> &spa_sys::spa_meta_region {
+        &self.0
+    }
+
+    pub fn position(&self) -> Point {
+        self.0.region.position
+    }
+
+    pub fn position_mut(&mut self) -> &mut Point {
+        &mut self.0.region.position
+    }
+
+    pub fn size(&self) -> Rectangle {
+        self.0.region.size
+    }
+
+    pub fn size_mut(&mut self) -> &mut Rectangle {
+        &mut self.0.region.size
+    }
+
+    /// Whether the region is not empty.
+    pub fn is_valid(&self) -> bool {
+        self.0.region.size.width != 0 && self.0.region.size.height != 0
+    }
+}
+
+impl Debug for MetaRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Point { x, y } = self.position();
+        let Rectangle { width, height } = self.size();
+        f.debug_struct("MetaRegion")
+            .field("position", &(x, y))
+            .field("size", &(width, height))
+            .finish()
+    }
+}
+
+/// The header of an image, followed by its pixels.
+#[repr(transparent)]
+pub struct MetaBitmap(spa_sys::spa_meta_bitmap);
+
+impl MetaBitmap {
+    pub fn as_raw(&self) -> &spa_sys::spa_meta_bitmap {
+        &self.0
+    }
+
+    /// The format of the pixels, [`VideoFormat::Unknown`] if there is no image
+    pub fn format(&self) -> VideoFormat {
+        VideoFormat::from_raw(self.0.format)
+    }
+
+    pub fn size(&self) -> Rectangle {
+        self.0.size
+    }
+
+    /// The number of bytes to the next line of pixels
+    pub fn stride(&self) -> i32 {
+        self.0.stride
+    }
+
+    /// The offset of the pixels from the start of the bitmap header
+    pub fn offset(&self) -> u32 {
+        self.0.offset
+    }
+}
+
+impl Debug for MetaBitmap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Rectangle { width, height } = self.size();
+        f.debug_struct("MetaBitmap")
+            .field("format", &self.format())
+            .field("size", &(width, height))
+            .field("stride", &self.stride())
+            .field("offset", &self.offset())
+            .finish()
+    }
+}
+
+#[repr(transparent)]
+pub struct MetaCursor(spa_sys::spa_meta_cursor);
+
+impl MetaCursor {
+    pub fn as_raw(&self) -> &spa_sys::spa_meta_cursor {
+        &self.0
+    }
+
+    /// The id of the cursor, 0 if there is no cursor
+    pub fn id(&self) -> u32 {
+        self.0.id
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.0.flags
+    }
+
+    /// The position of the cursor on the frame
+    pub fn position(&self) -> Point {
+        self.0.position
+    }
+
+    /// The position of the hotspot in the cursor image
+    pub fn hotspot(&self) -> Point {
+        self.0.hotspot
+    }
+
+    /// The offset of the [`MetaBitmap`] of the cursor image from the start of the cursor, 0 if
+    /// there is none
+    pub fn bitmap_offset(&self) -> u32 {
+        self.0.bitmap_offset
+    }
+}
+
+impl Debug for MetaCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let position = self.position();
+        let hotspot = self.hotspot();
+        f.debug_struct("MetaCursor")
+            .field("id", &self.id())
+            .field("flags", &self.flags())
+            .field("position", &(position.x, position.y))
+            .field("hotspot", &(hotspot.x, hotspot.y))
+            .field("bitmap_offset", &self.bitmap_offset())
+            .finish()
+    }
+}
+
+#[repr(transparent)]
+pub struct MetaBusy(spa_sys::spa_meta_busy);
+
+impl MetaBusy {
+    pub fn as_raw(&self) -> &spa_sys::spa_meta_busy {
+        &self.0
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.0.flags
+    }
+
+    /// The number of users of the buffer, it is busy while this is not 0
+    pub fn count(&self) -> u32 {
+        self.0.count
+    }
+}
+
+impl Debug for MetaBusy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MetaBusy")
+            .field("flags", &self.flags())
+            .field("count", &self.count())
+            .finish()
+    }
+}
@@ -3,6 +3,9 @@
 
 use std::{convert::TryFrom, fmt::Debug};
 
+mod meta;
+pub use meta::*;
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub struct DataType(spa_sys::spa_data_type);
 
@@ -0,0 +1,182 @@
+// Copyright The pipewire-rs Contributors.
+// SPDX-License-Identifier: MIT
+
+//! Requesting metadata on buffers with [`ParamType::Meta`] params.
+
+use std::mem;
+
+use crate::{
+    buffer::MetaType,
+    param::ParamType,
+    pod::{ChoiceValue, Object, Property, Value},
+    utils::{Choice, ChoiceEnum, ChoiceFlags, SpaTypes},
+};
+
+/// A [`ParamType::Meta`] param, asking for metadata of a type and size on every buffer.
+///
+/// Convert it into an [`Object`] to pass it to a stream or node along with its other params.
+/// Sizes are stored as `i32`, larger ones are clamped to `i32::MAX`.
+///
+/// # Examples
+/// Ask for timestamps and the cursor of a screen capture.
+/// ```rust
+/// use libspa::{param::meta::MetaParam, pod::Object};
+///
+/// let params: Vec<Object> = vec![
+///     MetaParam::header().into(),
+///     MetaParam::cursor(64, 64).into(),
+/// ];
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetaParam {
+    type_: MetaType,
+    size: Choice<i32>,
+}
+
+impl MetaParam {
+    /// Metadata of `type_` taking `size` bytes.
+    pub fn new(type_: MetaType, size: u32) -> Self {
+        Self {
+            type_,
+            size: Choice(ChoiceFlags::empty(), ChoiceEnum::None(pod_size(size))),
+        }
+    }
+
+    /// Metadata of `type_` taking between `min` and `max` bytes, preferably `default`.
+    pub fn with_size_range(type_: MetaType, default: u32, min: u32, max: u32) -> Self {
+        Self {
+            type_,
+            size: Choice(
+                ChoiceFlags::empty(),
+                ChoiceEnum::Range {
+                    default: pod_size(default),
+                    min: pod_size(min),
+                    max: pod_size(max),
+                },
+            ),
+        }
+    }
+
+    /// [`MetaType::Header`] metadata.
+    pub fn header() -> Self {
+        Self::new(
+            MetaType::Header,
+            mem::size_of::<spa_sys::spa_meta_header>() as u32,
+        )
+    }
+
+    /// [`MetaType::VideoCrop`] metadata.
+    pub fn video_crop() -> Self {
+        Self::new(
+            MetaType::VideoCrop,
+            mem::size_of::<spa_sys::spa_meta_region>() as u32,
+        )
+    }
+
+    /// [`MetaType::VideoDamage`] metadata with room for up to `max_regions` regions.
+    pub fn video_damage(max_regions: u32) -> Self {
+        let region = mem::size_of::<spa_sys::spa_meta_region>() as u32;
+        Self::with_size_range(
+            MetaType::VideoDamage,
+            region.saturating_mul(max_regions),
+            region,
+            region.saturating_mul(max_regions),
+        )
+    }
+
+    /// [`MetaType::Cursor`] metadata with room for a cursor image of up to `max_width` by
+    /// `max_height` pixels of 4 bytes.
+    pub fn cursor(max_width: u32, max_height: u32) -> Self {
+        let size = |width: u32, height: u32| {
+            let header = (mem::size_of::<spa_sys::spa_meta_cursor>()
+                + mem::size_of::<spa_sys::spa_meta_bitmap>()) as u32;
+            width
+                .checked_mul(height)
+                .and_then(|pixels| pixels.checked_mul(4))
+                .and_then(|bytes| bytes.checked_add(header))
+                .unwrap_or(u32::MAX)
+        };
+        Self::with_size_range(
+            MetaType::Cursor,
+            size(max_width.min(64), max_height.min(64)),
+            size(1, 1),
+            size(max_width, max_height),
+        )
+    }
+
+    /// [`MetaType::Busy`] metadata.
+    pub fn busy() -> Self {
+        Self::new(
+            MetaType::Busy,
+            mem::size_of::<spa_sys::spa_meta_busy>() as u32,
+        )
+    }
+
+    pub fn type_(&self) -> MetaType {
+        self.type_
+    }
+}
+
+/// `size` as the `i32` of a pod, clamped to `i32::MAX`.
+fn pod_size(size: u32) -> i32 {
+    i32::try_from(size).unwrap_or(i32::MAX)
+}
+
+impl From<MetaParam> for Object {
+    fn from(value: MetaParam) -> Self {
+        let size = match value.size {
+            Choice(_, ChoiceEnum::None(size)) => Value::Int(size),
+            size => Value::Choice(ChoiceValue::Int(size)),
+        };
+        Object {
+            type_: SpaTypes::ObjectParamMeta.as_raw(),
+            id: ParamType::Meta.as_raw(),
+            properties: vec![
+                Property::new(
+                    spa_sys::SPA_PARAM_META_type,
+                    Value::Id(crate::utils::Id(value.type_.as_raw())),
+                ),
+                Property::new(spa_sys::SPA_PARAM_META_size, size),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_param() {
+        let object: Object = MetaParam::cursor(32, 32).into();
+        assert_eq!(object.type_, spa_sys::SPA_TYPE_OBJECT_ParamMeta);
+        assert_eq!(object.id, spa_sys::SPA_PARAM_Meta);
+        assert_eq!(
+            object.properties[0].value,
+            Value::Id(crate::utils::Id(spa_sys::SPA_META_Cursor))
+        );
+
+        let header = (mem::size_of::<spa_sys::spa_meta_cursor>()
+            + mem::size_of::<spa_sys::spa_meta_bitmap>()) as i32;
+        assert_eq!(
+            object.properties[1].value,
+            Value::Choice(ChoiceValue::Int(Choice(
+                ChoiceFlags::empty(),
+                ChoiceEnum::Range {
+                    default: header + 32 * 32 * 4,
+                    min: header + 4,
+                    max: header + 32 * 32 * 4,
+                }
+            )))
+        );
+
+        // Too large for the pod, but no overflow.
+        let object: Object = MetaParam::cursor(u32::MAX, u32::MAX).into();
+        let Value::Choice(ChoiceValue::Int(Choice(_, ChoiceEnum::Range { max, .. }))) =
+            object.properties[1].value
+        else {
+            panic!("size is not a range");
+        };
+        assert_eq!(max, i32::MAX);
+    }
+}
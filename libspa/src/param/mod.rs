@@ -6,6 +6,7 @@
 pub mod audio;
 pub mod format;
 pub mod format_utils;
+pub mod meta;
 pub mod video;
 
 use std::ffi::CStr;
@@ -0,0 +1,743 @@
+//! SPA-JSON, the relaxed JSON PipeWire uses for metadata values, properties and config files.
+//!
+//! Every JSON document is valid SPA-JSON, which additionally allows:
+//!
+//! - bare words in place of strings, `{ name = alsa_output.pci-0000_00_1f.3 }`,
+//! - `=` in place of `:` and leaving out the `,` between items,
+//! - comments from `#` to the end of the line,
+//! - leaving out the braces around the object at the top of a config file, see
+//!   [`parse_relaxed`].
+//!
+//! A [`Tokenizer`] splits input into [`Token`]s like `spa_json_next` does, [`parse`] builds a
+//! [`Value`] from them and [`from_str`] converts it to any [`FromValue`] type. A [`Value`] is
+//! written back as plain JSON through its [`Display`](fmt::Display) implementation, which
+//! every SPA-JSON reader accepts.
+//!
+//! # Examples
+//! ```
+//! use libspa::utils::json::{self, Value};
+//!
+//! let value = json::parse(r#"{ "name": "alsa_output.pci", channels = 2 }"#).unwrap();
+//! assert_eq!(value.get_as::<String>("name").unwrap(), "alsa_output.pci");
+//! assert_eq!(value.get_as::<u32>("channels").unwrap(), 2);
+//!
+//! let serials: Vec<u64> = json::from_str("[ 40 41 42 ]").unwrap();
+//! assert_eq!(serials, [40, 41, 42]);
+//!
+//! let value = Value::object([("name", Value::from("sink"))]);
+//! assert_eq!(value.to_string(), r#"{"name":"sink"}"#);
+//! ```
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Containers nested deeper than this are rejected instead of overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+/// An error while parsing or converting SPA-JSON.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The input is not valid SPA-JSON at byte `position`.
+    Syntax {
+        position: usize,
+        message: &'static str,
+    },
+    /// A value does not have the type it is converted to.
+    Type { expected: &'static str },
+    /// An object has no member named `0`.
+    MissingKey(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Syntax { position, message } => write!(f, "{} at byte {}", message, position),
+            Error::Type { expected } => write!(f, "expected {}", expected),
+            Error::MissingKey(key) => write!(f, "missing key {:?}", key),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A token of SPA-JSON, see [`Tokenizer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token<'a> {
+    BeginObject,
+    EndObject,
+    BeginArray,
+    EndArray,
+    /// A quoted string, with its escapes resolved.
+    String(Cow<'a, str>),
+    /// A bare word: a number, `true`, `false`, `null` or an unquoted string.
+    Bare(&'a str),
+}
+
+/// Splits SPA-JSON into [`Token`]s.
+///
+/// Separators, that is whitespace, `,`, `:` and `=`, and comments are skipped. Like
+/// `spa_json_next`, the tokenizer does not check that the tokens form a valid document, see
+/// [`parse`] for that.
+pub struct Tokenizer<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self { input, position: 0 }
+    }
+
+    /// The byte offset of the next token.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The next token, `None` at the end of the input.
+    pub fn next_token(&mut self) -> Result<Option<Token<'a>>, Error> {
+        self.skip_separators();
+        let rest = &self.input[self.position..];
+        let Some(first) = rest.chars().next() else {
+            return Ok(None);
+        };
+
+        let token = match first {
+            '{' => Token::BeginObject,
+            '}' => Token::EndObject,
+            '[' => Token::BeginArray,
+            ']' => Token::EndArray,
+            '"' => return self.string().map(Some),
+            _ => {
+                let len = rest.find(ends_bare).unwrap_or(rest.len());
+                self.position += len;
+                return Ok(Some(Token::Bare(&rest[..len])));
+            }
+        };
+        self.position += 1;
+        Ok(Some(token))
+    }
+
+    fn skip_separators(&mut self) {
+        let bytes = self.input.as_bytes();
+        while let Some(&byte) = bytes.get(self.position) {
+            match byte {
+                b'#' => {
+                    self.position = bytes[self.position..]
+                        .iter()
+                        .position(|&byte| byte == b'\n')
+                        .map_or(bytes.len(), |newline| self.position + newline);
+                }
+                b',' | b':' | b'=' => self.position += 1,
+                byte if byte.is_ascii_whitespace() => self.position += 1,
+                _ => break,
+            }
+        }
+    }
+
+    /// The quoted string at the current position.
+    fn string(&mut self) -> Result<Token<'a>, Error> {
+        let start = self.position;
+        let body = &self.input[start + 1..];
+        let error = |offset: usize, message| Error::Syntax {
+            position: start + 1 + offset,
+            message,
+        };
+
+        // Borrow the input unless there are escapes to resolve.
+        let end = body
+            .find(&['"', '\\'][..])
+            .ok_or_else(|| error(body.len(), "unterminated string"))?;
+        if body.as_bytes()[end] == b'"' {
+            self.position = start + end + 2;
+            return Ok(Token::String(Cow::Borrowed(&body[..end])));
+        }
+
+        let mut string = String::from(&body[..end]);
+        let mut chars = body[end..]
+            .char_indices()
+            .map(|(offset, c)| (end + offset, c));
+        loop {
+            let Some((offset, c)) = chars.next() else {
+                return Err(error(body.len(), "unterminated string"));
+            };
+            match c {
+                '"' => {
+                    self.position = start + 1 + offset + 1;
+                    return Ok(Token::String(Cow::Owned(string)));
+                }
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, c)| c) {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let unit = hex4(&mut chars)
+                                .ok_or_else(|| error(offset, "invalid \\u escape"))?;
+                            let code = if (0xd800..0xdc00).contains(&unit) {
+                                // A surrogate pair, the low half follows as another escape.
+                                let low = match (chars.next(), chars.next()) {
+                                    (Some((_, '\\')), Some((_, 'u'))) => hex4(&mut chars),
+                                    _ => None,
+                                };
+                                match low {
+                                    Some(low @ 0xdc00..=0xdfff) => {
+                                        0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00)
+                                    }
+                                    _ => return Err(error(offset, "unpaired surrogate")),
+                                }
+                            } else {
+                                unit
+                            };
+                            char::from_u32(code)
+                                .ok_or_else(|| error(offset, "invalid \\u escape"))?
+                        }
+                        // `\"`, `\\`, `\/` and anything else stand for the character itself.
+                        Some(c) => c,
+                        None => return Err(error(body.len(), "unterminated string")),
+                    };
+                    string.push(escaped);
+                }
+                c => string.push(c),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Result<Token<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token().transpose()
+    }
+}
+
+/// Whether `c` ends a bare word.
+fn ends_bare(c: char) -> bool {
+    c.is_ascii_whitespace() || matches!(c, ',' | ':' | '=' | '{' | '}' | '[' | ']' | '"' | '#')
+}
+
+/// The value of the 4 hex digits `chars` continues with.
+fn hex4(chars: &mut impl Iterator<Item = (usize, char)>) -> Option<u32> {
+    (0..4).try_fold(0, |code, _| Some(code * 16 + chars.next()?.1.to_digit(16)?))
+}
+
+/// A parsed SPA-JSON value.
+///
+/// Bare words are numbers, booleans or `null` if they read as one, and strings otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// The members of an object in the order they appear in, keys may repeat.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// An object with the members `members`.
+    pub fn object<K, I>(members: I) -> Self
+    where
+        K: Into<String>,
+        I: IntoIterator<Item = (K, Value)>,
+    {
+        Value::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        )
+    }
+
+    /// The value of a bare word.
+    fn from_bare(word: &str) -> Self {
+        match word {
+            "null" => return Value::Null,
+            "true" => return Value::Bool(true),
+            "false" => return Value::Bool(false),
+            _ => {}
+        }
+        if let Ok(int) = word.parse() {
+            return Value::Int(int);
+        }
+        let numeric = word.bytes().any(|byte| byte.is_ascii_digit())
+            && word
+                .bytes()
+                .all(|byte| byte.is_ascii_digit() || b"+-.eE".contains(&byte));
+        match word.parse() {
+            Ok(float) if numeric => Value::Float(float),
+            _ => Value::String(word.to_string()),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The value as an integer, also for floats without a fractional part.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(value) => Some(*value),
+            Value::Float(value) if value.fract() == 0.0 && value.abs() < i64::MAX as f64 => {
+                Some(*value as i64)
+            }
+            _ => None,
+        }
+    }
+
+    /// The value as a float, also for integers.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(value) => Some(*value as f64),
+            Value::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, Value)]> {
+        match self {
+            Value::Object(members) => Some(members),
+            _ => None,
+        }
+    }
+
+    /// The member `key` of an object.
+    ///
+    /// If the key repeats, the last member wins, as it does when PipeWire reads config files.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.as_object()?
+            .iter()
+            .rev()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+    }
+
+    /// The member `key` of an object, converted to `T`.
+    pub fn get_as<T: FromValue>(&self, key: &str) -> Result<T, Error> {
+        let value = self
+            .get(key)
+            .ok_or_else(|| Error::MissingKey(key.to_string()))?;
+        T::from_value(value)
+    }
+}
+
+impl fmt::Display for Value {
+    /// Write the value as JSON, on a single line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Int(value) => write!(f, "{}", value),
+            // JSON has no infinity or NaN.
+            Value::Float(value) if !value.is_finite() => f.write_str("null"),
+            Value::Float(value) => write!(f, "{:?}", value),
+            Value::String(value) => write_string(f, value),
+            Value::Array(values) => {
+                f.write_str("[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+            Value::Object(members) => {
+                f.write_str("{")?;
+                for (index, (key, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// Write `value` as a quoted string.
+fn write_string(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            '\r' => f.write_str("\\r")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Self {
+        Value::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+/// Builds [`Value`]s from [`Token`]s.
+struct Parser<'a> {
+    tokens: Tokenizer<'a>,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &'static str) -> Error {
+        Error::Syntax {
+            position: self.tokens.position(),
+            message,
+        }
+    }
+
+    /// The value starting with the token `token`.
+    fn value(&mut self, token: Token<'a>, depth: usize) -> Result<Value, Error> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+        match token {
+            Token::BeginObject => Ok(Value::Object(self.members(Some(Token::EndObject), depth)?)),
+            Token::BeginArray => {
+                let mut values = Vec::new();
+                loop {
+                    match self.tokens.next_token()? {
+                        Some(Token::EndArray) => return Ok(Value::Array(values)),
+                        Some(token) => values.push(self.value(token, depth + 1)?),
+                        None => return Err(self.error("unterminated array")),
+                    }
+                }
+            }
+            Token::EndObject | Token::EndArray => Err(self.error("unexpected closing bracket")),
+            Token::String(value) => Ok(Value::String(value.into_owned())),
+            Token::Bare(word) => Ok(Value::from_bare(word)),
+        }
+    }
+
+    /// The members of an object up to the token `end`, or up to the end of the input.
+    fn members(&mut self, end: Option<Token>, depth: usize) -> Result<Vec<(String, Value)>, Error> {
+        let mut members = Vec::new();
+        loop {
+            let key = match self.tokens.next_token()? {
+                token if token == end => return Ok(members),
+                Some(Token::String(key)) => key.into_owned(),
+                Some(Token::Bare(key)) => key.to_string(),
+                Some(_) => return Err(self.error("expected a key")),
+                None => return Err(self.error("unterminated object")),
+            };
+            match self.tokens.next_token()? {
+                Some(token) if token != Token::EndObject => {
+                    members.push((key, self.value(token, depth + 1)?));
+                }
+                _ => return Err(self.error("expected a value")),
+            }
+        }
+    }
+
+    /// Fail unless the input is used up.
+    fn finish(mut self) -> Result<(), Error> {
+        match self.tokens.next_token()? {
+            Some(_) => Err(self.error("trailing characters")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parse the single SPA-JSON value in `input`.
+pub fn parse(input: &str) -> Result<Value, Error> {
+    let mut parser = Parser {
+        tokens: Tokenizer::new(input),
+    };
+    let token = parser
+        .tokens
+        .next_token()?
+        .ok_or_else(|| parser.error("expected a value"))?;
+    let value = parser.value(token, 0)?;
+    parser.finish()?;
+    Ok(value)
+}
+
+/// Like [`parse`], but the braces around an object at the top can be left out, as they are in
+/// config files.
+///
+/// Empty input is an empty object.
+pub fn parse_relaxed(input: &str) -> Result<Value, Error> {
+    match Tokenizer::new(input).next_token()? {
+        Some(Token::BeginObject | Token::BeginArray) => parse(input),
+        _ => {
+            let mut parser = Parser {
+                tokens: Tokenizer::new(input),
+            };
+            let members = parser.members(None, 0)?;
+            parser.finish()?;
+            Ok(Value::Object(members))
+        }
+    }
+}
+
+/// Parse the single SPA-JSON value in `input` and convert it to `T`.
+pub fn from_str<T: FromValue>(input: &str) -> Result<T, Error> {
+    T::from_value(&parse(input)?)
+}
+
+/// Conversion of a [`Value`] into a Rust type.
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, Error>;
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        Ok(value.clone())
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        value.as_bool().ok_or(Error::Type {
+            expected: "a boolean",
+        })
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        value.as_str().map(str::to_string).ok_or(Error::Type {
+            expected: "a string",
+        })
+    }
+}
+
+macro_rules! impl_from_value_int {
+    ($($type_:ty)*) => {
+        $(
+            impl FromValue for $type_ {
+                fn from_value(value: &Value) -> Result<Self, Error> {
+                    value
+                        .as_i64()
+                        .and_then(|value| value.try_into().ok())
+                        .ok_or(Error::Type {
+                            expected: concat!("an integer fitting ", stringify!($type_)),
+                        })
+                }
+            }
+        )*
+    };
+}
+
+impl_from_value_int!(i8 i16 i32 i64 u8 u16 u32 u64 usize);
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        value.as_f64().ok_or(Error::Type {
+            expected: "a number",
+        })
+    }
+}
+
+impl FromValue for f32 {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        f64::from_value(value).map(|value| value as f32)
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    /// `None` for `null`.
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        value
+            .as_array()
+            .ok_or(Error::Type {
+                expected: "an array",
+            })?
+            .iter()
+            .map(T::from_value)
+            .collect()
+    }
+}
+
+impl<T: FromValue> FromValue for HashMap<String, T> {
+    /// Later members win over earlier ones with the same key.
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        value
+            .as_object()
+            .ok_or(Error::Type {
+                expected: "an object",
+            })?
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), T::from_value(value)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let tokens: Result<Vec<_>, _> =
+            Tokenizer::new("{ key = \"a\\\"b\" # comment\n list: [1, two] }").collect();
+        assert_eq!(
+            tokens.unwrap(),
+            [
+                Token::BeginObject,
+                Token::Bare("key"),
+                Token::String(Cow::Owned("a\"b".to_string())),
+                Token::Bare("list"),
+                Token::BeginArray,
+                Token::Bare("1"),
+                Token::Bare("two"),
+                Token::EndArray,
+                Token::EndObject,
+            ]
+        );
+        assert_eq!(
+            Tokenizer::new("\"open").next_token(),
+            Err(Error::Syntax {
+                position: 5,
+                message: "unterminated string",
+            })
+        );
+    }
+
+    #[test]
+    fn values() {
+        let value =
+            parse(r#"{ "a": null, b = true, c: -3 d 1.5e3 e: sink.name f: "é😀" }"#).unwrap();
+        assert_eq!(
+            value,
+            Value::object([
+                ("a", Value::Null),
+                ("b", Value::Bool(true)),
+                ("c", Value::Int(-3)),
+                ("d", Value::Float(1500.0)),
+                ("e", Value::from("sink.name")),
+                ("f", Value::from("\u{e9}\u{1f600}")),
+            ])
+        );
+        assert_eq!(parse(&value.to_string()).unwrap(), value);
+
+        assert!(parse("[1, 2").is_err());
+        assert!(parse("{ key }").is_err());
+        assert!(parse("1 2").is_err());
+        assert!(parse(&"[".repeat(MAX_DEPTH + 2)).is_err());
+    }
+
+    #[test]
+    fn relaxed() {
+        let config = parse_relaxed("# config\ncontext.properties = { default.clock.rate = 48000 }\nkey = value\nkey = other\n").unwrap();
+        let properties = config.get("context.properties").unwrap();
+        assert_eq!(properties.get_as::<u32>("default.clock.rate"), Ok(48000));
+        assert_eq!(config.get_as::<String>("key").as_deref(), Ok("other"));
+        assert_eq!(parse_relaxed("").unwrap(), Value::Object(Vec::new()));
+        assert_eq!(parse_relaxed(" [1]").unwrap(), Value::from(vec![1]));
+    }
+
+    #[test]
+    fn typed() {
+        assert_eq!(
+            from_str::<Vec<Option<u8>>>("[1 null 3]"),
+            Ok(vec![Some(1), None, Some(3)])
+        );
+        assert_eq!(
+            from_str::<String>(r#""\ud83d\ude00\u00e9""#),
+            Ok("\u{1f600}\u{e9}".to_string())
+        );
+        assert_eq!(
+            from_str::<u8>("256"),
+            Err(Error::Type {
+                expected: "an integer fitting u8",
+            })
+        );
+        let map: HashMap<String, f32> = from_str("{ a: 1, b: 0.5 }").unwrap();
+        assert_eq!(map["b"], 0.5);
+        assert_eq!(
+            parse("{}").unwrap().get_as::<bool>("a"),
+            Err(Error::MissingKey("a".to_string()))
+        );
+    }
+}
@@ -4,6 +4,7 @@ pub mod dict;
 mod direction;
 pub use direction::*;
 pub mod hook;
+pub mod json;
 pub mod list;
 pub mod result;
 
@@ -12,6 +13,7 @@ use convert_case::{Case, Casing};
 use std::{ffi::CStr, fmt::Debug, os::raw::c_uint};
 
 pub use spa_sys::spa_fraction as Fraction;
+pub use spa_sys::spa_point as Point;
 pub use spa_sys::spa_rectangle as Rectangle;
 
 use crate::pod::CanonicalFixedSizedPod;
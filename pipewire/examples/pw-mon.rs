@@ -1,38 +1,53 @@
-use std::ops::{Deref, DerefMut};
-use std::sync::{Arc, mpsc, Mutex};
 use std::thread;
-use std::thread::Thread;
-use libc::sleep;
-use pipewire::{main_loop, pipe_wire};
-use pipewire::pipe_wire::*;
-use pipewire::pipe_wire_manager::PipeWireManager;
-
-struct Userdata {
-    pub(crate) format: spa::param::audio::AudioInfoRaw,
-    pub(crate) cursor_move: bool,
-}
+use std::time::Duration;
+use pipewire::e_stream::Frames;
+use pipewire::pipe_wire_manager::{ManagerEvent, PipeWireManager, ReconnectPolicy};
 
 fn main() {
-    let (send, receive) = mpsc::channel();
-    thread::spawn(move || {
-        let mut manager = PipeWireManager::new(receive);
-        let user_data = Userdata {
-            format: spa::param::audio::AudioInfoRaw::new(),
-            cursor_move: false,
+    let mut manager = PipeWireManager::new();
+    manager.set_reconnect_policy(Some(ReconnectPolicy::default()));
+
+    // Print the peak of every channel for each block of captured frames.
+    let sink = |frames: &Frames| {
+        let Some(samples) = frames.as_f32() else {
+            return;
         };
+        let n_channels = frames.info().channels() as usize;
+        if n_channels == 0 {
+            return;
+        }
+        for c in 0..n_channels {
+            let peak = samples
+                .iter()
+                .skip(c)
+                .step_by(n_channels)
+                .fold(0.0f32, |max, s| max.max(s.abs()));
+            println!("channel {}: peak {}", c, peak);
+        }
+    };
 
-        manager.setup_main(None, true, Some(user_data));
+    // Failures on the PipeWire thread are reported as events instead of panicking.
+    let events = manager.subscribe();
+    thread::spawn(move || {
+        for event in events {
+            match event {
+                ManagerEvent::Error(error) => eprintln!("error: {}", error),
+                ManagerEvent::Disconnected => eprintln!("disconnected from PipeWire"),
+                ManagerEvent::Reconnected { attempts } => {
+                    eprintln!("reconnected after {} attempts", attempts)
+                }
+                _ => {}
+            }
+        }
     });
 
-    //thread::spawn(move || {
-    //unsafe { sleep(3); }
-    send.send(72).unwrap();
-        loop {
+    // The manager runs PipeWire on its own thread, so this returns right away.
+    let handle = manager
+        .setup_main(None, true, Some(Box::new(sink)))
+        .expect("Failed to start the PipeWire thread");
 
-            //println!("Sent message");
-        }
-    //});
+    thread::sleep(Duration::from_secs(10));
 
-    // find a node that has google in the object_path it may not be the full string
-    //println!("Program ended")
+    handle.stop();
+    handle.join().unwrap();
 }
@@ -0,0 +1,95 @@
+//! Selecting application output nodes to capture from.
+//!
+//! An [`AppSelector`] describes which application a capture should follow. It is matched against
+//! the properties of every node the registry announces, so the selection keeps working as the
+//! application creates and destroys its output streams.
+
+use regex::Regex;
+
+use crate::graph::GraphNode;
+
+/// Media class used by applications playing audio into the graph.
+pub const AUDIO_OUTPUT_STREAM: &str = "Stream/Output/Audio";
+
+/// Describes the application (or node) a capture stream should follow.
+#[derive(Debug, Clone)]
+pub enum AppSelector {
+    /// Matches the `application.name` property, e.g. `"Firefox"`.
+    ApplicationName(String),
+    /// Matches the `application.process.binary` property, e.g. `"firefox"`.
+    ProcessBinary(String),
+    /// Matches the `application.process.id` property.
+    ProcessId(u32),
+    /// Matches the `node.name` property against a regular expression.
+    NodeName(Regex),
+}
+
+impl AppSelector {
+    /// Create a selector matching `node.name` against `pattern`.
+    pub fn node_name(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(AppSelector::NodeName)
+    }
+
+    /// Check whether `node` belongs to the selected application.
+    pub fn matches(&self, node: &GraphNode) -> bool {
+        match self {
+            AppSelector::ApplicationName(name) => node.app_name() == Some(name.as_str()),
+            AppSelector::ProcessBinary(binary) => node.process_binary() == Some(binary.as_str()),
+            AppSelector::ProcessId(pid) => node.process_id() == Some(*pid),
+            AppSelector::NodeName(regex) => node.name().map_or(false, |name| regex.is_match(name)),
+        }
+    }
+
+    /// Check whether `node` is an audio output stream of the selected application.
+    ///
+    /// Only these nodes are followed by a capture, other nodes of the same application
+    /// (e.g. its recording streams) are ignored.
+    pub fn matches_output(&self, node: &GraphNode) -> bool {
+        node.media_class() == Some(AUDIO_OUTPUT_STREAM) && self.matches(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::GraphNodeState;
+
+    fn node(id: u32, media_class: &str) -> GraphNode {
+        let props = [
+            ("node.name", "Firefox"),
+            ("media.class", media_class),
+            ("application.name", "Firefox"),
+            ("application.process.binary", "firefox"),
+            ("application.process.id", "4242"),
+        ];
+        GraphNode {
+            id,
+            state: GraphNodeState::Running,
+            n_input_ports: 0,
+            n_output_ports: 2,
+            props: props
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn matches_application_properties() {
+        let output = node(42, AUDIO_OUTPUT_STREAM);
+
+        assert!(AppSelector::ApplicationName("Firefox".to_string()).matches_output(&output));
+        assert!(AppSelector::ProcessBinary("firefox".to_string()).matches_output(&output));
+        assert!(AppSelector::ProcessId(4242).matches_output(&output));
+        assert!(AppSelector::node_name("^Fire").unwrap().matches_output(&output));
+        assert!(!AppSelector::ProcessId(1).matches_output(&output));
+    }
+
+    #[test]
+    fn ignores_non_output_nodes() {
+        let input = node(43, "Stream/Input/Audio");
+
+        assert!(AppSelector::ProcessId(4242).matches(&input));
+        assert!(!AppSelector::ProcessId(4242).matches_output(&input));
+    }
+}
@@ -0,0 +1,196 @@
+//! Async streams bridging the PipeWire thread to a tokio runtime.
+//!
+//! The PipeWire thread only ever does non-blocking sends into these streams, and the realtime
+//! thread only queues for the PipeWire thread, so neither waits on the runtime. See
+//! [`PipeWireManager::events`](crate::pipe_wire_manager::PipeWireManager::events) and
+//! [`ManagerHandle::capture_stream`](crate::pipe_wire_manager::ManagerHandle::capture_stream).
+
+use std::fmt;
+use std::mem;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::Stream;
+use spa::param::audio::AudioInfoRaw;
+use tokio::sync::mpsc;
+
+use crate::e_stream::{frame_size, Frames, SampleSink, MAX_QUANTUM};
+use crate::pipe_wire_manager::ManagerEvent;
+use crate::rt_queue::{self, Consumer, Producer};
+use crate::Error;
+
+/// The [`ManagerEvent`]s of a manager as an async stream.
+///
+/// The stream is unbounded, events pile up if it is not polled.
+pub struct EventStream {
+    pub(crate) receiver: mpsc::UnboundedReceiver<ManagerEvent>,
+}
+
+impl Stream for EventStream {
+    type Item = ManagerEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// A block of frames captured by a stream, copied out of the realtime thread.
+///
+/// The samples are copied into a buffer from a pool owned by the stream. Dropping the chunk
+/// hands the buffer back, so the realtime thread never allocates.
+pub struct SampleChunk {
+    info: AudioInfoRaw,
+    plane: Option<usize>,
+    data: Vec<u8>,
+    recycle: Arc<Mutex<Producer<Vec<u8>>>>,
+}
+
+impl SampleChunk {
+    /// The negotiated format of the samples.
+    pub fn info(&self) -> &AudioInfoRaw {
+        &self.info
+    }
+
+    /// The channel the samples belong to if the format is planar, see [`Frames::plane`].
+    pub fn plane(&self) -> Option<usize> {
+        self.plane
+    }
+
+    /// The raw sample bytes.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl fmt::Debug for SampleChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SampleChunk")
+            .field("info", &self.info)
+            .field("plane", &self.plane)
+            .field("len", &self.data.len())
+            .finish()
+    }
+}
+
+impl Drop for SampleChunk {
+    fn drop(&mut self) {
+        // Chunks the realtime thread could not queue have their buffer taken back already.
+        if self.data.capacity() == 0 {
+            return;
+        }
+        // The queue has room for every buffer of the pool, so this never fails.
+        let _ = self.recycle.lock().unwrap().push(mem::take(&mut self.data));
+    }
+}
+
+/// The frames captured by a stream as an async stream of [`SampleChunk`]s.
+///
+/// Up to the capacity given when creating the stream is buffered. When the stream is not
+/// polled fast enough, or the consumer holds on to every chunk, newer chunks are dropped
+/// rather than stalling the realtime thread, see [`dropped()`](Self::dropped).
+pub struct SampleStream {
+    pub(crate) receiver: mpsc::Receiver<SampleChunk>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl SampleStream {
+    /// The number of chunks dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Stream for SampleStream {
+    type Item = SampleChunk;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// The sink feeding a [`SampleStream`].
+///
+/// [`process()`](SampleSink::process) only queues the chunks, the PipeWire thread moves them
+/// into the channel of the stream in [`forward()`](SampleSink::forward).
+pub(crate) struct ChunkSink {
+    sender: mpsc::Sender<SampleChunk>,
+    /// Chunks filled on the realtime thread, waiting for [`forward()`](SampleSink::forward).
+    queued: Producer<SampleChunk>,
+    pending: Consumer<SampleChunk>,
+    /// Buffers ready to be filled. Allocated for every buffer of the pool, so pushing never
+    /// reallocates.
+    free: Vec<Vec<u8>>,
+    /// Buffers of dropped chunks, on their way back into `free`.
+    returned: Consumer<Vec<u8>>,
+    recycle: Arc<Mutex<Producer<Vec<u8>>>>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// Create a [`SampleStream`] buffering up to `capacity` chunks, and the sink feeding it.
+///
+/// # Panics
+/// If `capacity` is 0.
+pub(crate) fn sample_stream(capacity: usize) -> Result<(ChunkSink, SampleStream), Error> {
+    let (sender, receiver) = mpsc::channel(capacity);
+    let (queued, pending) = rt_queue::queue(capacity)?;
+    let (recycle, returned) = rt_queue::queue(capacity)?;
+    let dropped = Arc::new(AtomicU64::new(0));
+    let sink = ChunkSink {
+        sender,
+        queued,
+        pending,
+        free: (0..capacity).map(|_| Vec::new()).collect(),
+        returned,
+        recycle: Arc::new(Mutex::new(recycle)),
+        dropped: Arc::clone(&dropped),
+    };
+    Ok((sink, SampleStream { receiver, dropped }))
+}
+
+impl SampleSink for ChunkSink {
+    fn format_changed(&mut self, info: &AudioInfoRaw) {
+        // Not on the realtime thread, make room for the largest block of the new format.
+        let size = MAX_QUANTUM * frame_size(info);
+        self.free.extend(self.returned.drain());
+        for buffer in &mut self.free {
+            buffer.clear();
+            buffer.reserve(size);
+        }
+    }
+
+    fn process(&mut self, frames: &Frames) {
+        let data = frames.data();
+        self.free.extend(self.returned.drain());
+        // Buffers still out with the consumer during a format change may be too small.
+        let Some(index) = self.free.iter().position(|buffer| buffer.capacity() >= data.len()) else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        let mut buffer = self.free.swap_remove(index);
+        buffer.clear();
+        buffer.extend_from_slice(data);
+
+        let chunk = SampleChunk {
+            info: *frames.info(),
+            plane: frames.plane(),
+            data: buffer,
+            recycle: Arc::clone(&self.recycle),
+        };
+        if let Err(mut chunk) = self.queued.push(chunk) {
+            self.free.push(mem::take(&mut chunk.data));
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn forward(&mut self) {
+        // `try_send` never blocks, a full or closed stream just loses the chunk. Dropping it
+        // returns the buffer to the pool.
+        for chunk in self.pending.drain() {
+            if self.sender.try_send(chunk).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
@@ -1,15 +1,21 @@
+use super::filter::Port;
 use super::stream::StreamRef;
 
-use spa::buffer::Data;
+use spa::buffer::{Data, Meta, MetaHeader, MetaType};
 use std::convert::TryFrom;
 use std::ptr::NonNull;
 
 pub struct Buffer<'s> {
     buf: NonNull<pw_sys::pw_buffer>,
 
-    /// In Pipewire, buffers are owned by the stream that generated them.
+    /// In Pipewire, buffers are owned by the stream or filter port that generated them.
     /// This reference ensures that this rule is respected.
-    stream: &'s StreamRef,
+    owner: Owner<'s>,
+}
+
+enum Owner<'s> {
+    Stream(&'s StreamRef),
+    Port(&'s Port<'s>),
 }
 
 impl Buffer<'_> {
@@ -17,7 +23,20 @@ impl Buffer<'_> {
         buf: *mut pw_sys::pw_buffer,
         stream: &StreamRef,
     ) -> Option<Buffer<'_>> {
-        NonNull::new(buf).map(|buf| Buffer { buf, stream })
+        NonNull::new(buf).map(|buf| Buffer {
+            buf,
+            owner: Owner::Stream(stream),
+        })
+    }
+
+    pub(crate) unsafe fn from_port<'s>(
+        buf: *mut pw_sys::pw_buffer,
+        port: &'s Port<'_>,
+    ) -> Option<Buffer<'s>> {
+        NonNull::new(buf).map(|buf| Buffer {
+            buf,
+            owner: Owner::Port(port),
+        })
     }
 
     pub fn datas_mut(&mut self) -> &mut [Data] {
@@ -37,6 +56,49 @@ impl Buffer<'_> {
         slice_of_data
     }
 
+    pub fn metas(&self) -> &[Meta] {
+        let buffer: *mut spa_sys::spa_buffer = unsafe { self.buf.as_ref().buffer };
+
+        if !buffer.is_null() && unsafe { (*buffer).n_metas > 0 && !(*buffer).metas.is_null() } {
+            unsafe {
+                let metas = (*buffer).metas as *const Meta;
+                std::slice::from_raw_parts(metas, usize::try_from((*buffer).n_metas).unwrap())
+            }
+        } else {
+            &[]
+        }
+    }
+
+    pub fn metas_mut(&mut self) -> &mut [Meta] {
+        let buffer: *mut spa_sys::spa_buffer = unsafe { self.buf.as_ref().buffer };
+
+        if !buffer.is_null() && unsafe { (*buffer).n_metas > 0 && !(*buffer).metas.is_null() } {
+            unsafe {
+                let metas = (*buffer).metas as *mut Meta;
+                std::slice::from_raw_parts_mut(metas, usize::try_from((*buffer).n_metas).unwrap())
+            }
+        } else {
+            &mut []
+        }
+    }
+
+    /// Find the metadata of type `type_`
+    ///
+    /// Metadata is only present when it was negotiated, see
+    /// [`MetaParam`](spa::param::meta::MetaParam).
+    pub fn find_meta(&self, type_: MetaType) -> Option<&Meta> {
+        self.metas().iter().find(|meta| meta.type_() == type_)
+    }
+
+    pub fn find_meta_mut(&mut self, type_: MetaType) -> Option<&mut Meta> {
+        self.metas_mut().iter_mut().find(|meta| meta.type_() == type_)
+    }
+
+    /// The timing and sequence information of the buffer
+    pub fn header(&self) -> Option<&MetaHeader> {
+        self.find_meta(MetaType::Header)?.header()
+    }
+
     #[cfg(feature = "v0_3_49")]
     pub fn requested(&self) -> u64 {
         unsafe { self.buf.as_ref().requested }
@@ -46,7 +108,10 @@ impl Buffer<'_> {
 impl Drop for Buffer<'_> {
     fn drop(&mut self) {
         unsafe {
-            self.stream.queue_raw_buffer(self.buf.as_ptr());
+            match self.owner {
+                Owner::Stream(stream) => stream.queue_raw_buffer(self.buf.as_ptr()),
+                Owner::Port(port) => port.queue_raw_buffer(self.buf.as_ptr()),
+            }
         }
     }
 }
@@ -0,0 +1,500 @@
+//! Convert captured audio to one fixed format.
+//!
+//! A [`Converter`] is used as the [`SampleSink`] of a stream instead of the actual sink. It
+//! decodes whatever format the stream negotiated, planar formats included, mixes the channels
+//! to the positions of a [`ConvertTarget`], resamples to its rate and hands interleaved samples
+//! of the requested [`Sample`] type to the wrapped sink.
+//!
+//! The working buffers are sized for the largest block a stream processes whenever the format
+//! changes, so no allocations happen on the realtime thread.
+
+use spa::param::audio::{AudioFormat, AudioInfoRaw, AudioInfoRawFlags, MAX_CHANNELS};
+use spa::sys;
+
+use crate::e_stream::{Frames, SampleSink, MAX_QUANTUM};
+use crate::EasyWireError;
+
+/// A sample type a [`Converter`] can produce, with full scale at `-1.0..=1.0`.
+pub trait Sample: Copy + Send + 'static {
+    fn from_f32(value: f32) -> Self;
+}
+
+impl Sample for f32 {
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+}
+
+impl Sample for f64 {
+    fn from_f32(value: f32) -> Self {
+        value as f64
+    }
+}
+
+impl Sample for i16 {
+    fn from_f32(value: f32) -> Self {
+        (value.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+    }
+}
+
+impl Sample for i32 {
+    fn from_f32(value: f32) -> Self {
+        (value.clamp(-1.0, 1.0) as f64 * i32::MAX as f64).round() as i32
+    }
+}
+
+/// The format a [`Converter`] produces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConvertTarget {
+    /// The sample rate.
+    pub rate: u32,
+    /// The position of every channel, as `SPA_AUDIO_CHANNEL_*` values.
+    pub positions: Vec<u32>,
+}
+
+impl ConvertTarget {
+    pub fn new(rate: u32, positions: &[u32]) -> Self {
+        Self {
+            rate,
+            positions: positions.to_vec(),
+        }
+    }
+
+    pub fn mono(rate: u32) -> Self {
+        Self::new(rate, &[sys::SPA_AUDIO_CHANNEL_MONO])
+    }
+
+    pub fn stereo(rate: u32) -> Self {
+        Self::new(rate, &[sys::SPA_AUDIO_CHANNEL_FL, sys::SPA_AUDIO_CHANNEL_FR])
+    }
+}
+
+/// A [`SampleSink`] converting every block to a [`ConvertTarget`] before handing it to `sink`.
+///
+/// `sink` receives interleaved samples with one sample for every target position. Blocks in a
+/// format that cannot be decoded, like the compressed `ULAW` and `ALAW`, are dropped.
+pub struct Converter<S, F> {
+    target: ConvertTarget,
+    sink: F,
+    state: Option<State>,
+    /// The decoded block, interleaved.
+    input: Vec<f32>,
+    /// The block after mixing.
+    mixed: Vec<f32>,
+    /// The block after resampling.
+    resampled: Vec<f32>,
+    output: Vec<S>,
+}
+
+/// How to convert the negotiated format.
+struct State {
+    decode: Decode,
+    sample_size: usize,
+    channels: usize,
+    /// Row `o` holds the gain of every input channel for output channel `o`, `None` if the
+    /// channels already match the target.
+    matrix: Option<Vec<f32>>,
+    resampler: Option<Resampler>,
+}
+
+impl<S: Sample, F: FnMut(&[S]) + Send + 'static> Converter<S, F> {
+    /// Fails if `target` has a rate of 0, or no or more than [`MAX_CHANNELS`] positions.
+    pub fn new(target: ConvertTarget, sink: F) -> Result<Self, EasyWireError> {
+        if target.rate == 0 {
+            return Err(EasyWireError::Format("the target rate must not be 0".into()));
+        }
+        if target.positions.is_empty() || target.positions.len() > MAX_CHANNELS {
+            return Err(EasyWireError::Format(format!(
+                "the target needs 1 to {} channels, not {}",
+                MAX_CHANNELS,
+                target.positions.len()
+            )));
+        }
+        Ok(Self {
+            target,
+            sink,
+            state: None,
+            input: Vec::new(),
+            mixed: Vec::new(),
+            resampled: Vec::new(),
+            output: Vec::new(),
+        })
+    }
+
+    /// The format the samples are converted to.
+    pub fn target(&self) -> &ConvertTarget {
+        &self.target
+    }
+}
+
+impl<S: Sample, F: FnMut(&[S]) + Send + 'static> SampleSink for Converter<S, F> {
+    fn format_changed(&mut self, info: &AudioInfoRaw) {
+        let channels = (info.channels() as usize).min(MAX_CHANNELS);
+        self.state = decoder(info.format()).filter(|_| channels > 0).map(|(sample_size, decode)| {
+            let all_positions = info.position();
+            let positions = if info.flags().contains(AudioInfoRawFlags::UNPOSITIONED) {
+                &[][..]
+            } else {
+                &all_positions[..channels]
+            };
+            let matrix = if positions == self.target.positions.as_slice() {
+                None
+            } else {
+                Some(mix_matrix(positions, channels, &self.target.positions))
+            };
+            let resampler = (info.rate() != self.target.rate && info.rate() > 0)
+                .then(|| Resampler::new(info.rate(), self.target.rate, self.target.positions.len()));
+            State {
+                decode,
+                sample_size,
+                channels,
+                matrix,
+                resampler,
+            }
+        });
+
+        // Not on the realtime thread, make room for the largest block of the new format.
+        let Some(state) = &self.state else {
+            return;
+        };
+        let n_outputs = self.target.positions.len();
+        let resampled_frames = match &state.resampler {
+            Some(_) => (MAX_QUANTUM * self.target.rate as usize).div_ceil(info.rate() as usize) + 1,
+            None => MAX_QUANTUM,
+        };
+        reserve(&mut self.input, MAX_QUANTUM * state.channels);
+        reserve(&mut self.mixed, MAX_QUANTUM * n_outputs);
+        reserve(&mut self.resampled, resampled_frames * n_outputs);
+        reserve(&mut self.output, resampled_frames * n_outputs);
+    }
+
+    fn process(&mut self, frames: &Frames) {
+        let Some(state) = &mut self.state else {
+            return;
+        };
+        let data = frames.data();
+        let n_frames = match frames.plane() {
+            Some(_) => data.len() / state.sample_size,
+            None => data.len() / (state.sample_size * state.channels),
+        };
+
+        match frames.plane() {
+            None => {
+                self.input.clear();
+                self.input.extend(
+                    data.chunks_exact(state.sample_size)
+                        .take(n_frames * state.channels)
+                        .map(state.decode),
+                );
+            }
+            Some(plane) => {
+                // Gather the planes, the block is complete with the last one.
+                if plane >= state.channels {
+                    return;
+                }
+                if plane == 0 {
+                    self.input.clear();
+                    self.input.resize(n_frames * state.channels, 0.0);
+                }
+                let samples = data.chunks_exact(state.sample_size).map(state.decode);
+                for (frame, sample) in self.input.chunks_exact_mut(state.channels).zip(samples) {
+                    frame[plane] = sample;
+                }
+                if plane + 1 < state.channels {
+                    return;
+                }
+            }
+        }
+
+        let mixed = match &state.matrix {
+            Some(matrix) => {
+                mix(&self.input, state.channels, matrix, &mut self.mixed);
+                &self.mixed
+            }
+            None => &self.input,
+        };
+        let resampled = match &mut state.resampler {
+            Some(resampler) => {
+                resampler.process(mixed, &mut self.resampled);
+                &self.resampled
+            }
+            None => mixed,
+        };
+
+        self.output.clear();
+        self.output.extend(resampled.iter().map(|sample| S::from_f32(*sample)));
+        if !self.output.is_empty() {
+            (self.sink)(&self.output);
+        }
+    }
+}
+
+/// Empty `buffer` and make room for `len` values.
+fn reserve<T>(buffer: &mut Vec<T>, len: usize) {
+    buffer.clear();
+    buffer.reserve(len);
+}
+
+type Decode = fn(&[u8]) -> f32;
+
+/// Size in bytes and decoder of a sample of `format`.
+pub(crate) fn decoder(format: AudioFormat) -> Option<(usize, Decode)> {
+    let little = cfg!(target_endian = "little");
+    let decoder: (usize, Decode) = match format {
+        AudioFormat::S8 | AudioFormat::S8P => (1, |b| b[0] as i8 as f32 / 128.0),
+        AudioFormat::U8 | AudioFormat::U8P => (1, |b| (b[0] as f32 - 128.0) / 128.0),
+        AudioFormat::S16LE => (2, |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0),
+        AudioFormat::S16BE => (2, |b| i16::from_be_bytes([b[0], b[1]]) as f32 / 32768.0),
+        AudioFormat::S16P => (2, |b| i16::from_ne_bytes([b[0], b[1]]) as f32 / 32768.0),
+        AudioFormat::U16LE => (2, |b| (u16::from_le_bytes([b[0], b[1]]) as f32 - 32768.0) / 32768.0),
+        AudioFormat::U16BE => (2, |b| (u16::from_be_bytes([b[0], b[1]]) as f32 - 32768.0) / 32768.0),
+        AudioFormat::S24LE => (3, |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608.0),
+        AudioFormat::S24BE => (3, |b| (i32::from_be_bytes([b[0], b[1], b[2], 0]) >> 8) as f32 / 8388608.0),
+        AudioFormat::S24P if little => (3, |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608.0),
+        AudioFormat::S24P => (3, |b| (i32::from_be_bytes([b[0], b[1], b[2], 0]) >> 8) as f32 / 8388608.0),
+        AudioFormat::S24_32LE => (4, |b| (i32::from_le_bytes([b[0], b[1], b[2], b[3]]) << 8 >> 8) as f32 / 8388608.0),
+        AudioFormat::S24_32BE => (4, |b| (i32::from_be_bytes([b[0], b[1], b[2], b[3]]) << 8 >> 8) as f32 / 8388608.0),
+        AudioFormat::S24_32P => (4, |b| (i32::from_ne_bytes([b[0], b[1], b[2], b[3]]) << 8 >> 8) as f32 / 8388608.0),
+        AudioFormat::S32LE => (4, |b| (i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64 / 2147483648.0) as f32),
+        AudioFormat::S32BE => (4, |b| (i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64 / 2147483648.0) as f32),
+        AudioFormat::S32P => (4, |b| (i32::from_ne_bytes([b[0], b[1], b[2], b[3]]) as f64 / 2147483648.0) as f32),
+        AudioFormat::F32LE => (4, |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        AudioFormat::F32BE => (4, |b| f32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+        AudioFormat::F32P => (4, |b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]])),
+        AudioFormat::F64LE => (8, |b| f64::from_le_bytes(b[..8].try_into().unwrap()) as f32),
+        AudioFormat::F64BE => (8, |b| f64::from_be_bytes(b[..8].try_into().unwrap()) as f32),
+        AudioFormat::F64P => (8, |b| f64::from_ne_bytes(b[..8].try_into().unwrap()) as f32),
+        _ => return None,
+    };
+    Some(decoder)
+}
+
+/// Which side of the listener a channel position is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Left,
+    Right,
+    Center,
+    Lfe,
+}
+
+fn side(position: u32) -> Option<Side> {
+    Some(match position {
+        sys::SPA_AUDIO_CHANNEL_FL
+        | sys::SPA_AUDIO_CHANNEL_SL
+        | sys::SPA_AUDIO_CHANNEL_RL
+        | sys::SPA_AUDIO_CHANNEL_FLC
+        | sys::SPA_AUDIO_CHANNEL_TFL
+        | sys::SPA_AUDIO_CHANNEL_TRL => Side::Left,
+        sys::SPA_AUDIO_CHANNEL_FR
+        | sys::SPA_AUDIO_CHANNEL_SR
+        | sys::SPA_AUDIO_CHANNEL_RR
+        | sys::SPA_AUDIO_CHANNEL_FRC
+        | sys::SPA_AUDIO_CHANNEL_TFR
+        | sys::SPA_AUDIO_CHANNEL_TRR => Side::Right,
+        sys::SPA_AUDIO_CHANNEL_MONO
+        | sys::SPA_AUDIO_CHANNEL_FC
+        | sys::SPA_AUDIO_CHANNEL_RC
+        | sys::SPA_AUDIO_CHANNEL_TC
+        | sys::SPA_AUDIO_CHANNEL_TFC
+        | sys::SPA_AUDIO_CHANNEL_TRC => Side::Center,
+        sys::SPA_AUDIO_CHANNEL_LFE | sys::SPA_AUDIO_CHANNEL_LFE2 => Side::Lfe,
+        _ => return None,
+    })
+}
+
+/// The gains mixing `n_inputs` channels at the positions `from` into the positions `to`.
+///
+/// A channel present on both sides is copied. Any other channel is spread over the outputs on
+/// its side, or over both sides for a center channel. A single output channel gets the average
+/// of all inputs. Rows are scaled down so they cannot clip. Without usable positions, channels
+/// are mapped by index instead.
+fn mix_matrix(from: &[u32], n_inputs: usize, to: &[u32]) -> Vec<f32> {
+    let n_outputs = to.len();
+    let mut matrix = vec![0.0; n_outputs * n_inputs];
+    let positioned = from.len() == n_inputs
+        && from.iter().chain(to).all(|position| side(*position).is_some());
+
+    if !positioned {
+        for o in 0..n_outputs {
+            matrix[o * n_inputs + o % n_inputs] = 1.0;
+        }
+        return matrix;
+    }
+
+    if n_outputs == 1 {
+        let inputs: Vec<usize> = (0..n_inputs).filter(|i| side(from[*i]) != Some(Side::Lfe)).collect();
+        for i in &inputs {
+            matrix[*i] = 1.0 / inputs.len() as f32;
+        }
+        return matrix;
+    }
+
+    for (i, position) in from.iter().enumerate() {
+        if let Some(o) = to.iter().position(|to| to == position) {
+            matrix[o * n_inputs + i] = 1.0;
+            continue;
+        }
+        let side = side(*position).unwrap();
+        let targets: Vec<usize> = match side {
+            Side::Lfe => Vec::new(),
+            Side::Center => {
+                let center: Vec<usize> = (0..n_outputs).filter(|o| side_of(to, *o) == Side::Center).collect();
+                if center.is_empty() {
+                    (0..n_outputs).filter(|o| matches!(side_of(to, *o), Side::Left | Side::Right)).collect()
+                } else {
+                    center
+                }
+            }
+            side => (0..n_outputs).filter(|o| side_of(to, *o) == side).collect(),
+        };
+        let gain = if side == Side::Center && targets.len() > 1 {
+            std::f32::consts::FRAC_1_SQRT_2
+        } else {
+            1.0
+        };
+        for o in targets {
+            matrix[o * n_inputs + i] = gain;
+        }
+    }
+
+    for row in matrix.chunks_exact_mut(n_inputs) {
+        let sum: f32 = row.iter().sum();
+        if sum > 1.0 {
+            row.iter_mut().for_each(|gain| *gain /= sum);
+        }
+    }
+    matrix
+}
+
+fn side_of(positions: &[u32], index: usize) -> Side {
+    side(positions[index]).unwrap()
+}
+
+/// Mix the interleaved `input` of `n_inputs` channels through `matrix` into `output`.
+fn mix(input: &[f32], n_inputs: usize, matrix: &[f32], output: &mut Vec<f32>) {
+    output.clear();
+    for frame in input.chunks_exact(n_inputs) {
+        for row in matrix.chunks_exact(n_inputs) {
+            output.push(row.iter().zip(frame).map(|(gain, sample)| gain * sample).sum());
+        }
+    }
+}
+
+/// A linear interpolating resampler, keeping its position across blocks.
+struct Resampler {
+    /// Input frames per output frame.
+    step: f64,
+    channels: usize,
+    /// Position of the next output frame, where 0 is the last frame of the previous block
+    /// and 1 the first frame of the current one.
+    position: f64,
+    /// The last frame of the previous block, allocated up front.
+    last: Vec<f32>,
+    /// Whether `last` holds a frame yet.
+    started: bool,
+}
+
+impl Resampler {
+    fn new(from: u32, to: u32, channels: usize) -> Self {
+        Self {
+            step: from as f64 / to as f64,
+            channels,
+            position: 1.0,
+            last: vec![0.0; channels],
+            started: false,
+        }
+    }
+
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        output.clear();
+        let n_frames = input.len() / self.channels;
+        if n_frames == 0 {
+            return;
+        }
+        if !self.started {
+            self.last.copy_from_slice(&input[..self.channels]);
+            self.started = true;
+        }
+
+        let frame = |index: usize| match index {
+            0 => &self.last[..],
+            index => &input[(index - 1) * self.channels..index * self.channels],
+        };
+        while self.position < n_frames as f64 {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            let (a, b) = (frame(index), frame(index + 1));
+            output.extend(a.iter().zip(b).map(|(a, b)| a + (b - a) * fraction));
+            self.position += self.step;
+        }
+
+        self.position -= n_frames as f64;
+        self.last.copy_from_slice(&input[(n_frames - 1) * self.channels..n_frames * self.channels]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_samples() {
+        let (size, decode) = decoder(AudioFormat::S16LE).unwrap();
+        assert_eq!(size, 2);
+        assert_eq!(decode(&i16::MIN.to_le_bytes()), -1.0);
+        let (_, decode) = decoder(AudioFormat::S24_32LE).unwrap();
+        assert_eq!(decode(&(-4194304i32).to_le_bytes()), -0.5);
+        let (_, decode) = decoder(AudioFormat::F64BE).unwrap();
+        assert_eq!(decode(&0.25f64.to_be_bytes()), 0.25);
+        assert!(decoder(AudioFormat::ULAW).is_none());
+
+        assert_eq!(i16::from_f32(2.0), i16::MAX);
+        assert_eq!(i16::from_f32(-0.5), -16384);
+    }
+
+    #[test]
+    fn mix_by_position() {
+        let stereo = [sys::SPA_AUDIO_CHANNEL_FL, sys::SPA_AUDIO_CHANNEL_FR];
+        let mono = [sys::SPA_AUDIO_CHANNEL_MONO];
+        assert_eq!(mix_matrix(&stereo, 2, &mono), [0.5, 0.5]);
+
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert_eq!(mix_matrix(&mono, 1, &stereo), [half, half]);
+
+        let surround = [
+            sys::SPA_AUDIO_CHANNEL_FL,
+            sys::SPA_AUDIO_CHANNEL_FR,
+            sys::SPA_AUDIO_CHANNEL_FC,
+            sys::SPA_AUDIO_CHANNEL_LFE,
+        ];
+        let matrix = mix_matrix(&surround, 4, &stereo);
+        let expected = [1.0, 0.0, half, 0.0].map(|gain| gain / (1.0 + half));
+        assert!(matrix[..4].iter().zip(expected).all(|(gain, expected)| (gain - expected).abs() < 1e-6));
+
+        // Unknown positions map by index.
+        assert_eq!(mix_matrix(&[], 2, &mono), [1.0, 0.0]);
+    }
+
+    #[test]
+    fn reject_empty_target() {
+        let sink = |_: &[f32]| {};
+        assert!(Converter::new(ConvertTarget::mono(0), sink).is_err());
+        assert!(Converter::new(ConvertTarget::new(48000, &[]), sink).is_err());
+        assert!(Converter::new(ConvertTarget::stereo(48000), sink).is_ok());
+    }
+
+    #[test]
+    fn resample() {
+        let mut resampler = Resampler::new(1, 2, 1);
+        let mut output = Vec::new();
+        resampler.process(&[0.0, 1.0], &mut output);
+        assert_eq!(output, [0.0, 0.5]);
+        resampler.process(&[2.0], &mut output);
+        assert_eq!(output, [1.0, 1.5]);
+
+        let mut resampler = Resampler::new(2, 1, 2);
+        resampler.process(&[0.0, 0.0, 1.0, -1.0, 2.0, -2.0, 3.0, -3.0], &mut output);
+        assert_eq!(output, [0.0, 0.0, 2.0, -2.0]);
+    }
+}
@@ -0,0 +1,331 @@
+//! Typed access to the `default` metadata.
+//!
+//! The session manager keeps the default devices and the targets of streams as properties of
+//! the metadata object named `default`:
+//!
+//! - `default.audio.sink`, `default.audio.source` and `default.video.source` on subject 0 name
+//!   the nodes in use right now, as JSON like `{ "name": "alsa_output.pci-0000_00_1f.3" }`.
+//! - `default.configured.*` on subject 0 name the nodes the user picked. The session manager
+//!   falls back to another node while the configured one is missing, and writing these is how
+//!   the defaults are changed.
+//! - `target.object`, a node name or serial, and the older `target.node`, a node id, on the
+//!   node of a stream pick the node the stream is linked to.
+//!
+//! A [`DefaultMetadata`] binds the object, follows these properties and reports their changes
+//! as [`DefaultChange`]s.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use spa::utils::dict::DictRef;
+use spa::utils::json;
+
+use crate::e_stream::{TARGET_NODE, TARGET_OBJECT};
+use crate::metadata::{Metadata, MetadataListener};
+use crate::registry::{GlobalObject, Registry};
+use crate::types::ObjectType;
+use crate::Error;
+
+/// The `metadata.name` of the default metadata.
+pub const NAME: &str = "default";
+
+/// Type of the JSON values of the default keys.
+const JSON_TYPE: &str = "Spa:String:JSON";
+/// Type of the stream targets.
+const ID_TYPE: &str = "Spa:Id";
+
+/// A default device property on subject 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DefaultKey {
+    AudioSink,
+    AudioSource,
+    VideoSource,
+    ConfiguredAudioSink,
+    ConfiguredAudioSource,
+    ConfiguredVideoSource,
+}
+
+const KEYS: [(DefaultKey, &str); 6] = [
+    (DefaultKey::AudioSink, "default.audio.sink"),
+    (DefaultKey::AudioSource, "default.audio.source"),
+    (DefaultKey::VideoSource, "default.video.source"),
+    (DefaultKey::ConfiguredAudioSink, "default.configured.audio.sink"),
+    (DefaultKey::ConfiguredAudioSource, "default.configured.audio.source"),
+    (DefaultKey::ConfiguredVideoSource, "default.configured.video.source"),
+];
+
+impl DefaultKey {
+    /// The metadata key.
+    pub fn as_str(&self) -> &'static str {
+        KEYS.iter().find(|(key, _)| key == self).map(|(_, name)| *name).unwrap()
+    }
+
+    /// The default key named `key`, `None` for any other key.
+    pub fn from_key(key: &str) -> Option<Self> {
+        KEYS.iter().find(|(_, name)| *name == key).map(|(key, _)| *key)
+    }
+}
+
+/// The node a stream is linked to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamTarget {
+    /// A node by `node.name` or `object.serial`, set as `target.object`.
+    Object(String),
+    /// A node by id, set as `target.node`.
+    Node(u32),
+}
+
+/// A change of the default metadata, see [`DefaultMetadata::bind`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DefaultChange {
+    /// The default `key` is now the node named `name`, `None` if it was removed.
+    Default { key: DefaultKey, name: Option<String> },
+    /// The target of the stream whose node is `subject` changed.
+    Target {
+        subject: u32,
+        target: Option<StreamTarget>,
+    },
+    /// Every property was removed.
+    Cleared,
+}
+
+/// The targets set on a stream's node.
+#[derive(Debug, Default)]
+struct Targets {
+    object: Option<String>,
+    node: Option<u32>,
+}
+
+impl Targets {
+    /// The target the session manager goes by, `target.object` wins.
+    fn target(&self) -> Option<StreamTarget> {
+        match (&self.object, self.node) {
+            (Some(object), _) => Some(StreamTarget::Object(object.clone())),
+            (None, Some(node)) => Some(StreamTarget::Node(node)),
+            (None, None) => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    defaults: HashMap<DefaultKey, String>,
+    targets: HashMap<u32, Targets>,
+}
+
+impl State {
+    /// Apply a property event, returning what changed.
+    fn update(&mut self, subject: u32, key: Option<&str>, value: Option<&str>) -> Option<DefaultChange> {
+        let Some(key) = key else {
+            // All properties of `subject` are gone, or all of them for any subject.
+            if subject == crate::constants::ID_ANY {
+                self.defaults.clear();
+                self.targets.clear();
+                return Some(DefaultChange::Cleared);
+            }
+            if subject == 0 && !self.defaults.is_empty() {
+                self.defaults.clear();
+                return Some(DefaultChange::Cleared);
+            }
+            let targets = self.targets.remove(&subject)?;
+            return targets.target().map(|_| DefaultChange::Target {
+                subject,
+                target: None,
+            });
+        };
+
+        if subject == 0 {
+            let key = DefaultKey::from_key(key)?;
+            let name = value.and_then(parse_name);
+            match &name {
+                Some(name) => self.defaults.insert(key, name.clone()),
+                None => self.defaults.remove(&key),
+            };
+            return Some(DefaultChange::Default { key, name });
+        }
+
+        let targets = self.targets.entry(subject).or_default();
+        let before = targets.target();
+        if key == TARGET_OBJECT {
+            targets.object = value.map(str::to_string);
+        } else if key == TARGET_NODE {
+            targets.node = value.and_then(|value| value.parse().ok());
+        } else {
+            return None;
+        }
+        let target = targets.target();
+        if target.is_none() {
+            self.targets.remove(&subject);
+        }
+        (target != before).then_some(DefaultChange::Target { subject, target })
+    }
+}
+
+/// The bound `default` metadata, see the [module documentation](self).
+pub struct DefaultMetadata {
+    // Declared first so the listener is removed before the proxy is destroyed.
+    _listener: MetadataListener,
+    metadata: Metadata,
+    state: Rc<RefCell<State>>,
+}
+
+impl DefaultMetadata {
+    /// Whether the registry global `global` is the `default` metadata.
+    pub fn is_default(global: &GlobalObject<&DictRef>) -> bool {
+        global.type_ == ObjectType::Metadata
+            && global.props.and_then(|props| props.get("metadata.name")) == Some(NAME)
+    }
+
+    /// Bind the `default` metadata announced as `global`, see [`is_default()`](Self::is_default).
+    ///
+    /// `changed` is called for every change of a default or a stream target, starting with the
+    /// values the metadata already holds.
+    pub fn bind<F>(registry: &Registry, global: &GlobalObject<&DictRef>, changed: F) -> Result<Self, Error>
+    where
+        F: Fn(&DefaultChange) + 'static,
+    {
+        let metadata: Metadata = registry.bind(global)?;
+        let state = Rc::new(RefCell::new(State::default()));
+        let listener = metadata
+            .add_listener_local()
+            .property({
+                let state = Rc::clone(&state);
+                move |subject, key, _type, value| {
+                    let change = state.borrow_mut().update(subject, key, value);
+                    if let Some(change) = change {
+                        changed(&change);
+                    }
+                    0
+                }
+            })
+            .register();
+
+        Ok(Self {
+            _listener: listener,
+            metadata,
+            state,
+        })
+    }
+
+    /// The name of the node that is the default `key`.
+    pub fn get(&self, key: DefaultKey) -> Option<String> {
+        self.state.borrow().defaults.get(&key).cloned()
+    }
+
+    /// The name of the default sink.
+    pub fn audio_sink(&self) -> Option<String> {
+        self.get(DefaultKey::AudioSink)
+    }
+
+    /// The name of the default source.
+    pub fn audio_source(&self) -> Option<String> {
+        self.get(DefaultKey::AudioSource)
+    }
+
+    /// The name of the sink the user picked as default.
+    pub fn configured_audio_sink(&self) -> Option<String> {
+        self.get(DefaultKey::ConfiguredAudioSink)
+    }
+
+    /// The name of the source the user picked as default.
+    pub fn configured_audio_source(&self) -> Option<String> {
+        self.get(DefaultKey::ConfiguredAudioSource)
+    }
+
+    /// Make the node named `name` the default `key`, or remove the default with `None`.
+    ///
+    /// To change a default device set the configured key, the session manager updates the
+    /// other one.
+    pub fn set(&self, key: DefaultKey, name: Option<&str>) {
+        let value = name.map(json_name);
+        self.metadata.set_property(0, key.as_str(), Some(JSON_TYPE), value.as_deref());
+    }
+
+    /// Make the node named `name` the default sink.
+    pub fn set_audio_sink(&self, name: &str) {
+        self.set(DefaultKey::ConfiguredAudioSink, Some(name));
+    }
+
+    /// Make the node named `name` the default source.
+    pub fn set_audio_source(&self, name: &str) {
+        self.set(DefaultKey::ConfiguredAudioSource, Some(name));
+    }
+
+    /// The target of the stream whose node is `subject`.
+    pub fn target(&self, subject: u32) -> Option<StreamTarget> {
+        self.state.borrow().targets.get(&subject).and_then(Targets::target)
+    }
+
+    /// Link the stream whose node is `subject` to `target`, or let the session manager pick
+    /// a node with `None`.
+    ///
+    /// The other target key is removed, so that a stale value does not win.
+    pub fn set_target(&self, subject: u32, target: Option<&StreamTarget>) {
+        let (object, node) = match target {
+            Some(StreamTarget::Object(object)) => (Some(object.clone()), None),
+            Some(StreamTarget::Node(node)) => (None, Some(node.to_string())),
+            None => (None, None),
+        };
+        let type_ = |value: &Option<String>| value.as_ref().map(|_| ID_TYPE);
+        self.metadata.set_property(subject, TARGET_OBJECT, type_(&object), object.as_deref());
+        self.metadata.set_property(subject, TARGET_NODE, type_(&node), node.as_deref());
+    }
+
+    /// The bound metadata proxy.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+/// The `name` of a default value like `{ "name": "alsa_output.pci-0000_00_1f.3" }`.
+fn parse_name(value: &str) -> Option<String> {
+    json::parse(value).ok()?.get_as("name").ok()
+}
+
+/// The default value naming the node `name`.
+fn json_name(name: &str) -> String {
+    json::Value::object([("name", json::Value::from(name))]).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_json() {
+        let name = "alsa_output.\"pci\"\\\n";
+        assert_eq!(parse_name(&json_name(name)).as_deref(), Some(name));
+        assert_eq!(parse_name(r#"{"level": "1", "name": "sink"}"#).as_deref(), Some("sink"));
+        assert_eq!(parse_name("{ \"level\": 1 }"), None);
+        assert_eq!(parse_name("sink"), None);
+    }
+
+    #[test]
+    fn tracks_changes() {
+        let mut state = State::default();
+        let change = state.update(0, Some("default.audio.sink"), Some(r#"{ "name": "sink" }"#));
+        assert_eq!(change, Some(DefaultChange::Default {
+            key: DefaultKey::AudioSink,
+            name: Some("sink".to_string()),
+        }));
+        assert_eq!(state.update(0, Some("other.key"), Some("1")), None);
+
+        // `target.object` wins over `target.node`.
+        let change = state.update(42, Some(TARGET_NODE), Some("7"));
+        assert_eq!(change, Some(DefaultChange::Target {
+            subject: 42,
+            target: Some(StreamTarget::Node(7)),
+        }));
+        state.update(42, Some(TARGET_OBJECT), Some("120"));
+        assert_eq!(state.targets[&42].target(), Some(StreamTarget::Object("120".to_string())));
+        assert_eq!(state.update(42, Some(TARGET_NODE), None), None);
+
+        assert_eq!(state.update(42, None, None), Some(DefaultChange::Target {
+            subject: 42,
+            target: None,
+        }));
+        assert_eq!(state.update(crate::constants::ID_ANY, None, None), Some(DefaultChange::Cleared));
+        assert!(state.defaults.is_empty());
+    }
+}
@@ -1,79 +1,655 @@
-use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, mpsc, Mutex};
-use spa::param::format::{MediaSubtype, MediaType};
+use spa::param::audio::{AudioFormat, AudioInfoRaw};
+use spa::param::format::{FormatProperties, MediaSubtype, MediaType};
 use spa::param::format_utils::parse_format;
-use spa::pod::Pod;
+use spa::param::ParamType;
+use spa::pod::{CanonicalFixedSizedPod, ChoiceValue, Object, Pod, Property, Value, ValueArray};
+use spa::utils::{Choice, ChoiceEnum, ChoiceFlags, Id, SpaTypes};
+use crate::buffer::Buffer;
 use crate::core::Core;
 use crate::properties::properties;
 use crate::{keys};
-use crate::pipe_wire::IncomingEvent;
-use crate::stream::{Stream, StreamListener, StreamRef};
+use crate::pipe_wire::{report, PWEvent};
+use crate::default_metadata::{DefaultMetadata, StreamTarget};
+use crate::stream::{Stream, StreamListener, StreamState};
+use crate::meter::{Meter, MeterConfig, MeterEvent};
+use crate::rt_queue::{self, Consumer, Producer};
+use crate::EasyWireError;
 
-pub enum StreamEvent {
-    UpdateObjID(u32),
+/// Metadata key the session manager uses to pick the node a stream links to, by name or serial.
+pub const TARGET_OBJECT: &str = "target.object";
+/// Older metadata key the session manager uses to pick the node a stream links to, by id.
+pub const TARGET_NODE: &str = "target.node";
+
+/// How many meter events wait for the PipeWire thread before newer ones are dropped.
+const METER_EVENTS: usize = 64;
+
+/// A block of frames captured by a stream.
+///
+/// The samples are in the format described by [`info()`](Self::info), which is the format
+/// negotiated with the graph. Interleaved formats hand over all channels in one block, planar
+/// formats hand over one block for every channel, see [`plane()`](Self::plane).
+pub struct Frames<'a> {
+    info: &'a AudioInfoRaw,
+    data: &'a [u8],
+    plane: Option<usize>,
+}
+
+impl<'a> Frames<'a> {
+    /// The negotiated format of the samples.
+    pub fn info(&self) -> &AudioInfoRaw {
+        self.info
+    }
+
+    /// The raw sample bytes.
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    /// The channel the samples belong to if the format is planar, `None` if it is interleaved.
+    pub fn plane(&self) -> Option<usize> {
+        self.plane
+    }
+
+    /// The number of frames in this block, one sample for every channel of the block.
+    pub fn n_frames(&self) -> usize {
+        n_frames(self.info, self.data.len())
+    }
+
+    /// The samples as `f32`, if the negotiated format is native endian 32 bit float.
+    pub fn as_f32(&self) -> Option<&[f32]> {
+        if self.info.format() != AudioFormat::F32LE || cfg!(target_endian = "big") {
+            return None;
+        }
+        // SAFETY: every bit pattern is a valid f32, and `align_to` only returns the aligned middle.
+        let (head, samples, tail) = unsafe { self.data.align_to::<f32>() };
+        if head.is_empty() && tail.is_empty() {
+            Some(samples)
+        } else {
+            None
+        }
+    }
+}
+
+/// A block of frames to be filled for a playback stream.
+///
+/// The samples have to be written in the format described by [`info()`](Self::info), which
+/// is the format negotiated with the graph. Like for [`Frames`], planar formats are filled one
+/// channel at a time.
+pub struct FramesMut<'a> {
+    info: &'a AudioInfoRaw,
+    data: &'a mut [u8],
+    plane: Option<usize>,
+}
+
+impl<'a> FramesMut<'a> {
+    pub(crate) fn new(info: &'a AudioInfoRaw, data: &'a mut [u8], plane: Option<usize>) -> Self {
+        Self { info, data, plane }
+    }
+
+    /// The negotiated format of the samples.
+    pub fn info(&self) -> &AudioInfoRaw {
+        self.info
+    }
+
+    /// The raw sample bytes to fill.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        self.data
+    }
+
+    /// The channel to fill if the format is planar, `None` if it is interleaved.
+    pub fn plane(&self) -> Option<usize> {
+        self.plane
+    }
+
+    /// The size in bytes of one frame, one sample for every channel of the block.
+    pub fn frame_size(&self) -> usize {
+        frame_size(self.info)
+    }
+
+    /// The number of frames that fit into this block.
+    pub fn n_frames(&self) -> usize {
+        n_frames(self.info, self.data.len())
+    }
+
+    /// The samples as `f32`, if the negotiated format is native endian 32 bit float.
+    pub fn as_f32_mut(&mut self) -> Option<&mut [f32]> {
+        if self.info.format() != AudioFormat::F32LE || cfg!(target_endian = "big") {
+            return None;
+        }
+        // SAFETY: every bit pattern is a valid f32, and `align_to_mut` only returns the aligned middle.
+        let (head, samples, tail) = unsafe { self.data.align_to_mut::<f32>() };
+        if head.is_empty() && tail.is_empty() {
+            Some(samples)
+        } else {
+            None
+        }
+    }
+}
+
+/// The largest block a stream processes at once, in frames, the default
+/// `default.clock.max-quantum` of PipeWire.
+pub(crate) const MAX_QUANTUM: usize = 8192;
+
+/// Size in bytes of one frame of `info` in a single data block.
+///
+/// A block of a planar format only holds the samples of one channel.
+pub(crate) fn frame_size(info: &AudioInfoRaw) -> usize {
+    if info.format().is_planar() {
+        sample_size(info.format())
+    } else {
+        sample_size(info.format()) * info.channels() as usize
+    }
+}
+
+/// The number of data blocks of a buffer in the format `info` that hold samples.
+fn n_planes(info: &AudioInfoRaw, n_datas: usize) -> usize {
+    if info.format().is_planar() {
+        n_datas.min(info.channels() as usize)
+    } else {
+        n_datas.min(1)
+    }
+}
+
+/// The number of whole frames of `info` in `len` bytes.
+fn n_frames(info: &AudioInfoRaw, len: usize) -> usize {
+    match frame_size(info) {
+        0 => 0,
+        frame_size => len / frame_size,
+    }
+}
+
+/// Size in bytes of a single sample of `format`, or 0 if the format is not a raw PCM format.
+fn sample_size(format: AudioFormat) -> usize {
+    match format {
+        AudioFormat::S8
+        | AudioFormat::U8
+        | AudioFormat::ULAW
+        | AudioFormat::ALAW
+        | AudioFormat::S8P
+        | AudioFormat::U8P => 1,
+        AudioFormat::S16LE
+        | AudioFormat::S16BE
+        | AudioFormat::U16LE
+        | AudioFormat::U16BE
+        | AudioFormat::S16P => 2,
+        AudioFormat::S24LE
+        | AudioFormat::S24BE
+        | AudioFormat::U24LE
+        | AudioFormat::U24BE
+        | AudioFormat::S24P => 3,
+        AudioFormat::S24_32LE
+        | AudioFormat::S24_32BE
+        | AudioFormat::U24_32LE
+        | AudioFormat::U24_32BE
+        | AudioFormat::S32LE
+        | AudioFormat::S32BE
+        | AudioFormat::U32LE
+        | AudioFormat::U32BE
+        | AudioFormat::F32LE
+        | AudioFormat::F32BE
+        | AudioFormat::S24_32P
+        | AudioFormat::S32P
+        | AudioFormat::F32P => 4,
+        AudioFormat::F64LE | AudioFormat::F64BE | AudioFormat::F64P => 8,
+        _ => 0,
+    }
+}
+
+/// Receives the audio captured by an [`EStream`].
+///
+/// [`process()`](Self::process) is called from the realtime thread of the stream, so it should
+/// not block or allocate. Any `FnMut(&Frames)` closure can be used as a sink.
+pub trait SampleSink: Send + 'static {
+    /// Called when the stream negotiated a new format, before any frames in that format.
+    fn format_changed(&mut self, _info: &AudioInfoRaw) {}
+
+    /// Called with every block of frames captured by the stream.
+    fn process(&mut self, frames: &Frames);
+
+    /// Called regularly on the PipeWire thread, to hand on what [`process()`](Self::process)
+    /// queued without blocking the realtime thread.
+    fn forward(&mut self) {}
+}
+
+impl<F> SampleSink for F
+where
+    F: FnMut(&Frames) + Send + 'static,
+{
+    fn process(&mut self, frames: &Frames) {
+        self(frames)
+    }
+}
+
+/// Provides the audio played by a playback [`EStream`].
+///
+/// [`fill()`](Self::fill) is called from the realtime thread of the stream, so it should not
+/// block or allocate. Any `FnMut(&mut FramesMut) -> usize` closure can be used as a source.
+pub trait SampleSource: Send + 'static {
+    /// Called when the stream negotiated a new format, before any frames in that format.
+    fn format_changed(&mut self, _info: &AudioInfoRaw) {}
+
+    /// Write up to [`n_frames()`](FramesMut::n_frames) frames to the start of `frames` and
+    /// return how many were written. The rest of the block is played as silence.
+    fn fill(&mut self, frames: &mut FramesMut) -> usize;
+}
+
+impl<F> SampleSource for F
+where
+    F: FnMut(&mut FramesMut) -> usize + Send + 'static,
+{
+    fn fill(&mut self, frames: &mut FramesMut) -> usize {
+        self(frames)
+    }
+}
+
+/// A source shared by every stream created for it, see [`SharedSink`].
+pub type SharedSource = Arc<Mutex<Box<dyn SampleSource>>>;
+
+/// What a stream does with its audio.
+#[derive(Clone)]
+pub enum StreamIo {
+    /// Capture audio from the graph and hand it to the sink, if any.
+    Capture(Option<SharedSink>),
+    /// Play the audio provided by the source into the graph.
+    Playback(SharedSource),
+}
+
+impl StreamIo {
+    pub fn direction(&self) -> spa::utils::Direction {
+        match self {
+            StreamIo::Capture(_) => spa::utils::Direction::Input,
+            StreamIo::Playback(_) => spa::utils::Direction::Output,
+        }
+    }
+}
+
+/// A sink shared by every stream created for it.
+///
+/// The stream is recreated after a reconnect, the sink and its state carry over.
+pub type SharedSink = Arc<Mutex<Box<dyn SampleSink>>>;
+
+pub struct Userdata {
+    pub(crate) format: AudioInfoRaw,
+    pub(crate) io: StreamIo,
+    /// Locked like the sink, `param_changed` updates it on the PipeWire thread while
+    /// `process` may be measuring on the realtime thread.
+    pub(crate) meter: Option<Mutex<StreamMeter>>,
+    /// Cycles without a buffer to process, shared with the [`EStream`].
+    pub(crate) underruns: Arc<AtomicU64>,
+}
+
+/// The meter of a stream, and the queue its events leave the realtime thread through.
+pub(crate) struct StreamMeter {
+    meter: Meter,
+    events: Producer<MeterEvent>,
+}
+
+/// Identifies a stream hosted by the [`PipeWireManager`](crate::pipe_wire_manager::PipeWireManager).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId(pub(crate) u32);
+
+impl StreamId {
+    /// The capture stream created by
+    /// [`PipeWireManager::setup_main`](crate::pipe_wire_manager::PipeWireManager::setup_main).
+    pub const MAIN: StreamId = StreamId(0);
+
+    pub fn as_raw(&self) -> u32 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for StreamId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Describes a stream to create.
+///
+/// The format fields are offered to the graph when the stream connects. Each of them is either
+/// a fixed value or a range or list to pick from, as a [`ChoiceValue`]. The format the stream
+/// ends up with is reported as
+/// [`ManagerEvent::FormatNegotiated`](crate::pipe_wire_manager::ManagerEvent::FormatNegotiated).
+#[derive(Debug, Clone)]
+pub struct EStreamConfig {
+    /// The name of the stream, also used as its `node.name`.
+    pub name: String,
+    /// The node to connect to, `None` lets the session manager pick one.
+    ///
+    /// The id is only valid on the current connection. After a reconnect the stream connects
+    /// to the node with the same `node.name`, or `object.serial` without a name.
+    pub target: Option<u32>,
+    /// The sample formats to accept, an `Id` choice of [`AudioFormat`]s.
+    pub format: ChoiceValue,
+    /// The sample rates to accept, an `Int` choice. `None` accepts any rate.
+    pub rate: Option<ChoiceValue>,
+    /// The channel counts to accept, an `Int` choice. `None` accepts any count.
+    pub channels: Option<ChoiceValue>,
+    /// The position of every channel, as `SPA_AUDIO_CHANNEL_*` values. `None` accepts any
+    /// position map.
+    pub positions: Option<Vec<u32>>,
+    /// Measure the levels of the stream and report them as
+    /// [`ManagerEvent::Meter`](crate::pipe_wire_manager::ManagerEvent::Meter).
+    pub meter: Option<MeterConfig>,
+}
+
+impl EStreamConfig {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Only accept the sample format `format`.
+    pub fn set_format(&mut self, format: AudioFormat) {
+        self.format = ChoiceValue::Id(fixed(Id(format.as_raw())));
+    }
+
+    /// Accept any of `formats`, preferring the first one.
+    ///
+    /// # Panics
+    /// If `formats` is empty.
+    pub fn set_formats(&mut self, formats: &[AudioFormat]) {
+        let default = formats.first().expect("no sample format given");
+        // SPA skips the default when intersecting an Enum choice, so it has to be among the
+        // alternatives too.
+        self.format = ChoiceValue::Id(Choice(ChoiceFlags::empty(), ChoiceEnum::Enum {
+            default: Id(default.as_raw()),
+            alternatives: formats.iter().map(|format| Id(format.as_raw())).collect(),
+        }));
+    }
+
+    /// Only accept the sample rate `rate`.
+    pub fn set_rate(&mut self, rate: u32) {
+        self.rate = Some(ChoiceValue::Int(fixed(rate as i32)));
+    }
+
+    /// Accept any sample rate from `min` to `max`, preferring `default`.
+    pub fn set_rate_range(&mut self, default: u32, min: u32, max: u32) {
+        self.rate = Some(ChoiceValue::Int(Choice(ChoiceFlags::empty(), ChoiceEnum::Range {
+            default: default as i32,
+            min: min as i32,
+            max: max as i32,
+        })));
+    }
+
+    /// Only accept `channels` channels.
+    pub fn set_channels(&mut self, channels: u32) {
+        self.channels = Some(ChoiceValue::Int(fixed(channels as i32)));
+    }
+
+    /// Request the channel positions `positions`, and as many channels.
+    pub fn set_positions(&mut self, positions: &[u32]) {
+        self.set_channels(positions.len() as u32);
+        self.positions = Some(positions.to_vec());
+    }
+
+    /// Build the `EnumFormat` param offered to the graph.
+    fn format_param(&self) -> Result<Object, EasyWireError> {
+        let mut properties = vec![
+            Property::new(FormatProperties::MediaType.as_raw(), Value::Id(Id(MediaType::Audio.as_raw()))),
+            Property::new(FormatProperties::MediaSubtype.as_raw(), Value::Id(Id(MediaSubtype::Raw.as_raw()))),
+        ];
+
+        if !matches!(self.format, ChoiceValue::Id(_)) {
+            return Err(EasyWireError::Format("the sample format must be an Id choice".to_string()));
+        }
+        properties.push(Property::new(FormatProperties::AudioFormat.as_raw(), choice_value(&self.format)));
+
+        for (key, value, name) in [
+            (FormatProperties::AudioRate, &self.rate, "rate"),
+            (FormatProperties::AudioChannels, &self.channels, "channel count"),
+        ] {
+            match value {
+                Some(value @ ChoiceValue::Int(_)) => properties.push(Property::new(key.as_raw(), choice_value(value))),
+                Some(_) => return Err(EasyWireError::Format(format!("the {} must be an Int choice", name))),
+                None => {}
+            }
+        }
+
+        if let Some(positions) = &self.positions {
+            if positions.is_empty() || positions.len() > spa::param::audio::MAX_CHANNELS {
+                return Err(EasyWireError::Format(format!("invalid number of channel positions: {}", positions.len())));
+            }
+            if let Some(ChoiceValue::Int(Choice(_, ChoiceEnum::None(channels)))) = &self.channels {
+                if *channels as usize != positions.len() {
+                    return Err(EasyWireError::Format(format!(
+                        "{} channel positions for {} channels", positions.len(), channels
+                    )));
+                }
+            }
+            properties.push(Property::new(
+                FormatProperties::AudioPosition.as_raw(),
+                Value::ValueArray(ValueArray::Id(positions.iter().copied().map(Id).collect())),
+            ));
+        }
+
+        Ok(Object {
+            type_: SpaTypes::ObjectParamFormat.as_raw(),
+            id: ParamType::EnumFormat.as_raw(),
+            properties,
+        })
+    }
+}
+
+impl Default for EStreamConfig {
+    fn default() -> Self {
+        Self {
+            name: "capture-audio".to_string(),
+            target: None,
+            format: ChoiceValue::Id(fixed(Id(AudioFormat::F32LE.as_raw()))),
+            rate: None,
+            channels: None,
+            positions: None,
+            meter: None,
+        }
+    }
 }
 
-pub(crate) struct Userdata {
-    pub(crate) format: spa::param::audio::AudioInfoRaw,
-    pub(crate) cursor_move: bool,
+/// A choice without alternatives.
+fn fixed<T: CanonicalFixedSizedPod>(value: T) -> Choice<T> {
+    Choice(ChoiceFlags::empty(), ChoiceEnum::None(value))
 }
 
-pub struct StreamCoreData<T> {
-    pub(crate) receiver: mpsc::Receiver<StreamEvent>,
-    pub(crate) data: T,
+/// The pod value of `choice`, a plain value if there is nothing to choose from.
+fn choice_value(choice: &ChoiceValue) -> Value {
+    match choice {
+        ChoiceValue::Int(Choice(_, ChoiceEnum::None(value))) => Value::Int(*value),
+        ChoiceValue::Id(Choice(_, ChoiceEnum::None(value))) => Value::Id(*value),
+        choice => Value::Choice(choice.clone()),
+    }
+}
+
+pub struct StreamCoreData {
+    pub(crate) config: EStreamConfig,
+    pub(crate) sink: Option<Box<dyn SampleSink>>,
 }
 
-pub struct StreamCore<T> {
+pub struct StreamCore {
     pub(crate) core: Arc<Mutex<Option<Core>>>,
-    pub(crate) receiver: mpsc::Receiver<StreamEvent>,
-    pub(crate) data: T,
+    pub(crate) io: StreamIo,
+    pub(crate) events: Arc<Mutex<mpsc::Sender<PWEvent>>>,
 }
 
-pub struct EStream<T> {
+pub struct EStream {
+    pub(crate) id: StreamId,
+    pub(crate) config: EStreamConfig,
     pub(crate) core: Core,
     pub(crate) stream: Arc<Mutex<Stream>>,
-    pub(crate) receiver: Arc<Mutex<mpsc::Receiver<StreamEvent>>>,
-    pub(crate) data: T,
+    pub(crate) io: StreamIo,
+    pub(crate) events: Arc<Mutex<mpsc::Sender<PWEvent>>>,
+    /// The events of the meter of the current stream, see [`forward_events()`](Self::forward_events).
+    pub(crate) meter_events: Option<Consumer<MeterEvent>>,
+    /// Cycles without a buffer since the last [`forward_events()`](Self::forward_events).
+    pub(crate) underruns: Arc<AtomicU64>,
 }
 
-fn audio_info() -> crate::spa::pod::Object {
-    let mut audio_info = spa::param::audio::AudioInfoRaw::new();
-    audio_info.set_format(spa::param::audio::AudioFormat::F32LE);
-    crate::spa::pod::Object {
-        type_: crate::spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
-        id: crate::spa::param::ParamType::EnumFormat.as_raw(),
-        properties: audio_info.into(),
+/// Hand the frames captured into `buffer` to `sink` and `observe`.
+fn capture(
+    buffer: &mut Buffer,
+    format: &AudioInfoRaw,
+    sink: Option<&SharedSink>,
+    mut observe: impl FnMut(&Frames),
+) {
+    // Only the stream of the current connection uses the sink, so the lock is
+    // never contended. Skip the block rather than block the realtime thread.
+    let mut sink = sink.and_then(|sink| sink.try_lock().ok());
+
+    let datas = buffer.datas_mut();
+    let planes = n_planes(format, datas.len());
+    for (index, data) in datas.iter_mut().take(planes).enumerate() {
+        let offset = data.chunk().offset() as usize;
+        let size = data.chunk().size() as usize;
+        if let Some(samples) = data.data() {
+            let end = (offset + size).min(samples.len());
+            let frames = Frames {
+                info: format,
+                data: &samples[offset.min(end)..end],
+                plane: format.format().is_planar().then_some(index),
+            };
+            if let Some(sink) = &mut sink {
+                sink.process(&frames);
+            }
+            observe(&frames);
+        }
     }
 }
 
-impl <T>EStream<T> {
+/// Fill `buffer` with frames from `source`, padding with silence, and hand them to `observe`.
+fn playback(
+    buffer: &mut Buffer,
+    format: &AudioInfoRaw,
+    source: &SharedSource,
+    mut observe: impl FnMut(&Frames),
+) {
+    #[cfg(feature = "v0_3_49")]
+    let requested = buffer.requested() as usize;
+    // Same as for capture: never block the realtime thread, play silence instead.
+    let mut source = source.try_lock().ok();
+
+    let datas = buffer.datas_mut();
+    let planes = n_planes(format, datas.len());
+    let stride = frame_size(format);
+    for (index, data) in datas.iter_mut().take(planes).enumerate() {
+        let Some(samples) = data.data() else {
+            continue;
+        };
+        #[allow(unused_mut)]
+        let mut count = n_frames(format, samples.len());
+        #[cfg(feature = "v0_3_49")]
+        if requested > 0 {
+            count = count.min(requested);
+        }
+        let samples = &mut samples[..count * stride];
+
+        let written = match &mut source {
+            Some(source) => {
+                let plane = format.format().is_planar().then_some(index);
+                let mut frames = FramesMut::new(format, &mut *samples, plane);
+                source.fill(&mut frames).min(count)
+            }
+            None => 0,
+        };
+        samples[written * stride..].fill(0);
+        observe(&Frames {
+            info: format,
+            data: samples,
+            plane: format.format().is_planar().then_some(index),
+        });
+
+        let chunk = data.chunk_mut();
+        *chunk.offset_mut() = 0;
+        *chunk.stride_mut() = stride as i32;
+        *chunk.size_mut() = (count * stride) as u32;
+    }
+}
+
+/// Serialize `obj` into the bytes of a pod.
+fn serialize(obj: Object) -> Result<Vec<u8>, EasyWireError> {
+    crate::spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &crate::spa::pod::Value::Object(obj),
+    )
+    .map(|(cursor, _)| cursor.into_inner())
+    .map_err(|error| EasyWireError::Format(format!("{:?}", error)))
+}
+
+/// View `values` as a pod.
+fn pod(values: &[u8]) -> Result<&Pod, EasyWireError> {
+    Pod::from_bytes(values).ok_or_else(|| EasyWireError::Format("Invalid pod".to_string()))
+}
+
+impl EStream {
     pub fn new(
-        stream_core: StreamCore<T>,
-    ) -> Self {
-        let (stream, core) = Stream::new(&stream_core.core.clone().lock().unwrap().clone().unwrap(), "capture-audio", properties! {
-                *keys::NODE_ID => 0.to_string(),
-            }).expect("Failed to create stream");
-println!("Created stream");
-        Self {
+        id: StreamId,
+        stream_core: StreamCore,
+        config: EStreamConfig,
+    ) -> Result<Self, EasyWireError> {
+        let core = stream_core.core.lock().unwrap().clone().ok_or(EasyWireError::NotRunning)?;
+        let category = match stream_core.io {
+            StreamIo::Capture(_) => "Capture",
+            StreamIo::Playback(_) => "Playback",
+        };
+        let (stream, core) = Stream::new(&core, &config.name, properties! {
+                *keys::NODE_NAME => config.name.clone(),
+                *keys::MEDIA_TYPE => "Audio",
+                *keys::MEDIA_CATEGORY => category,
+            }).map_err(EasyWireError::StreamCreation)?;
+        Ok(Self {
+            id,
+            config,
             core,
             stream: Arc::new(Mutex::new(stream)),
-            receiver: Arc::new(Mutex::new(stream_core.receiver)),
-            data: stream_core.data,
-        }
+            io: stream_core.io,
+            events: stream_core.events,
+            meter_events: None,
+            underruns: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub fn id(&self) -> StreamId {
+        self.id
     }
 
-    pub fn create_stream(&mut self, node_id: Option<u32>) -> StreamListener<Userdata> {
-        println!("Creating stream");
-        let receiver = Arc::clone(&self.receiver);
-        let mut data = Userdata {
+    pub fn config(&self) -> &EStreamConfig {
+        &self.config
+    }
+
+    /// Register the stream's listener and connect it to the configured target.
+    pub fn create_stream(&mut self) -> Result<StreamListener<Userdata>, EasyWireError> {
+        let stream_id = self.id;
+        let events = Arc::clone(&self.events);
+        let state_events = Arc::clone(&self.events);
+        let meter = match &self.config.meter {
+            Some(config) => {
+                let (producer, consumer) = rt_queue::queue(METER_EVENTS)?;
+                self.meter_events = Some(consumer);
+                Some(Mutex::new(StreamMeter {
+                    meter: Meter::new(config.clone()),
+                    events: producer,
+                }))
+            }
+            None => None,
+        };
+        let data = Userdata {
             format: Default::default(),
-            cursor_move: false,
+            io: self.io.clone(),
+            meter,
+            underruns: Arc::clone(&self.underruns),
         };
         let stream = Arc::clone(&self.stream);
 
         let _listener = stream.lock().unwrap()
             .add_local_listener_with_user_data(data)
-            .param_changed(|_, user_data, id, pod| {
+            .state_changed(move |_, _, _, new| {
+                if let StreamState::Error(message) = new {
+                    report(&state_events, EasyWireError::Stream(message));
+                }
+            })
+            .param_changed(move |_, user_data, id, pod| {
                 // NULL means to clear the format
                 let Some(pod): Option<&Pod> = pod else {
                     return;
@@ -93,147 +669,153 @@ println!("Created stream");
                 }
 
                 // call a helper function to parse the format for us.
-                user_data
-                    .format
-                    .parse(pod)
-                    .expect("Failed to parse param changed to AudioInfoRaw");
-
-                println!(
-                    "capturing rate:{} channels:{}",
-                    //user_data.lock().unwrap().into().format.rate(),
-                    //user_data.lock().unwrap().into().format.channels()
-                    user_data.format.rate(),
-                    user_data.format.channels()
-                );
+                if let Err(error) = user_data.format.parse(pod) {
+                    report(&events, EasyWireError::PipeWire(error.into()));
+                    return;
+                }
+
+                match &user_data.io {
+                    StreamIo::Capture(Some(sink)) => sink.lock().unwrap().format_changed(&user_data.format),
+                    StreamIo::Playback(source) => source.lock().unwrap().format_changed(&user_data.format),
+                    StreamIo::Capture(None) => {}
+                }
+                if let Some(meter) = &user_data.meter {
+                    meter.lock().unwrap().meter.format_changed(&user_data.format);
+                }
+                let _ = events.lock().unwrap().send(PWEvent::FormatNegotiated {
+                    stream: stream_id,
+                    info: user_data.format,
+                });
             })
             .process(move |streams, user_data| match streams.dequeue_buffer() {
-                None => println!("out of buffers"),
+                None => {
+                    // Counted rather than printed, the realtime thread must not do I/O.
+                    user_data.underruns.fetch_add(1, Ordering::Relaxed);
+                }
                 Some(mut buffer) => {
-                    // println!("processing");
-
-                    for event in receiver.lock().unwrap().iter() {
-                        match event {
-                            StreamEvent::UpdateObjID(obj_id) => {
-                                //println!("UpdateObjID: {:?}", obj_id);
-                                change_stream_node(&streams, obj_id);
-                            },
-                        }
-                    }
-
-                    let datas = buffer.datas_mut();
-                    if datas.is_empty() {
-                        return;
-                    }
-
-                    let data = &mut datas[0];
-                    //let n_channels = user_data.lock().unwrap().into().format.channels();
-                    let n_channels = user_data.format.channels();
-                    let n_samples = data.chunk().size() / (mem::size_of::<f32>() as u32);
-
-                    if let Some(samples) = data.data() {
-                        //if user_data.lock().unwrap().into().cursor_move {
-                        if user_data.cursor_move {
-                            print!("\x1B[{}A", n_channels + 1);
-                        }
-                        match (n_samples, n_channels) {
-                            (s, d) if s < 1 || d < 1 => {
-                                println!("Failed: samples {} | channels {}", n_samples, n_channels);
-                            }
-                            (s, d) if s >= 1 && d >= 1 => {
-                                println!("Success: captured {} samples", n_samples / n_channels);
-                            }
-                            _ => (),
-                        }
-
-                        for c in 0..n_channels {
-                            let mut max: f32 = 0.0;
-                            for n in (c..n_samples).step_by(n_channels as usize) {
-                                let start = n as usize * mem::size_of::<f32>();
-                                let end = start + mem::size_of::<f32>();
-                                let chan = &samples[start..end];
-                                let f = f32::from_le_bytes(chan.try_into().unwrap());
-                                max = max.max(f.abs());
-                            }
-
-                            let peak = ((max * 30.0) as usize).clamp(0, 39);
-
-                            println!(
-                                "channel {}: |{:>w1$}{:w2$}| peak:{}",
-                                c,
-                                "*",
-                                "",
-                                max,
-                                w1 = peak + 1,
-                                w2 = 40 - peak
-                            );
+                    let Userdata { format, io, meter, .. } = user_data;
+                    // Skip measuring rather than block the realtime thread while the format
+                    // changes.
+                    let mut meter = meter.as_ref().and_then(|meter| meter.try_lock().ok());
+                    let observe = |frames: &Frames| {
+                        if let Some(StreamMeter { meter, events }) = meter.as_deref_mut() {
+                            meter.process(frames, |event| {
+                                // Drop the event if the PipeWire thread is falling behind.
+                                let _ = events.push(event);
+                            });
                         }
-                        //user_data.lock().unwrap().into().cursor_move = true;
-                        user_data.cursor_move = true;
+                    };
+                    match io {
+                        StreamIo::Capture(sink) => capture(&mut buffer, format, sink.as_ref(), observe),
+                        StreamIo::Playback(source) => playback(&mut buffer, format, source, observe),
                     }
                 }
             })
-            .register().expect("Failed to create stream");
+            .register()
+            .map_err(EasyWireError::StreamCreation)?;
 
-
-        let obj = audio_info();
-        let values: Vec<u8> = crate::spa::pod::serialize::PodSerializer::serialize(
-            std::io::Cursor::new(Vec::new()),
-            &crate::spa::pod::Value::Object(obj),
-        )
-            .unwrap()
-            .0
-            .into_inner();
-
-        let mut params = [Pod::from_bytes(&values).unwrap()];
+        let values = serialize(self.config.format_param()?)?;
+        let mut params = [pod(&values)?];
 
         /* Now connect this stream. We ask that our process function is
          * called in a realtime thread. */
         stream.lock().unwrap().connect(
-            spa::utils::Direction::Input,
-            Some(node_id.unwrap_or(0).clone()),
+            self.io.direction(),
+            self.config.target,
             crate::stream::StreamFlags::AUTOCONNECT
                 | crate::stream::StreamFlags::MAP_BUFFERS
                 | crate::stream::StreamFlags::RT_PROCESS,
             &mut params,
-        ).expect("Failed to connect stream");
-        println!("Connected stream");
-        _listener
+        ).map_err(EasyWireError::StreamConnection)?;
+        Ok(_listener)
     }
-}
 
-fn change_stream_node(stream: &&StreamRef, obj_id: u32) {
-    // let stream = self.stream.clone();
-    // let mut old_props = stream.lock().unwrap().unwrap().properties();
-    // old_props.insert(*keys::NODE_ID, obj_id.clone().to_string());
+    /// Send the events the process callback queued to the manager, and let the sink forward
+    /// what it queued, see [`SampleSink::forward`].
+    ///
+    /// The process callback runs on the realtime thread, which must not take the lock of the
+    /// event sender, so the PipeWire thread calls this regularly instead.
+    pub fn forward_events(&mut self) {
+        let events = self.events.lock().unwrap();
+        if let Some(meter_events) = &mut self.meter_events {
+            for event in meter_events.drain() {
+                let _ = events.send(PWEvent::Meter { stream: self.id, event });
+            }
+        }
+        let count = self.underruns.swap(0, Ordering::Relaxed);
+        if count > 0 {
+            let _ = events.send(PWEvent::Underrun { stream: self.id, count });
+        }
+        if let StreamIo::Capture(Some(sink)) = &self.io {
+            sink.lock().unwrap().forward();
+        }
+    }
 
+    /// Whether the stream's node was created on the server, which it needs to be moved.
+    pub fn has_node(&self) -> bool {
+        self.stream.lock().unwrap().node_id() != crate::constants::ID_ANY
+    }
 
-    // stream.disconnect().expect("TODO: panic message");
-    let mut audio_info = spa::param::audio::AudioInfoRaw::new();
-    audio_info.set_format(spa::param::audio::AudioFormat::F32LE);
-    let obj = crate::spa::pod::Object {
-        type_: crate::spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
-        id: obj_id,
-        properties: audio_info.into(),
-    };
-    let values: Vec<u8> = crate::spa::pod::serialize::PodSerializer::serialize(
-        std::io::Cursor::new(Vec::new()),
-        &crate::spa::pod::Value::Object(obj),
-    )
-        .unwrap()
-        .0
-        .into_inner();
+    /// Move the stream to the node `target` while it keeps running.
+    ///
+    /// The target is set on the stream's node with [`DefaultMetadata::set_target`], the session
+    /// manager then relinks the stream. `serial` is the `object.serial` of `target`. When known
+    /// it is set as `target.object`, which newer session managers prefer over `target.node`.
+    ///
+    /// Fails if the stream does not have a node yet or there is no `default` metadata.
+    pub fn retarget(
+        &self,
+        target: u32,
+        serial: Option<&str>,
+        metadata: Option<&DefaultMetadata>,
+    ) -> Result<(), EasyWireError> {
+        let error = |reason: &str| EasyWireError::Retarget {
+            target,
+            reason: reason.to_string(),
+        };
+
+        let metadata = metadata.ok_or_else(|| error("no default metadata"))?;
+        let node_id = self.stream.lock().unwrap().node_id();
+        if node_id == crate::constants::ID_ANY {
+            return Err(error("the stream has no node yet"));
+        }
 
-    let mut params = [Pod::from_bytes(&values).unwrap()];
+        let target = match serial {
+            Some(serial) => StreamTarget::Object(serial.to_string()),
+            None => StreamTarget::Node(target),
+        };
+        metadata.set_target(node_id, Some(&target));
+        Ok(())
+    }
+}
 
-    stream.update_params(&mut params).expect("Failed to create stream");
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // stream.connect(
-    //     spa::utils::Direction::Input,
-    //     Some(obj_id),
-    //     crate::stream::StreamFlags::AUTOCONNECT
-    //         | crate::stream::StreamFlags::MAP_BUFFERS
-    //         | crate::stream::StreamFlags::RT_PROCESS,
-    //     &mut params,
-    // );
+    #[test]
+    fn format_param() {
+        let mut config = EStreamConfig::new("test");
+        config.set_formats(&[AudioFormat::S16LE, AudioFormat::F32P]);
+        config.set_rate_range(48000, 44100, 96000);
+        config.set_positions(&[spa::sys::SPA_AUDIO_CHANNEL_FL, spa::sys::SPA_AUDIO_CHANNEL_FR]);
 
-}
\ No newline at end of file
+        let param = config.format_param().unwrap();
+        assert_eq!(param.properties.len(), 6);
+        let Value::Choice(ChoiceValue::Id(Choice(_, ChoiceEnum::Enum { default, alternatives }))) =
+            &param.properties[2].value
+        else {
+            panic!("the sample format is not an Enum choice");
+        };
+        assert_eq!(*default, Id(AudioFormat::S16LE.as_raw()));
+        assert_eq!(alternatives, &[Id(AudioFormat::S16LE.as_raw()), Id(AudioFormat::F32P.as_raw())]);
+        assert_eq!(param.properties[4].value, Value::Int(2));
+
+        config.set_channels(1);
+        assert!(config.format_param().is_err());
+
+        config.positions = None;
+        config.rate = Some(ChoiceValue::Id(fixed(Id(48000))));
+        assert!(config.format_param().is_err());
+    }
+}
@@ -13,3 +13,64 @@ pub enum Error {
     #[error(transparent)]
     SpaError(#[from] spa::utils::result::Error),
 }
+
+/// Errors of the easy-wire layer built on top of the bindings.
+///
+/// Failures of the bindings themselves are wrapped in [`EasyWireError::PipeWire`], every other
+/// variant describes what the easy-wire layer was doing when it failed.
+#[derive(Error, Debug)]
+pub enum EasyWireError {
+    #[error(transparent)]
+    PipeWire(#[from] Error),
+    #[error("Failed to connect to PipeWire: {0}")]
+    Connection(#[source] Error),
+    #[error("Failed to bind global {id}: {source}")]
+    Bind {
+        id: u32,
+        #[source]
+        source: Error,
+    },
+    #[error("Failed to create stream: {0}")]
+    StreamCreation(#[source] Error),
+    #[error("Failed to connect stream: {0}")]
+    StreamConnection(#[source] Error),
+    #[error("Stream error: {0}")]
+    Stream(String),
+    #[error("Invalid format: {0}")]
+    Format(String),
+    #[error("PipeWire error on object {id} (seq {seq}, res {res}): {message}")]
+    Remote {
+        id: u32,
+        seq: i32,
+        res: i32,
+        message: String,
+    },
+    #[error("Failed to retarget the stream to node {target}: {reason}")]
+    Retarget { target: u32, reason: String },
+    #[error("There is no stream {0}")]
+    UnknownStream(crate::e_stream::StreamId),
+    #[error("The stream {0} already exists")]
+    StreamExists(crate::e_stream::StreamId),
+    #[error("Invalid virtual device: {0}")]
+    VirtualDevice(String),
+    #[error("There is no virtual device {0}")]
+    UnknownVirtualDevice(crate::virtual_device::VirtualDeviceId),
+    #[error("Failed to link node {output} to node {input}: {reason}")]
+    Link { output: u32, input: u32, reason: String },
+    #[error("There are no links {0}")]
+    UnknownLinks(crate::node_links::NodeLinksId),
+    #[error("There is no default metadata")]
+    NoDefaultMetadata,
+    #[error("Gave up reconnecting to PipeWire after {0} attempts")]
+    ReconnectFailed(u32),
+    #[error("The PipeWire thread is not running")]
+    NotRunning,
+    #[error("Failed to record to {path:?}")]
+    Recording {
+        path: std::path::PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to spawn the PipeWire thread: {0}")]
+    Thread(#[source] std::io::Error),
+}
@@ -0,0 +1,736 @@
+//! Pipewire Filter
+//!
+//! A filter is a node with any number of input and output ports, processed together in one
+//! `process` callback. Use it for DSP nodes like upmixers or sidechain compressors, where a
+//! [`Stream`](crate::stream::Stream) with its single port does not fit.
+//!
+//! # Examples
+//! ```no_run
+//! use pipewire::{filter::*, main_loop::MainLoop, context::Context, properties::properties};
+//! use spa::utils::Direction;
+//!
+//! struct Ports<'f> {
+//!     input: Port<'f>,
+//!     output: Port<'f>,
+//! }
+//!
+//! let main_loop = MainLoop::new()?;
+//! let context = Context::new(&main_loop)?;
+//! let core = context.connect(None)?;
+//!
+//! let filter = Filter::new(&core, "volume", properties! {
+//!     *pipewire::keys::MEDIA_TYPE => "Audio",
+//!     *pipewire::keys::MEDIA_CATEGORY => "Filter",
+//! })?;
+//! let dsp = || properties! {
+//!     *pipewire::keys::FORMAT_DSP => "32 bit float mono audio",
+//! };
+//! let ports = Ports {
+//!     input: filter.add_port(Direction::Input, FilterPortFlags::MAP_BUFFERS, dsp(), &mut [])?,
+//!     output: filter.add_port(Direction::Output, FilterPortFlags::MAP_BUFFERS, dsp(), &mut [])?,
+//! };
+//!
+//! let _listener = filter
+//!     .add_local_listener_with_user_data(ports)
+//!     .process(|_, ports, position| {
+//!         let Some(position) = position else { return };
+//!         let n_samples = position.clock.duration as u32;
+//!         let input = ports.input.dsp_buffer(n_samples);
+//!         let output = ports.output.dsp_buffer(n_samples);
+//!         if let (Some(input), Some(output)) = (input, output) {
+//!             for (output, input) in output.iter_mut().zip(input.iter()) {
+//!                 *output = input * 0.5;
+//!             }
+//!         }
+//!     })
+//!     .register()?;
+//!
+//! filter.connect(FilterFlags::RT_PROCESS, &mut [])?;
+//! main_loop.run();
+//! # Ok::<(), pipewire::Error>(())
+//! ```
+
+use std::{
+    ffi::{self, CStr, CString},
+    marker::PhantomData,
+    mem, os,
+    pin::Pin,
+    ptr,
+};
+
+use bitflags::bitflags;
+use spa::utils::result::SpaResult;
+
+use crate::{
+    buffer::Buffer,
+    core::Core,
+    error::Error,
+    properties::{Properties, PropertiesRef},
+};
+
+#[derive(Debug, PartialEq)]
+pub enum FilterState {
+    Error(String),
+    Unconnected,
+    Connecting,
+    Paused,
+    Streaming,
+}
+
+impl FilterState {
+    pub(crate) fn from_raw(state: pw_sys::pw_filter_state, error: *const os::raw::c_char) -> Self {
+        match state {
+            pw_sys::pw_filter_state_PW_FILTER_STATE_UNCONNECTED => FilterState::Unconnected,
+            pw_sys::pw_filter_state_PW_FILTER_STATE_CONNECTING => FilterState::Connecting,
+            pw_sys::pw_filter_state_PW_FILTER_STATE_PAUSED => FilterState::Paused,
+            pw_sys::pw_filter_state_PW_FILTER_STATE_STREAMING => FilterState::Streaming,
+            _ => {
+                let error = if error.is_null() {
+                    "".to_string()
+                } else {
+                    unsafe { ffi::CStr::from_ptr(error).to_string_lossy().to_string() }
+                };
+
+                FilterState::Error(error)
+            }
+        }
+    }
+}
+
+/// A wrapper around the pipewire filter interface, see the [module documentation](self).
+pub struct Filter {
+    ptr: ptr::NonNull<pw_sys::pw_filter>,
+}
+
+impl Filter {
+    /// Create a [`Filter`]
+    ///
+    /// Initialises a new filter with the given `name` and `properties`.
+    pub fn new(core: &Core, name: &str, properties: Properties) -> Result<Self, Error> {
+        let name = CString::new(name).expect("Invalid byte in filter name");
+        let filter = unsafe {
+            pw_sys::pw_filter_new(core.as_raw_ptr(), name.as_ptr(), properties.into_raw())
+        };
+        let filter = ptr::NonNull::new(filter).ok_or(Error::CreationFailed)?;
+
+        Ok(Filter { ptr: filter })
+    }
+
+    pub fn into_raw(self) -> *mut pw_sys::pw_filter {
+        let ptr = self.ptr.as_ptr();
+        mem::forget(self);
+        ptr
+    }
+}
+
+impl std::ops::Deref for Filter {
+    type Target = FilterRef;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.ptr.cast().as_ref() }
+    }
+}
+
+impl std::fmt::Debug for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Filter")
+            .field("name", &self.name())
+            .field("state", &self.state())
+            .field("node-id", &self.node_id())
+            .field("properties", &self.properties())
+            .finish()
+    }
+}
+
+impl std::ops::Drop for Filter {
+    fn drop(&mut self) {
+        unsafe { pw_sys::pw_filter_destroy(self.as_raw_ptr()) }
+    }
+}
+
+#[repr(transparent)]
+pub struct FilterRef(pw_sys::pw_filter);
+
+impl FilterRef {
+    pub fn as_raw(&self) -> &pw_sys::pw_filter {
+        &self.0
+    }
+
+    pub fn as_raw_ptr(&self) -> *mut pw_sys::pw_filter {
+        ptr::addr_of!(self.0).cast_mut()
+    }
+
+    /// Add a local listener builder
+    #[must_use = "Fluent builder API"]
+    pub fn add_local_listener_with_user_data<D>(
+        &self,
+        user_data: D,
+    ) -> FilterListenerLocalBuilder<'_, D> {
+        let mut callbacks = ListenerLocalCallbacks::with_user_data(user_data);
+        callbacks.filter =
+            Some(ptr::NonNull::new(self.as_raw_ptr()).expect("Pointer should be nonnull"));
+        FilterListenerLocalBuilder {
+            filter: self,
+            callbacks,
+        }
+    }
+
+    /// Add a local listener builder. User data is initialized with its default value
+    #[must_use = "Fluent builder API"]
+    pub fn add_local_listener<D: Default>(&self) -> FilterListenerLocalBuilder<'_, D> {
+        self.add_local_listener_with_user_data(Default::default())
+    }
+
+    /// Add a port to the filter
+    ///
+    /// `properties` describe the port, for DSP ports `format.dsp` gives the sample format of
+    /// the buffers returned by [`Port::dsp_buffer`]. `params` are the initial params of the
+    /// port, like its `EnumFormat`.
+    ///
+    /// The returned [`Port`] removes the port again on drop, and borrows the filter so that it
+    /// cannot outlive it.
+    pub fn add_port(
+        &self,
+        direction: spa::utils::Direction,
+        flags: FilterPortFlags,
+        properties: Properties,
+        params: &mut [&spa::pod::Pod],
+    ) -> Result<Port<'_>, Error> {
+        let port = unsafe {
+            pw_sys::pw_filter_add_port(
+                self.as_raw_ptr(),
+                direction.as_raw(),
+                flags.bits(),
+                0,
+                properties.into_raw(),
+                // spa::pod::Pod is a transparent wrapper around spa_sys::spa_pod
+                params.as_mut_ptr().cast(),
+                params.len() as u32,
+            )
+        };
+        let ptr = ptr::NonNull::new(port).ok_or(Error::CreationFailed)?;
+
+        Ok(Port {
+            ptr,
+            direction,
+            _filter: PhantomData,
+        })
+    }
+
+    /// Connect the filter
+    ///
+    /// The filter is linked by the session manager according to its properties. `params` are
+    /// the params of the filter node itself.
+    pub fn connect(&self, flags: FilterFlags, params: &mut [&spa::pod::Pod]) -> Result<(), Error> {
+        let r = unsafe {
+            pw_sys::pw_filter_connect(
+                self.as_raw_ptr(),
+                flags.bits(),
+                params.as_mut_ptr().cast(),
+                params.len() as u32,
+            )
+        };
+
+        SpaResult::from_c(r).into_sync_result()?;
+        Ok(())
+    }
+
+    /// Disconnect the filter
+    pub fn disconnect(&self) -> Result<(), Error> {
+        let r = unsafe { pw_sys::pw_filter_disconnect(self.as_raw_ptr()) };
+
+        SpaResult::from_c(r).into_sync_result()?;
+        Ok(())
+    }
+
+    /// Update the params of `port`, or of the filter node itself without a port
+    pub fn update_params(
+        &self,
+        port: Option<&Port<'_>>,
+        params: &mut [&spa::pod::Pod],
+    ) -> Result<(), Error> {
+        let r = unsafe {
+            pw_sys::pw_filter_update_params(
+                self.as_raw_ptr(),
+                port.map_or(ptr::null_mut(), Port::as_raw_ptr),
+                params.as_mut_ptr().cast(),
+                params.len() as u32,
+            )
+        };
+
+        SpaResult::from_c(r).into_sync_result()?;
+        Ok(())
+    }
+
+    /// Update the properties of `port`, or of the filter node itself without a port
+    ///
+    /// Returns the number of properties that changed.
+    pub fn update_properties(
+        &self,
+        port: Option<&Port<'_>>,
+        properties: &spa::utils::dict::DictRef,
+    ) -> Result<u32, Error> {
+        let r = unsafe {
+            pw_sys::pw_filter_update_properties(
+                self.as_raw_ptr(),
+                port.map_or(ptr::null_mut(), Port::as_raw_ptr),
+                properties.as_raw_ptr(),
+            )
+        };
+
+        let changed = SpaResult::from_c(r).into_sync_result()?;
+        Ok(changed as u32)
+    }
+
+    /// Activate or deactivate the filter
+    pub fn set_active(&self, active: bool) -> Result<(), Error> {
+        let r = unsafe { pw_sys::pw_filter_set_active(self.as_raw_ptr(), active) };
+
+        SpaResult::from_c(r).into_sync_result()?;
+        Ok(())
+    }
+
+    /// Flush the filter. When  `drain` is `true`, the `drained` callback will
+    /// be called when all data is processed.
+    pub fn flush(&self, drain: bool) -> Result<(), Error> {
+        let r = unsafe { pw_sys::pw_filter_flush(self.as_raw_ptr(), drain) };
+
+        SpaResult::from_c(r).into_sync_result()?;
+        Ok(())
+    }
+
+    // getters
+
+    /// Get the name of the filter.
+    pub fn name(&self) -> String {
+        let name = unsafe {
+            let name = pw_sys::pw_filter_get_name(self.as_raw_ptr());
+            CStr::from_ptr(name)
+        };
+
+        name.to_string_lossy().to_string()
+    }
+
+    /// Get the current state of the filter.
+    pub fn state(&self) -> FilterState {
+        let mut error: *const std::os::raw::c_char = ptr::null();
+        let state = unsafe {
+            pw_sys::pw_filter_get_state(self.as_raw_ptr(), (&mut error) as *mut *const _)
+        };
+        FilterState::from_raw(state, error)
+    }
+
+    /// Get the properties of the filter node.
+    pub fn properties(&self) -> &PropertiesRef {
+        unsafe {
+            let props = pw_sys::pw_filter_get_properties(self.as_raw_ptr(), ptr::null_mut());
+            let props = ptr::NonNull::new(props.cast_mut()).expect("filter properties is NULL");
+            props.cast().as_ref()
+        }
+    }
+
+    /// Get the properties of `port`.
+    pub fn port_properties(&self, port: &Port<'_>) -> &PropertiesRef {
+        unsafe {
+            let props = pw_sys::pw_filter_get_properties(self.as_raw_ptr(), port.as_raw_ptr());
+            let props = ptr::NonNull::new(props.cast_mut()).expect("port properties is NULL");
+            props.cast().as_ref()
+        }
+    }
+
+    /// Get the node ID of the filter.
+    pub fn node_id(&self) -> u32 {
+        unsafe { pw_sys::pw_filter_get_node_id(self.as_raw_ptr()) }
+    }
+}
+
+/// A port of a [`Filter`], see [`FilterRef::add_port`].
+pub struct Port<'f> {
+    ptr: ptr::NonNull<os::raw::c_void>,
+    direction: spa::utils::Direction,
+    _filter: PhantomData<&'f FilterRef>,
+}
+
+impl Port<'_> {
+    /// The port data pointer PipeWire identifies the port by, as passed to the callbacks of a
+    /// filter listener.
+    pub fn as_raw_ptr(&self) -> *mut os::raw::c_void {
+        self.ptr.as_ptr()
+    }
+
+    pub fn direction(&self) -> spa::utils::Direction {
+        self.direction
+    }
+
+    /// Take a Buffer from the port
+    ///
+    /// For an input port the buffer contains data ready to process, for an output port it can
+    /// be filled. The buffer is given back to the port on drop.
+    pub fn dequeue_buffer(&self) -> Option<Buffer> {
+        unsafe { Buffer::from_port(pw_sys::pw_filter_dequeue_buffer(self.as_raw_ptr()), self) }
+    }
+
+    /// Return a Buffer to the port
+    ///
+    /// # Safety
+    ///
+    /// The buffer pointer should be one obtained from this port by
+    /// [`pw_filter_dequeue_buffer`](pw_sys::pw_filter_dequeue_buffer).
+    pub unsafe fn queue_raw_buffer(&self, buffer: *mut pw_sys::pw_buffer) {
+        pw_sys::pw_filter_queue_buffer(self.as_raw_ptr(), buffer);
+    }
+
+    /// Get the samples of the current cycle of a DSP port
+    ///
+    /// Only valid in the `process` callback, with `n_samples` the duration of the cycle. For an
+    /// output port the samples are to be filled. Returns `None` when there is no buffer.
+    pub fn dsp_buffer(&mut self, n_samples: u32) -> Option<&mut [f32]> {
+        let samples = unsafe { pw_sys::pw_filter_get_dsp_buffer(self.as_raw_ptr(), n_samples) };
+        if samples.is_null() {
+            return None;
+        }
+        unsafe {
+            Some(std::slice::from_raw_parts_mut(
+                samples as *mut f32,
+                n_samples as usize,
+            ))
+        }
+    }
+}
+
+impl std::ops::Drop for Port<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            pw_sys::pw_filter_remove_port(self.as_raw_ptr());
+        }
+    }
+}
+
+type PortCB<D> = dyn FnMut(&FilterRef, &mut D, *mut os::raw::c_void, *mut pw_sys::pw_buffer);
+type ParamChangedCB<D> =
+    dyn FnMut(&FilterRef, &mut D, *mut os::raw::c_void, u32, Option<&spa::pod::Pod>);
+type ProcessCB<D> = dyn FnMut(&FilterRef, &mut D, Option<&spa_sys::spa_io_position>);
+
+#[allow(clippy::type_complexity)]
+pub struct ListenerLocalCallbacks<D> {
+    pub state_changed: Option<Box<dyn FnMut(&FilterRef, &mut D, FilterState, FilterState)>>,
+    pub io_changed: Option<
+        Box<dyn FnMut(&FilterRef, &mut D, *mut os::raw::c_void, u32, *mut os::raw::c_void, u32)>,
+    >,
+    pub param_changed: Option<Box<ParamChangedCB<D>>>,
+    pub add_buffer: Option<Box<PortCB<D>>>,
+    pub remove_buffer: Option<Box<PortCB<D>>>,
+    pub process: Option<Box<ProcessCB<D>>>,
+    pub drained: Option<Box<dyn FnMut(&FilterRef, &mut D)>>,
+    pub user_data: D,
+    filter: Option<ptr::NonNull<pw_sys::pw_filter>>,
+}
+
+unsafe fn unwrap_filter_ptr<'a>(filter: Option<ptr::NonNull<pw_sys::pw_filter>>) -> &'a FilterRef {
+    filter
+        .map(|ptr| ptr.cast::<FilterRef>().as_ref())
+        .expect("filter cannot be null")
+}
+
+impl<D> ListenerLocalCallbacks<D> {
+    fn with_user_data(user_data: D) -> Self {
+        ListenerLocalCallbacks {
+            state_changed: Default::default(),
+            io_changed: Default::default(),
+            param_changed: Default::default(),
+            add_buffer: Default::default(),
+            remove_buffer: Default::default(),
+            process: Default::default(),
+            drained: Default::default(),
+            user_data,
+            filter: Default::default(),
+        }
+    }
+
+    pub(crate) fn into_raw(
+        self,
+    ) -> (
+        Pin<Box<pw_sys::pw_filter_events>>,
+        Box<ListenerLocalCallbacks<D>>,
+    ) {
+        let callbacks = Box::new(self);
+
+        unsafe extern "C" fn on_state_changed<D>(
+            data: *mut os::raw::c_void,
+            old: pw_sys::pw_filter_state,
+            new: pw_sys::pw_filter_state,
+            error: *const os::raw::c_char,
+        ) {
+            if let Some(state) = (data as *mut ListenerLocalCallbacks<D>).as_mut() {
+                if let Some(cb) = &mut state.state_changed {
+                    let filter = unwrap_filter_ptr(state.filter);
+                    let old = FilterState::from_raw(old, error);
+                    let new = FilterState::from_raw(new, error);
+                    cb(filter, &mut state.user_data, old, new)
+                };
+            }
+        }
+
+        unsafe extern "C" fn on_io_changed<D>(
+            data: *mut os::raw::c_void,
+            port_data: *mut os::raw::c_void,
+            id: u32,
+            area: *mut os::raw::c_void,
+            size: u32,
+        ) {
+            if let Some(state) = (data as *mut ListenerLocalCallbacks<D>).as_mut() {
+                if let Some(cb) = &mut state.io_changed {
+                    let filter = unwrap_filter_ptr(state.filter);
+                    cb(filter, &mut state.user_data, port_data, id, area, size);
+                }
+            }
+        }
+
+        unsafe extern "C" fn on_param_changed<D>(
+            data: *mut os::raw::c_void,
+            port_data: *mut os::raw::c_void,
+            id: u32,
+            param: *const spa_sys::spa_pod,
+        ) {
+            if let Some(state) = (data as *mut ListenerLocalCallbacks<D>).as_mut() {
+                if let Some(cb) = &mut state.param_changed {
+                    let filter = unwrap_filter_ptr(state.filter);
+                    let param = if !param.is_null() {
+                        Some(spa::pod::Pod::from_raw(param))
+                    } else {
+                        None
+                    };
+
+                    cb(filter, &mut state.user_data, port_data, id, param);
+                }
+            }
+        }
+
+        unsafe extern "C" fn on_add_buffer<D>(
+            data: *mut os::raw::c_void,
+            port_data: *mut os::raw::c_void,
+            buffer: *mut pw_sys::pw_buffer,
+        ) {
+            if let Some(state) = (data as *mut ListenerLocalCallbacks<D>).as_mut() {
+                if let Some(cb) = &mut state.add_buffer {
+                    let filter = unwrap_filter_ptr(state.filter);
+                    cb(filter, &mut state.user_data, port_data, buffer);
+                }
+            }
+        }
+
+        unsafe extern "C" fn on_remove_buffer<D>(
+            data: *mut os::raw::c_void,
+            port_data: *mut os::raw::c_void,
+            buffer: *mut pw_sys::pw_buffer,
+        ) {
+            if let Some(state) = (data as *mut ListenerLocalCallbacks<D>).as_mut() {
+                if let Some(cb) = &mut state.remove_buffer {
+                    let filter = unwrap_filter_ptr(state.filter);
+                    cb(filter, &mut state.user_data, port_data, buffer);
+                }
+            }
+        }
+
+        unsafe extern "C" fn on_process<D>(
+            data: *mut os::raw::c_void,
+            position: *mut spa_sys::spa_io_position,
+        ) {
+            if let Some(state) = (data as *mut ListenerLocalCallbacks<D>).as_mut() {
+                if let Some(cb) = &mut state.process {
+                    let filter = unwrap_filter_ptr(state.filter);
+                    cb(filter, &mut state.user_data, position.as_ref());
+                }
+            }
+        }
+
+        unsafe extern "C" fn on_drained<D>(data: *mut os::raw::c_void) {
+            if let Some(state) = (data as *mut ListenerLocalCallbacks<D>).as_mut() {
+                if let Some(cb) = &mut state.drained {
+                    let filter = unwrap_filter_ptr(state.filter);
+                    cb(filter, &mut state.user_data);
+                }
+            }
+        }
+
+        let events = unsafe {
+            let mut events: Pin<Box<pw_sys::pw_filter_events>> = Box::pin(mem::zeroed());
+            events.version = pw_sys::PW_VERSION_FILTER_EVENTS;
+
+            if callbacks.state_changed.is_some() {
+                events.state_changed = Some(on_state_changed::<D>);
+            }
+            if callbacks.io_changed.is_some() {
+                events.io_changed = Some(on_io_changed::<D>);
+            }
+            if callbacks.param_changed.is_some() {
+                events.param_changed = Some(on_param_changed::<D>);
+            }
+            if callbacks.add_buffer.is_some() {
+                events.add_buffer = Some(on_add_buffer::<D>);
+            }
+            if callbacks.remove_buffer.is_some() {
+                events.remove_buffer = Some(on_remove_buffer::<D>);
+            }
+            if callbacks.process.is_some() {
+                events.process = Some(on_process::<D>);
+            }
+            if callbacks.drained.is_some() {
+                events.drained = Some(on_drained::<D>);
+            }
+
+            events
+        };
+
+        (events, callbacks)
+    }
+}
+
+#[must_use]
+pub struct FilterListenerLocalBuilder<'a, D> {
+    filter: &'a FilterRef,
+    callbacks: ListenerLocalCallbacks<D>,
+}
+
+impl<'a, D> FilterListenerLocalBuilder<'a, D> {
+    /// Set the callback for the `state_changed` event.
+    pub fn state_changed<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&FilterRef, &mut D, FilterState, FilterState) + 'static,
+    {
+        self.callbacks.state_changed = Some(Box::new(callback));
+        self
+    }
+
+    /// Set the callback for the `io_changed` event.
+    ///
+    /// The callback gets the [port data](Port::as_raw_ptr) of the port, or NULL for the filter
+    /// node itself.
+    pub fn io_changed<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&FilterRef, &mut D, *mut os::raw::c_void, u32, *mut os::raw::c_void, u32)
+            + 'static,
+    {
+        self.callbacks.io_changed = Some(Box::new(callback));
+        self
+    }
+
+    /// Set the callback for the `param_changed` event.
+    ///
+    /// The callback gets the [port data](Port::as_raw_ptr) of the port, or NULL for the filter
+    /// node itself.
+    pub fn param_changed<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&FilterRef, &mut D, *mut os::raw::c_void, u32, Option<&spa::pod::Pod>)
+            + 'static,
+    {
+        self.callbacks.param_changed = Some(Box::new(callback));
+        self
+    }
+
+    /// Set the callback for the `add_buffer` event.
+    pub fn add_buffer<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&FilterRef, &mut D, *mut os::raw::c_void, *mut pw_sys::pw_buffer) + 'static,
+    {
+        self.callbacks.add_buffer = Some(Box::new(callback));
+        self
+    }
+
+    /// Set the callback for the `remove_buffer` event.
+    pub fn remove_buffer<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&FilterRef, &mut D, *mut os::raw::c_void, *mut pw_sys::pw_buffer) + 'static,
+    {
+        self.callbacks.remove_buffer = Some(Box::new(callback));
+        self
+    }
+
+    /// Set the callback for the `process` event.
+    ///
+    /// The callback gets the position of the graph, whose `clock.duration` is the number of
+    /// samples to process in this cycle.
+    pub fn process<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&FilterRef, &mut D, Option<&spa_sys::spa_io_position>) + 'static,
+    {
+        self.callbacks.process = Some(Box::new(callback));
+        self
+    }
+
+    /// Set the callback for the `drained` event.
+    pub fn drained<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&FilterRef, &mut D) + 'static,
+    {
+        self.callbacks.drained = Some(Box::new(callback));
+        self
+    }
+
+    /// Register the Callbacks
+    ///
+    /// Stop building the listener and register it on the filter. Returns a
+    /// `FilterListener` handle that will un-register the listener on drop.
+    pub fn register(self) -> Result<FilterListener<D>, Error> {
+        let (events, data) = self.callbacks.into_raw();
+        let (listener, data) = unsafe {
+            let listener: Box<spa_sys::spa_hook> = Box::new(mem::zeroed());
+            let raw_listener = Box::into_raw(listener);
+            let raw_data = Box::into_raw(data);
+            pw_sys::pw_filter_add_listener(
+                self.filter.as_raw_ptr(),
+                raw_listener,
+                events.as_ref().get_ref(),
+                raw_data as *mut _,
+            );
+            (Box::from_raw(raw_listener), Box::from_raw(raw_data))
+        };
+        Ok(FilterListener {
+            listener,
+            _events: events,
+            _data: data,
+        })
+    }
+}
+
+pub struct FilterListener<D> {
+    listener: Box<spa_sys::spa_hook>,
+    // Need to stay allocated while the listener is registered
+    _events: Pin<Box<pw_sys::pw_filter_events>>,
+    _data: Box<ListenerLocalCallbacks<D>>,
+}
+
+impl<D> FilterListener<D> {
+    /// Stop the listener from receiving any events
+    ///
+    /// Removes the listener registration and cleans up allocated resources.
+    pub fn unregister(self) {
+        // do nothing, drop will clean up.
+    }
+}
+
+impl<D> std::ops::Drop for FilterListener<D> {
+    fn drop(&mut self) {
+        spa::utils::hook::remove(*self.listener);
+    }
+}
+
+bitflags! {
+    /// Extra flags that can be used in [`FilterRef::connect()`]
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub struct FilterFlags: pw_sys::pw_filter_flags {
+        const INACTIVE = pw_sys::pw_filter_flags_PW_FILTER_FLAG_INACTIVE;
+        const DRIVER = pw_sys::pw_filter_flags_PW_FILTER_FLAG_DRIVER;
+        const RT_PROCESS = pw_sys::pw_filter_flags_PW_FILTER_FLAG_RT_PROCESS;
+        const CUSTOM_LATENCY = pw_sys::pw_filter_flags_PW_FILTER_FLAG_CUSTOM_LATENCY;
+    }
+}
+
+bitflags! {
+    /// Extra flags that can be used in [`FilterRef::add_port()`]
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub struct FilterPortFlags: pw_sys::pw_filter_port_flags {
+        const MAP_BUFFERS = pw_sys::pw_filter_port_flags_PW_FILTER_PORT_FLAG_MAP_BUFFERS;
+        const ALLOC_BUFFERS = pw_sys::pw_filter_port_flags_PW_FILTER_PORT_FLAG_ALLOC_BUFFERS;
+    }
+}
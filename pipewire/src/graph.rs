@@ -0,0 +1,464 @@
+//! In-memory model of the PipeWire graph.
+//!
+//! The [`PipeWireManager`](crate::pipe_wire_manager::PipeWireManager) binds every node, port,
+//! link, device and client announced by the registry and keeps a [`Graph`] of them up to date.
+//! Every change to the graph is also reported as a [`GraphEvent`].
+
+use std::collections::HashMap;
+
+use spa::utils::dict::DictRef;
+use spa::utils::Direction;
+
+use crate::keys;
+use crate::link::LinkState;
+use crate::node::NodeState;
+
+/// Owned copy of the properties of a graph object.
+pub type Props = HashMap<String, String>;
+
+pub(crate) fn props_from_dict(dict: Option<&DictRef>) -> Props {
+    dict.map(|dict| {
+        dict.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+fn parse_id(props: &Props, key: &str) -> Option<u32> {
+    props.get(key).and_then(|id| id.parse().ok())
+}
+
+/// The kind of object stored in the [`Graph`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObjectKind {
+    Node,
+    Port,
+    Link,
+    Device,
+    Client,
+}
+
+/// Owned copy of a [`NodeState`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphNodeState {
+    Error(String),
+    Creating,
+    Suspended,
+    Idle,
+    Running,
+}
+
+impl From<NodeState<'_>> for GraphNodeState {
+    fn from(state: NodeState<'_>) -> Self {
+        match state {
+            NodeState::Error(error) => GraphNodeState::Error(error.to_string()),
+            NodeState::Creating => GraphNodeState::Creating,
+            NodeState::Suspended => GraphNodeState::Suspended,
+            NodeState::Idle => GraphNodeState::Idle,
+            NodeState::Running => GraphNodeState::Running,
+        }
+    }
+}
+
+/// Owned copy of a [`LinkState`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphLinkState {
+    Error(String),
+    Unlinked,
+    Init,
+    Negotiating,
+    Allocating,
+    Paused,
+    Active,
+}
+
+impl From<LinkState<'_>> for GraphLinkState {
+    fn from(state: LinkState<'_>) -> Self {
+        match state {
+            LinkState::Error(error) => GraphLinkState::Error(error.to_string()),
+            LinkState::Unlinked => GraphLinkState::Unlinked,
+            LinkState::Init => GraphLinkState::Init,
+            LinkState::Negotiating => GraphLinkState::Negotiating,
+            LinkState::Allocating => GraphLinkState::Allocating,
+            LinkState::Paused => GraphLinkState::Paused,
+            LinkState::Active => GraphLinkState::Active,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphNode {
+    pub id: u32,
+    pub state: GraphNodeState,
+    pub n_input_ports: u32,
+    pub n_output_ports: u32,
+    pub props: Props,
+}
+
+impl GraphNode {
+    /// The `node.name` property.
+    pub fn name(&self) -> Option<&str> {
+        self.props.get(*keys::NODE_NAME).map(String::as_str)
+    }
+
+    /// The `media.class` property, e.g. `Audio/Sink` or `Stream/Output/Audio`.
+    pub fn media_class(&self) -> Option<&str> {
+        self.props.get(*keys::MEDIA_CLASS).map(String::as_str)
+    }
+
+    /// The `application.name` property.
+    pub fn app_name(&self) -> Option<&str> {
+        self.props.get(*keys::APP_NAME).map(String::as_str)
+    }
+
+    /// The `application.process.binary` property.
+    pub fn process_binary(&self) -> Option<&str> {
+        self.props.get(*keys::APP_PROCESS_BINARY).map(String::as_str)
+    }
+
+    /// The `application.process.id` property.
+    pub fn process_id(&self) -> Option<u32> {
+        parse_id(&self.props, *keys::APP_PROCESS_ID)
+    }
+
+    /// The device this node belongs to.
+    pub fn device_id(&self) -> Option<u32> {
+        parse_id(&self.props, *keys::DEVICE_ID)
+    }
+
+    /// The client that created this node.
+    pub fn client_id(&self) -> Option<u32> {
+        parse_id(&self.props, *keys::CLIENT_ID)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphPort {
+    pub id: u32,
+    pub direction: Direction,
+    pub props: Props,
+}
+
+impl GraphPort {
+    /// The node owning this port.
+    pub fn node_id(&self) -> Option<u32> {
+        parse_id(&self.props, *keys::NODE_ID)
+    }
+
+    /// The `port.name` property.
+    pub fn name(&self) -> Option<&str> {
+        self.props.get(*keys::PORT_NAME).map(String::as_str)
+    }
+
+    /// The `audio.channel` property, e.g. `FL`.
+    pub fn channel(&self) -> Option<&str> {
+        self.props.get(*keys::AUDIO_CHANNEL).map(String::as_str)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphLink {
+    pub id: u32,
+    pub output_node: u32,
+    pub output_port: u32,
+    pub input_node: u32,
+    pub input_port: u32,
+    pub state: GraphLinkState,
+    pub props: Props,
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphDevice {
+    pub id: u32,
+    pub props: Props,
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphClient {
+    pub id: u32,
+    pub props: Props,
+}
+
+/// Any object stored in the [`Graph`].
+#[derive(Debug, Clone)]
+pub enum GraphObject {
+    Node(GraphNode),
+    Port(GraphPort),
+    Link(GraphLink),
+    Device(GraphDevice),
+    Client(GraphClient),
+}
+
+impl GraphObject {
+    pub fn id(&self) -> u32 {
+        match self {
+            GraphObject::Node(node) => node.id,
+            GraphObject::Port(port) => port.id,
+            GraphObject::Link(link) => link.id,
+            GraphObject::Device(device) => device.id,
+            GraphObject::Client(client) => client.id,
+        }
+    }
+
+    pub fn kind(&self) -> ObjectKind {
+        match self {
+            GraphObject::Node(_) => ObjectKind::Node,
+            GraphObject::Port(_) => ObjectKind::Port,
+            GraphObject::Link(_) => ObjectKind::Link,
+            GraphObject::Device(_) => ObjectKind::Device,
+            GraphObject::Client(_) => ObjectKind::Client,
+        }
+    }
+}
+
+/// A change to the [`Graph`].
+#[derive(Debug, Clone)]
+pub enum GraphEvent {
+    /// An object appeared in the graph.
+    Added(GraphObject),
+    /// The info of an object already in the graph changed.
+    Changed(GraphObject),
+    /// An object was removed from the graph.
+    Removed { kind: ObjectKind, id: u32 },
+}
+
+/// The nodes, ports, links, devices and clients currently known to the manager.
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    nodes: HashMap<u32, GraphNode>,
+    ports: HashMap<u32, GraphPort>,
+    links: HashMap<u32, GraphLink>,
+    devices: HashMap<u32, GraphDevice>,
+    clients: HashMap<u32, GraphClient>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert or update an object, returning the matching [`GraphEvent`].
+    pub fn update(&mut self, object: GraphObject) -> GraphEvent {
+        let existed = match object.clone() {
+            GraphObject::Node(node) => self.nodes.insert(node.id, node).is_some(),
+            GraphObject::Port(port) => self.ports.insert(port.id, port).is_some(),
+            GraphObject::Link(link) => self.links.insert(link.id, link).is_some(),
+            GraphObject::Device(device) => self.devices.insert(device.id, device).is_some(),
+            GraphObject::Client(client) => self.clients.insert(client.id, client).is_some(),
+        };
+
+        if existed {
+            GraphEvent::Changed(object)
+        } else {
+            GraphEvent::Added(object)
+        }
+    }
+
+    /// Remove the object with the global `id`.
+    ///
+    /// Returns `None` if no object with this id is known.
+    pub fn remove(&mut self, id: u32) -> Option<GraphEvent> {
+        let kind = if self.nodes.remove(&id).is_some() {
+            ObjectKind::Node
+        } else if self.ports.remove(&id).is_some() {
+            ObjectKind::Port
+        } else if self.links.remove(&id).is_some() {
+            ObjectKind::Link
+        } else if self.devices.remove(&id).is_some() {
+            ObjectKind::Device
+        } else if self.clients.remove(&id).is_some() {
+            ObjectKind::Client
+        } else {
+            return None;
+        };
+
+        Some(GraphEvent::Removed { kind, id })
+    }
+
+    /// Forget every object, e.g. after the connection to PipeWire was lost.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.ports.clear();
+        self.links.clear();
+        self.devices.clear();
+        self.clients.clear();
+    }
+
+    pub fn node(&self, id: u32) -> Option<&GraphNode> {
+        self.nodes.get(&id)
+    }
+
+    pub fn port(&self, id: u32) -> Option<&GraphPort> {
+        self.ports.get(&id)
+    }
+
+    pub fn link(&self, id: u32) -> Option<&GraphLink> {
+        self.links.get(&id)
+    }
+
+    pub fn device(&self, id: u32) -> Option<&GraphDevice> {
+        self.devices.get(&id)
+    }
+
+    pub fn client(&self, id: u32) -> Option<&GraphClient> {
+        self.clients.get(&id)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &GraphNode> {
+        self.nodes.values()
+    }
+
+    pub fn ports(&self) -> impl Iterator<Item = &GraphPort> {
+        self.ports.values()
+    }
+
+    pub fn links(&self) -> impl Iterator<Item = &GraphLink> {
+        self.links.values()
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = &GraphDevice> {
+        self.devices.values()
+    }
+
+    pub fn clients(&self) -> impl Iterator<Item = &GraphClient> {
+        self.clients.values()
+    }
+
+    /// All ports of the node `node_id`.
+    pub fn ports_of(&self, node_id: u32) -> impl Iterator<Item = &GraphPort> {
+        self.ports
+            .values()
+            .filter(move |port| port.node_id() == Some(node_id))
+    }
+
+    /// The ports of the node `node_id` in the given `direction`.
+    pub fn ports_of_direction(
+        &self,
+        node_id: u32,
+        direction: Direction,
+    ) -> impl Iterator<Item = &GraphPort> {
+        self.ports_of(node_id)
+            .filter(move |port| port.direction == direction)
+    }
+
+    /// The output ports of the node `node_id`.
+    pub fn output_ports(&self, node_id: u32) -> impl Iterator<Item = &GraphPort> {
+        self.ports_of_direction(node_id, Direction::Output)
+    }
+
+    /// The input ports of the node `node_id`.
+    pub fn input_ports(&self, node_id: u32) -> impl Iterator<Item = &GraphPort> {
+        self.ports_of_direction(node_id, Direction::Input)
+    }
+
+    /// All links from or to the node `node_id`.
+    pub fn links_of(&self, node_id: u32) -> impl Iterator<Item = &GraphLink> {
+        self.links
+            .values()
+            .filter(move |link| link.output_node == node_id || link.input_node == node_id)
+    }
+
+    /// The ids of all nodes linked to the node `node_id`, in either direction.
+    pub fn linked_nodes(&self, node_id: u32) -> Vec<u32> {
+        let mut nodes: Vec<u32> = self
+            .links_of(node_id)
+            .map(|link| {
+                if link.output_node == node_id {
+                    link.input_node
+                } else {
+                    link.output_node
+                }
+            })
+            .collect();
+        nodes.sort_unstable();
+        nodes.dedup();
+        nodes
+    }
+
+    /// The nodes belonging to the device `device_id`.
+    pub fn nodes_of_device(&self, device_id: u32) -> impl Iterator<Item = &GraphNode> {
+        self.nodes
+            .values()
+            .filter(move |node| node.device_id() == Some(device_id))
+    }
+
+    /// The nodes created by the client `client_id`.
+    pub fn nodes_of_client(&self, client_id: u32) -> impl Iterator<Item = &GraphNode> {
+        self.nodes
+            .values()
+            .filter(move |node| node.client_id() == Some(client_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn props(entries: &[(&str, &str)]) -> Props {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn port(id: u32, node_id: u32, direction: Direction) -> GraphObject {
+        GraphObject::Port(GraphPort {
+            id,
+            direction,
+            props: props(&[("node.id", &node_id.to_string())]),
+        })
+    }
+
+    fn link(id: u32, output_node: u32, input_node: u32) -> GraphObject {
+        GraphObject::Link(GraphLink {
+            id,
+            output_node,
+            output_port: 0,
+            input_node,
+            input_port: 0,
+            state: GraphLinkState::Active,
+            props: Props::new(),
+        })
+    }
+
+    #[test]
+    fn update_and_remove() {
+        let mut graph = Graph::new();
+
+        assert!(matches!(
+            graph.update(port(10, 1, Direction::Output)),
+            GraphEvent::Added(_)
+        ));
+        assert!(matches!(
+            graph.update(port(10, 1, Direction::Output)),
+            GraphEvent::Changed(_)
+        ));
+        assert!(matches!(
+            graph.remove(10),
+            Some(GraphEvent::Removed {
+                kind: ObjectKind::Port,
+                id: 10
+            })
+        ));
+        assert!(graph.remove(10).is_none());
+    }
+
+    #[test]
+    fn queries() {
+        let mut graph = Graph::new();
+        graph.update(port(10, 1, Direction::Output));
+        graph.update(port(11, 1, Direction::Output));
+        graph.update(port(12, 1, Direction::Input));
+        graph.update(port(20, 2, Direction::Input));
+        graph.update(link(30, 1, 2));
+        graph.update(link(31, 3, 1));
+
+        let mut outputs: Vec<u32> = graph.output_ports(1).map(|port| port.id).collect();
+        outputs.sort_unstable();
+        assert_eq!(outputs, vec![10, 11]);
+        assert_eq!(graph.input_ports(1).count(), 1);
+        assert_eq!(graph.linked_nodes(1), vec![2, 3]);
+        assert_eq!(graph.linked_nodes(2), vec![1]);
+    }
+}
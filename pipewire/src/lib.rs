@@ -116,6 +116,7 @@ pub mod context;
 pub mod core;
 pub mod device;
 pub mod factory;
+pub mod filter;
 pub mod keys;
 pub mod link;
 pub mod loop_;
@@ -128,16 +129,27 @@ pub mod port;
 pub mod properties;
 pub mod proxy;
 pub mod registry;
+pub mod rt_queue;
 pub mod stream;
 pub mod thread_loop;
 pub mod types;
 pub mod pipe_wire_manager; // Created by Viridian-Inc
 pub mod pipe_wire; // Created by Viridian-Inc
+pub mod app_selector; // Created by Viridian-Inc
+pub mod e_stream; // Created by Viridian-Inc
+pub mod graph; // Created by Viridian-Inc
+pub mod ring_buffer; // Created by Viridian-Inc
+pub mod async_stream; // Created by Viridian-Inc
+pub mod recorder; // Created by Viridian-Inc
+pub mod convert; // Created by Viridian-Inc
+pub mod meter; // Created by Viridian-Inc
+pub mod virtual_device; // Created by Viridian-Inc
+pub mod node_links; // Created by Viridian-Inc
+pub mod default_metadata; // Created by Viridian-Inc
 
 mod error;
 pub use error::*;
 mod utils;
-mod e_stream;
 mod e_listener;
 
 
@@ -3,10 +3,12 @@
 
 use std::{
     convert::TryInto,
+    marker::PhantomData,
     ops::Deref,
     os::unix::prelude::*,
     ptr::{self, NonNull},
     rc::{Rc, Weak},
+    sync::{Arc, Mutex},
     time::Duration,
 };
 
@@ -137,6 +139,14 @@ impl LoopRef {
         )
     }
 
+    /// Get a handle that runs closures on the thread of the loop, from any thread.
+    pub fn invoker(&self) -> Invoker<'_> {
+        Invoker {
+            ptr: NonNull::new(self.as_ptr()).expect("Pointer should be nonnull"),
+            _loop: PhantomData,
+        }
+    }
+
     /// Register some type of IO object with a callback that is called when reading/writing on the IO object
     /// is available.
     ///
@@ -380,6 +390,99 @@ impl LoopRef {
     }
 }
 
+/// Runs closures on the thread of a loop, see [`LoopRef::invoker`].
+///
+/// Unlike the loop itself, an invoker can be sent to and shared with other threads. It borrows
+/// the loop, so those threads have to be [scoped](std::thread::scope) to the lifetime of it.
+#[derive(Debug, Clone, Copy)]
+pub struct Invoker<'l> {
+    ptr: NonNull<pw_sys::pw_loop>,
+    _loop: PhantomData<&'l LoopRef>,
+}
+
+// SAFETY: invoking is the one loop method that is safe to call from any thread, and the
+// callbacks only get the loop on its own thread.
+unsafe impl Send for Invoker<'_> {}
+unsafe impl Sync for Invoker<'_> {}
+
+impl Invoker<'_> {
+    /// Run `callback` on the thread of the loop, which passes itself to it.
+    ///
+    /// When called from the thread of the loop, `callback` runs right away. Otherwise it is queued
+    /// and runs the next time the loop iterates, and with `block` this waits until it did.
+    ///
+    /// Returns the result of `callback` if it ran before this returns, that is when blocking or
+    /// when called from the thread of the loop, and `None` if it is still queued.
+    ///
+    /// Blocking on a loop that does not run, or whose [`ThreadLoop`](crate::thread_loop::ThreadLoop)
+    /// lock the caller holds, never returns.
+    pub fn invoke<F, T>(&self, callback: F, block: bool) -> Result<Option<T>, Error>
+    where
+        F: FnOnce(&LoopRef) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        struct Invocation<F, T> {
+            loop_: NonNull<pw_sys::pw_loop>,
+            callback: Mutex<Option<F>>,
+            result: Mutex<Option<T>>,
+        }
+
+        unsafe extern "C" fn call_closure<F, T>(
+            _loop: *mut spa_sys::spa_loop,
+            _async: bool,
+            _seq: u32,
+            _data: *const c_void,
+            _size: usize,
+            user_data: *mut c_void,
+        ) -> c_int
+        where
+            F: FnOnce(&LoopRef) -> T,
+        {
+            // The loop owns one reference, released once the callback ran.
+            let invocation = Arc::from_raw(user_data as *const Invocation<F, T>);
+            let callback = invocation.callback.lock().unwrap().take();
+            if let Some(callback) = callback {
+                // This runs on the thread of the loop, where using it is fine.
+                let loop_ = &*(invocation.loop_.as_ptr() as *const LoopRef);
+                *invocation.result.lock().unwrap() = Some(callback(loop_));
+            }
+            0
+        }
+
+        let invocation = Arc::new(Invocation {
+            loop_: self.ptr,
+            callback: Mutex::new(Some(callback)),
+            result: Mutex::new(None),
+        });
+        let user_data = Arc::into_raw(Arc::clone(&invocation));
+
+        let res = unsafe {
+            let mut iface = self.ptr.as_ref().loop_.as_ref().unwrap().iface;
+
+            spa_interface_call_method!(
+                &mut iface as *mut spa_sys::spa_interface,
+                spa_sys::spa_loop_methods,
+                invoke,
+                Some(call_closure::<F, T>),
+                0,
+                ptr::null(),
+                0,
+                block,
+                user_data as *mut c_void
+            )
+        };
+
+        if let Err(error) = SpaResult::from_c(res).into_result() {
+            // The callback was not queued, take back the reference of the loop.
+            unsafe { drop(Arc::from_raw(user_data)) };
+            return Err(error.into());
+        }
+
+        let result = invocation.result.lock().unwrap().take();
+        Ok(result)
+    }
+}
+
 pub fn loop_from_ptr(ptr: pw_sys::pw_loop) -> LoopRef {
     LoopRef(ptr)
 }
@@ -0,0 +1,362 @@
+//! Level metering and silence detection.
+//!
+//! A [`Meter`] measures blocks of [`Frames`] and reports [`MeterEvent`]s: the levels of every
+//! channel once per interval, and transitions between silence and activity. Streams whose
+//! [`EStreamConfig::meter`](crate::e_stream::EStreamConfig::meter) is set run a meter on their
+//! realtime thread and report its events as
+//! [`ManagerEvent::Meter`](crate::pipe_wire_manager::ManagerEvent::Meter).
+//!
+//! Measuring and reporting never allocates once the format is known, so a meter can run in
+//! the realtime `process` callback.
+
+use std::time::Duration;
+
+use spa::param::audio::{AudioInfoRaw, AudioInfoRawFlags, MAX_CHANNELS};
+use spa::sys;
+
+use crate::convert::decoder;
+use crate::e_stream::Frames;
+
+/// How a [`Meter`] measures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeterConfig {
+    /// How often the levels are reported.
+    pub interval: Duration,
+    /// Level in dBFS below which the audio counts as silent.
+    pub silence_threshold: f32,
+    /// Level in dBFS above which silent audio counts as active again.
+    ///
+    /// Keeping this above `silence_threshold` stops noise around the threshold from toggling.
+    pub activity_threshold: f32,
+    /// How long the audio has to stay below `silence_threshold` before it counts as silent.
+    pub silence_hold: Duration,
+}
+
+impl Default for MeterConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(100),
+            silence_threshold: -60.0,
+            activity_threshold: -50.0,
+            silence_hold: Duration::from_millis(500),
+        }
+    }
+}
+
+/// The levels measured over one interval.
+///
+/// The levels are stored inline, so that the realtime thread can report them without
+/// allocating.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Levels {
+    channels: usize,
+    peak: [f32; MAX_CHANNELS],
+    rms: [f32; MAX_CHANNELS],
+    /// The K-weighted loudness of all channels, in LUFS.
+    ///
+    /// Computed like the momentary loudness of ITU-R BS.1770, but over the meter interval
+    /// instead of a sliding 400 ms window.
+    pub loudness: f32,
+}
+
+impl Levels {
+    /// The peak of every channel in dBFS.
+    pub fn peak(&self) -> &[f32] {
+        &self.peak[..self.channels]
+    }
+
+    /// The RMS of every channel in dBFS.
+    pub fn rms(&self) -> &[f32] {
+        &self.rms[..self.channels]
+    }
+}
+
+/// Reported by a [`Meter`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeterEvent {
+    /// The levels of the last interval.
+    Levels(Levels),
+    /// The audio stayed below the silence threshold for the hold time.
+    Silence,
+    /// The audio rose above the activity threshold after being silent.
+    Activity,
+}
+
+/// Measures levels and detects silence, see the [module documentation](self).
+///
+/// A meter starts out silent, so the first event after audio starts is
+/// [`MeterEvent::Activity`].
+pub struct Meter {
+    config: MeterConfig,
+    format: Option<Format>,
+    channels: Vec<Channel>,
+    /// Frames measured in the current interval.
+    frames: u64,
+    silent: bool,
+    /// How long the audio has been below the silence threshold.
+    quiet_for: Duration,
+}
+
+struct Format {
+    decode: fn(&[u8]) -> f32,
+    sample_size: usize,
+    rate: u32,
+    interval: u64,
+}
+
+/// The measurements of one channel.
+struct Channel {
+    /// Gain of the channel in the loudness sum.
+    weight: f64,
+    filter: [Biquad; 2],
+    peak: f32,
+    sum: f64,
+    weighted_sum: f64,
+}
+
+impl Meter {
+    pub fn new(config: MeterConfig) -> Self {
+        Self {
+            config,
+            format: None,
+            channels: Vec::new(),
+            frames: 0,
+            silent: true,
+            quiet_for: Duration::ZERO,
+        }
+    }
+
+    /// Measure frames in the format `info` from now on.
+    ///
+    /// The measurements of the current interval are dropped.
+    pub fn format_changed(&mut self, info: &AudioInfoRaw) {
+        let channels = (info.channels() as usize).min(MAX_CHANNELS);
+        self.format = decoder(info.format())
+            .filter(|_| channels > 0 && info.rate() > 0)
+            .map(|(sample_size, decode)| Format {
+                decode,
+                sample_size,
+                rate: info.rate(),
+                interval: ((self.config.interval.as_secs_f64() * info.rate() as f64) as u64).max(1),
+            });
+
+        let positions = info.position();
+        let positioned = !info.flags().contains(AudioInfoRawFlags::UNPOSITIONED);
+        self.channels = (0..channels)
+            .map(|channel| Channel::new(info.rate(), positioned.then(|| positions[channel])))
+            .collect();
+        self.frames = 0;
+    }
+
+    /// Measure `frames`, passing every resulting event to `emit`.
+    ///
+    /// Frames of a planar format have to be passed one plane after the other.
+    pub fn process(&mut self, frames: &Frames, mut emit: impl FnMut(MeterEvent)) {
+        let Some(format) = &self.format else {
+            return;
+        };
+        let samples = frames.data().chunks_exact(format.sample_size).map(format.decode);
+
+        let n_frames = match frames.plane() {
+            Some(plane) => {
+                let Some(channel) = self.channels.get_mut(plane) else {
+                    return;
+                };
+                let mut n_frames = 0;
+                for sample in samples {
+                    channel.measure(sample);
+                    n_frames += 1;
+                }
+                // Count the frames once, with the last plane.
+                if plane + 1 < self.channels.len() {
+                    return;
+                }
+                n_frames
+            }
+            None => {
+                let n_channels = self.channels.len();
+                let mut n_samples = 0;
+                for (index, sample) in samples.enumerate() {
+                    self.channels[index % n_channels].measure(sample);
+                    n_samples += 1;
+                }
+                n_samples / n_channels as u64
+            }
+        };
+
+        self.frames += n_frames;
+        if self.frames >= format.interval {
+            let elapsed = Duration::from_secs_f64(self.frames as f64 / format.rate as f64);
+            self.report(elapsed, &mut emit);
+        }
+    }
+
+    /// Emit the levels of the finished interval and start the next one.
+    fn report(&mut self, elapsed: Duration, emit: &mut impl FnMut(MeterEvent)) {
+        let frames = self.frames as f64;
+        let mut levels = Levels {
+            channels: self.channels.len(),
+            peak: [f32::NEG_INFINITY; MAX_CHANNELS],
+            rms: [f32::NEG_INFINITY; MAX_CHANNELS],
+            loudness: 0.0,
+        };
+        let mut loudness = 0.0;
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            levels.peak[index] = decibels(channel.peak as f64);
+            levels.rms[index] = decibels((channel.sum / frames).sqrt());
+            loudness += channel.weight * channel.weighted_sum / frames;
+            channel.reset();
+        }
+        levels.loudness = (-0.691 + 10.0 * loudness.log10()) as f32;
+        self.frames = 0;
+
+        let level = levels.rms().iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        emit(MeterEvent::Levels(levels));
+
+        if self.silent {
+            if level > self.config.activity_threshold {
+                self.silent = false;
+                self.quiet_for = Duration::ZERO;
+                emit(MeterEvent::Activity);
+            }
+        } else if level < self.config.silence_threshold {
+            self.quiet_for += elapsed;
+            if self.quiet_for >= self.config.silence_hold {
+                self.silent = true;
+                emit(MeterEvent::Silence);
+            }
+        } else {
+            self.quiet_for = Duration::ZERO;
+        }
+    }
+}
+
+impl Channel {
+    fn new(rate: u32, position: Option<u32>) -> Self {
+        let weight = match position {
+            Some(sys::SPA_AUDIO_CHANNEL_LFE | sys::SPA_AUDIO_CHANNEL_LFE2) => 0.0,
+            Some(
+                sys::SPA_AUDIO_CHANNEL_SL
+                | sys::SPA_AUDIO_CHANNEL_SR
+                | sys::SPA_AUDIO_CHANNEL_RL
+                | sys::SPA_AUDIO_CHANNEL_RR,
+            ) => 1.41,
+            _ => 1.0,
+        };
+        Self {
+            weight,
+            filter: k_weighting(rate as f64),
+            peak: 0.0,
+            sum: 0.0,
+            weighted_sum: 0.0,
+        }
+    }
+
+    fn measure(&mut self, sample: f32) {
+        self.peak = self.peak.max(sample.abs());
+        let sample = sample as f64;
+        self.sum += sample * sample;
+        let shelved = self.filter[0].process(sample);
+        let weighted = self.filter[1].process(shelved);
+        self.weighted_sum += weighted * weighted;
+    }
+
+    fn reset(&mut self) {
+        self.peak = 0.0;
+        self.sum = 0.0;
+        self.weighted_sum = 0.0;
+    }
+}
+
+/// `value` relative to full scale in dB, negative infinity for 0.
+fn decibels(value: f64) -> f32 {
+    (20.0 * value.log10()) as f32
+}
+
+/// A second order IIR filter in transposed direct form II.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The K-weighting filter of ITU-R BS.1770 for the sample rate `rate`: a high shelf modelling
+/// the head, followed by a high pass.
+fn k_weighting(rate: f64) -> [Biquad; 2] {
+    use std::f64::consts::PI;
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loudness_of_sine() {
+        // A full scale 997 Hz sine on one channel is -3.01 LUFS.
+        let rate = 48000.0;
+        let mut channel = Channel::new(48000, None);
+        let n = 48000;
+        for i in 0..n {
+            channel.measure((2.0 * std::f64::consts::PI * 997.0 * i as f64 / rate).sin() as f32);
+        }
+        let loudness = -0.691 + 10.0 * (channel.weighted_sum / n as f64).log10();
+        assert!((loudness + 3.01).abs() < 0.05, "{}", loudness);
+        assert!((decibels((channel.sum / n as f64).sqrt()) + 3.01).abs() < 0.01);
+        assert!(decibels(channel.peak as f64) > -0.01);
+    }
+
+    #[test]
+    fn silence_hysteresis() {
+        let mut meter = Meter::new(MeterConfig {
+            silence_hold: Duration::from_millis(200),
+            ..Default::default()
+        });
+        meter.channels = vec![Channel::new(48000, None)];
+        let interval = Duration::from_millis(100);
+        let run = |meter: &mut Meter, level: f32| {
+            let mut events = Vec::new();
+            meter.channels[0].measure(level);
+            meter.frames = 1;
+            meter.report(interval, &mut |event| events.push(event));
+            events.into_iter().filter(|event| !matches!(event, MeterEvent::Levels(_))).collect::<Vec<_>>()
+        };
+
+        // -55 dBFS is between the thresholds, it neither starts nor ends activity.
+        assert_eq!(run(&mut meter, 0.0018), []);
+        assert_eq!(run(&mut meter, 0.5), [MeterEvent::Activity]);
+        assert_eq!(run(&mut meter, 0.0018), []);
+        assert_eq!(run(&mut meter, 0.0), []);
+        assert_eq!(run(&mut meter, 0.0), [MeterEvent::Silence]);
+    }
+}
@@ -0,0 +1,233 @@
+//! Linking the ports of two nodes.
+//!
+//! [`pair_ports`] decides which output port of one node feeds which input port of another:
+//! ports carrying the same `audio.channel` are paired, `FL` to `FL` and `FR` to `FR`, and ports
+//! without a channel by the last part of their `port.name`, so `capture_1` feeds `playback_1`.
+//! When nothing matches and one node has a single port, it is linked to every port of the
+//! other. [`NodeLinks`] then creates a link for every pair through the `link-factory`.
+//!
+//! The [`PipeWireManager`](crate::pipe_wire_manager::PipeWireManager) finds the ports in the
+//! registry and waits for the links to become active, see
+//! [`ManagerHandle::link_nodes`](crate::pipe_wire_manager::ManagerHandle::link_nodes).
+
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::core::Core;
+use crate::graph::GraphPort;
+use crate::link::{Link, LinkListener, LinkState};
+use crate::properties::properties;
+use crate::{keys, EasyWireError};
+
+/// The factory links are created with.
+pub const FACTORY: &str = "link-factory";
+
+/// Identifies a set of links hosted by the
+/// [`PipeWireManager`](crate::pipe_wire_manager::PipeWireManager).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeLinksId(pub(crate) u32);
+
+impl NodeLinksId {
+    pub fn as_raw(&self) -> u32 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for NodeLinksId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// How two nodes are linked.
+#[derive(Debug, Clone)]
+pub struct LinkPolicy {
+    /// Link the port of a node with a single port to every port of the other node, when no
+    /// channels match.
+    ///
+    /// Without this, a mono port is only linked to a port of the same channel.
+    pub fan_out_mono: bool,
+    /// Keep the links on the server when the connection is lost, `object.linger`.
+    ///
+    /// Links are always removed when they are torn down explicitly.
+    pub linger: bool,
+    /// Do not keep the nodes running just because they are linked, `link.passive`.
+    pub passive: bool,
+    /// How long to wait for every link to become active.
+    ///
+    /// Links between nodes that are not running stay paused, linking them fails after this.
+    pub timeout: Duration,
+}
+
+impl Default for LinkPolicy {
+    fn default() -> Self {
+        Self {
+            fan_out_mono: true,
+            linger: false,
+            passive: false,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// What a port is paired by: its `audio.channel`, or the last part of its `port.name`.
+fn port_key(port: &GraphPort) -> Option<&str> {
+    port.channel()
+        .or_else(|| port.name().map(|name| name.rsplit('_').next().unwrap_or(name)))
+}
+
+/// Whether `ports` carry a single channel.
+fn is_mono(ports: &[&GraphPort]) -> bool {
+    ports.len() == 1
+}
+
+/// Pair the output ports `outputs` with the input ports `inputs` according to `policy`.
+///
+/// Returns the `(output, input)` port ids to link, ordered by output port. Empty if no port
+/// fits another.
+pub fn pair_ports(outputs: &[&GraphPort], inputs: &[&GraphPort], policy: &LinkPolicy) -> Vec<(u32, u32)> {
+    let mut pairs: Vec<(u32, u32)> = outputs
+        .iter()
+        .flat_map(|output| {
+            let key = port_key(output);
+            inputs
+                .iter()
+                .filter(move |input| key.is_some() && port_key(input) == key)
+                .map(move |input| (output.id, input.id))
+        })
+        .collect();
+
+    if pairs.is_empty() && policy.fan_out_mono && (is_mono(outputs) || is_mono(inputs)) {
+        pairs = outputs
+            .iter()
+            .flat_map(|output| inputs.iter().map(move |input| (output.id, input.id)))
+            .collect();
+    }
+    pairs.sort_unstable();
+    pairs.dedup();
+    pairs
+}
+
+/// A set of links from the ports of one node to the ports of another.
+///
+/// Dropping this destroys the proxies, which removes the links from the server unless they were
+/// created with [`LinkPolicy::linger`]. Use [`destroy()`](Self::destroy) to always remove them.
+pub struct NodeLinks {
+    links: Vec<NodeLink>,
+}
+
+struct NodeLink {
+    // Declared first so the listener is removed before the proxy is destroyed.
+    _listener: LinkListener,
+    link: Link,
+}
+
+impl NodeLinks {
+    /// Link the node `output_node` to the node `input_node`, one link for every port pair in
+    /// `pairs`.
+    ///
+    /// `state` is called with the index of the link in `pairs` whenever the state of a link
+    /// changes.
+    pub fn create<F>(
+        core: &Core,
+        output_node: u32,
+        input_node: u32,
+        pairs: &[(u32, u32)],
+        policy: &LinkPolicy,
+        state: F,
+    ) -> Result<Self, EasyWireError>
+    where
+        F: Fn(usize, LinkState) + 'static,
+    {
+        let state = Rc::new(state);
+        let mut links = Vec::with_capacity(pairs.len());
+        for (index, (output_port, input_port)) in pairs.iter().enumerate() {
+            let link: Link = core.create_object(
+                FACTORY,
+                &properties! {
+                    *keys::LINK_OUTPUT_NODE => output_node.to_string(),
+                    *keys::LINK_OUTPUT_PORT => output_port.to_string(),
+                    *keys::LINK_INPUT_NODE => input_node.to_string(),
+                    *keys::LINK_INPUT_PORT => input_port.to_string(),
+                    *keys::LINK_PASSIVE => policy.passive.to_string(),
+                    *keys::OBJECT_LINGER => policy.linger.to_string(),
+                },
+            )?;
+            let listener = link
+                .add_listener_local()
+                .info({
+                    let state = Rc::clone(&state);
+                    move |info| state(index, info.state())
+                })
+                .register();
+            links.push(NodeLink {
+                _listener: listener,
+                link,
+            });
+        }
+        Ok(Self { links })
+    }
+
+    /// The number of links.
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Remove every link from the server, even lingering ones.
+    pub fn destroy(self, core: &Core) -> Result<(), EasyWireError> {
+        let mut result = Ok(());
+        for NodeLink { _listener: listener, link } in self.links {
+            drop(listener);
+            // Keep going, the other links still have to be removed.
+            if let Err(error) = core.destroy_object(link) {
+                if result.is_ok() {
+                    result = Err(error.into());
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spa::utils::Direction;
+
+    fn port(id: u32, direction: Direction, name: &str, channel: Option<&str>) -> GraphPort {
+        let mut props = crate::graph::Props::new();
+        props.insert("port.name".to_string(), name.to_string());
+        if let Some(channel) = channel {
+            props.insert("audio.channel".to_string(), channel.to_string());
+        }
+        GraphPort { id, direction, props }
+    }
+
+    #[test]
+    fn pairs_ports() {
+        let policy = LinkPolicy::default();
+        let fl = port(1, Direction::Output, "output_FL", Some("FL"));
+        let fr = port(2, Direction::Output, "output_FR", Some("FR"));
+        let in_fr = port(11, Direction::Input, "playback_FR", Some("FR"));
+        let in_fl = port(10, Direction::Input, "playback_FL", Some("FL"));
+        assert_eq!(pair_ports(&[&fl, &fr], &[&in_fr, &in_fl], &policy), [(1, 10), (2, 11)]);
+
+        // Channels are matched by name without `audio.channel`.
+        let capture = port(3, Direction::Output, "capture_1", None);
+        let playback = port(12, Direction::Input, "playback_1", None);
+        assert_eq!(pair_ports(&[&capture], &[&playback], &policy), [(3, 12)]);
+
+        // A mono output feeds both channels, unless fanning out is off.
+        let mono = port(4, Direction::Output, "output_MONO", Some("MONO"));
+        assert_eq!(pair_ports(&[&mono], &[&in_fl, &in_fr], &policy), [(4, 10), (4, 11)]);
+        let policy = LinkPolicy {
+            fan_out_mono: false,
+            ..LinkPolicy::default()
+        };
+        assert_eq!(pair_ports(&[&mono], &[&in_fl, &in_fr], &policy), []);
+    }
+}
@@ -1,17 +1,34 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::{mem, thread};
+use std::cell::Cell;
+use std::time::Duration;
 use std::ops::Deref;
 use std::rc::{Rc, Weak};
 use std::sync::{Arc, mpsc, Mutex};
-use nix::sys::signal::Signal;
-use spa::utils::dict::DictRef;
-use crate::{context, keys, main_loop, registry, stream};
-use crate::e_stream::{EStream, StreamCore, StreamCoreData};
+use crate::{channel, context, keys, main_loop, registry, EasyWireError};
+use crate::client::Client;
+use crate::device::Device;
+use crate::e_stream::{EStream, EStreamConfig, StreamCore, StreamCoreData, StreamId, StreamIo, Userdata};
+use crate::stream::StreamListener;
+use crate::default_metadata::{DefaultChange, DefaultKey, DefaultMetadata};
+use crate::meter::MeterEvent;
+use crate::pipe_wire_manager::ReconnectPolicy;
+use crate::graph::{props_from_dict, GraphClient, GraphDevice, GraphLink, GraphNode, GraphObject, GraphPort};
+use crate::link::{Link, LinkState};
+use crate::node_links::{self, LinkPolicy, NodeLinks, NodeLinksId};
+use crate::node::Node;
 use crate::port::Port;
 use crate::properties::properties;
 use crate::proxy::{Listener, ProxyListener, ProxyT};
 use crate::types::ObjectType;
-use crate::registry::Registry;
+use crate::registry::{GlobalObject, Registry};
+use crate::virtual_device::{self, VirtualDevice, VirtualDeviceConfig, VirtualDeviceId};
+use spa::param::audio::AudioInfoRaw;
+use spa::utils::dict::DictRef;
+use spa::utils::Direction;
+
+/// How often the events of the realtime process callbacks are sent to the manager.
+const FORWARD_INTERVAL: Duration = Duration::from_millis(20);
 
 struct Proxies {
     proxies_t: HashMap<u32, Box<dyn ProxyT>>,
@@ -44,146 +61,900 @@ impl Proxies {
         v.push(Box::new(listener));
     }
 
+    fn clear(&mut self) {
+        self.listeners.clear();
+        self.proxies_t.clear();
+    }
+
     fn remove(&mut self, proxy_id: u32) {
         self.proxies_t.remove(&proxy_id);
         self.listeners.remove(&proxy_id);
     }
 }
 
-// TODO: Remove unneeded fields
-#[derive(Debug)]
-pub struct NodeEvent {
-    pub proxy_id: u32,
-    pub node_id: u32,
-    pub port_id: u32,
-    pub channel: String,
-    pub object_path: String,
-    pub port_name: String,
-    pub direction: String,
-    pub format: String,
+pub enum PWEvent {
+    /// An object was bound or its info changed.
+    Object(GraphObject),
+    /// The global with the given id was removed.
+    GlobalRemoved(u32),
+    /// Something failed while the main loop was running.
+    Error(EasyWireError),
+    /// The connection to PipeWire was lost, every object of the graph is gone.
+    Disconnected,
+    /// The connection was established again after `attempts` attempts.
+    Reconnected { attempts: u32 },
+    /// The stream `stream` was moved to the node `target`.
+    Retargeted { stream: StreamId, target: u32 },
+    /// The stream `stream` was created.
+    StreamCreated(StreamId),
+    /// The stream `stream` was destroyed.
+    StreamDestroyed(StreamId),
+    /// The stream `stream` negotiated the format `info`.
+    FormatNegotiated { stream: StreamId, info: AudioInfoRaw },
+    /// The meter of the stream `stream` measured something.
+    Meter { stream: StreamId, event: MeterEvent },
+    /// The stream `stream` had no buffer to process in `count` cycles.
+    Underrun { stream: StreamId, count: u64 },
+    /// A default device or a stream target changed in the `default` metadata.
+    DefaultChanged(DefaultChange),
+    /// The virtual device `device` was created as the node `node`.
+    VirtualDeviceCreated { device: VirtualDeviceId, node: u32 },
+    /// The virtual device `device` was removed.
+    VirtualDeviceDestroyed(VirtualDeviceId),
 }
 
-pub enum PWEvent {
-    Node(NodeEvent),
-    RemoveNode(u32),
+/// Where the PipeWire thread sends the outcome of a request.
+pub enum Reply {
+    /// The requester blocks on a std channel.
+    Blocking(mpsc::Sender<Result<(), EasyWireError>>),
+    /// The requester awaits the outcome.
+    Async(tokio::sync::oneshot::Sender<Result<(), EasyWireError>>),
+}
+
+impl Reply {
+    /// Send `result`, the requester may have given up waiting already.
+    pub fn send(self, result: Result<(), EasyWireError>) {
+        match self {
+            Reply::Blocking(sender) => {
+                let _ = sender.send(result);
+            }
+            Reply::Async(sender) => {
+                let _ = sender.send(result);
+            }
+        }
+    }
 }
 
+/// Messages sent from the manager into the PipeWire thread.
 pub enum IncomingEvent {
-    UpdateObjID(u32),
+    /// Move a stream to the node with this id once the stream can be moved, see
+    /// [`PipeWire::follow`]. The outcome is reported as an event.
+    UpdateObjID(StreamId, u32),
+    /// Move the stream `stream` to the node `target` and send the outcome to `reply`.
+    Retarget {
+        stream: StreamId,
+        target: u32,
+        reply: Reply,
+    },
+    /// Create a stream, now if connected and again after every reconnect.
+    CreateStream {
+        stream: StreamId,
+        config: EStreamConfig,
+        io: StreamIo,
+        reply: Reply,
+    },
+    /// Destroy the stream `stream`.
+    DestroyStream {
+        stream: StreamId,
+        reply: Reply,
+    },
+    /// Create a virtual device, now if connected and again after every reconnect.
+    CreateVirtualDevice {
+        device: VirtualDeviceId,
+        config: VirtualDeviceConfig,
+        reply: Reply,
+    },
+    /// Remove the virtual device `device`.
+    DestroyVirtualDevice {
+        device: VirtualDeviceId,
+        reply: Reply,
+    },
+    /// Make the node named `name` the default `key`, or remove the default.
+    SetDefault {
+        key: DefaultKey,
+        name: Option<String>,
+        reply: Reply,
+    },
+    /// Link the node `output` to the node `input`, the outcome is sent once every link is
+    /// active.
+    LinkNodes {
+        links: NodeLinksId,
+        output: u32,
+        input: u32,
+        policy: LinkPolicy,
+        reply: Reply,
+    },
+    /// Remove the links `links` from the server.
+    UnlinkNodes {
+        links: NodeLinksId,
+        reply: Reply,
+    },
+    /// Quit the main loop and tear down every object owned by the thread.
+    Terminate,
 }
 
 #[derive(Clone)]
-pub struct PipeWire<T> {
+pub struct PipeWire {
     proxies: Arc<Mutex<Proxies>>,
     core: Arc<Mutex<Option<crate::core::Core>>>,
     main_loop: Arc<Mutex<Option<main_loop::MainLoop>>>,
-    stream: Arc<Mutex<Option<stream::Stream>>>,
     sender:  Arc<Mutex<mpsc::Sender<PWEvent>>>,
-    e_stream: Arc<Mutex<Option<EStream<StreamCore<T>>>>>,
+    /// Every stream the manager asked for, recreated on every connection.
+    stream_configs: Arc<Mutex<BTreeMap<StreamId, (EStreamConfig, StreamIo)>>>,
+    /// The streams of the current connection.
+    streams: Arc<Mutex<HashMap<StreamId, RunningStream>>>,
+    /// Every virtual device the manager asked for, recreated on every connection.
+    device_configs: Arc<Mutex<BTreeMap<VirtualDeviceId, VirtualDeviceConfig>>>,
+    /// The virtual devices of the current connection.
+    devices: Arc<Mutex<HashMap<VirtualDeviceId, VirtualDevice>>>,
+    metadata: Arc<Mutex<Option<DefaultMetadata>>>,
+    serials: Arc<Mutex<HashMap<u32, String>>>,
+    /// The `node.name` of every node, by global id.
+    node_names: Arc<Mutex<HashMap<u32, String>>>,
+    /// The node every stream was connected or moved to, by `node.name` or `object.serial`.
+    ///
+    /// Global ids do not survive a reconnect, so these are resolved again on every connection.
+    targets: Arc<Mutex<HashMap<StreamId, String>>>,
+    /// Nodes application captures want their streams moved to, until the stream has a node
+    /// and the `default` metadata is bound.
+    pending_targets: Arc<Mutex<HashMap<StreamId, u32>>>,
+    /// The names of the factories offered by the server, by global id.
+    factories: Arc<Mutex<HashMap<u32, String>>>,
+    /// Every port of the current connection, to pair them when linking nodes.
+    ports: Arc<Mutex<HashMap<u32, GraphPort>>>,
+    /// The links the manager created on the current connection.
+    node_links: Arc<Mutex<HashMap<NodeLinksId, RunningLinks>>>,
 }
 
 
 
-impl <T>PipeWire<T> {
-    pub fn new(sender:  mpsc::Sender<PWEvent>) -> Result<Self, String> {
-        Ok(Self {
+impl PipeWire {
+    pub fn new(sender:  mpsc::Sender<PWEvent>) -> Self {
+        Self {
             proxies: Arc::new(Mutex::new(Proxies::new())),
             core: Arc::new(Mutex::new(None)),
             main_loop: Arc::new(Mutex::new(None)),
-            e_stream: Arc::new(Mutex::new(None)),
-            stream: Arc::new(Mutex::new(None)),
+            stream_configs: Arc::new(Mutex::new(BTreeMap::new())),
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            device_configs: Arc::new(Mutex::new(BTreeMap::new())),
+            devices: Arc::new(Mutex::new(HashMap::new())),
             sender: Arc::new(Mutex::new(sender)),
+            metadata: Arc::new(Mutex::new(None)),
+            serials: Arc::new(Mutex::new(HashMap::new())),
+            node_names: Arc::new(Mutex::new(HashMap::new())),
+            targets: Arc::new(Mutex::new(HashMap::new())),
+            pending_targets: Arc::new(Mutex::new(HashMap::new())),
+            factories: Arc::new(Mutex::new(HashMap::new())),
+            ports: Arc::new(Mutex::new(HashMap::new())),
+            node_links: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Move the stream `stream` to the node `target`.
+    ///
+    /// See [`EStream::retarget`].
+    pub fn retarget(&self, stream: StreamId, target: u32) -> Result<(), EasyWireError> {
+        let streams = self.streams.lock().unwrap();
+        let running = streams.get(&stream).ok_or(EasyWireError::UnknownStream(stream))?;
+        let serial = self.serials.lock().unwrap().get(&target).cloned();
+        let metadata = self.metadata.lock().unwrap();
+        running.stream.retarget(target, serial.as_deref(), metadata.as_ref())?;
+
+        // Follow the stream to its new node after a reconnect.
+        match self.logical_target(target) {
+            Some(logical) => self.targets.lock().unwrap().insert(stream, logical),
+            None => self.targets.lock().unwrap().remove(&stream),
+        };
+        if let Some((config, _)) = self.stream_configs.lock().unwrap().get_mut(&stream) {
+            config.target = Some(target);
+        }
+
+        let _ = self.sender.lock().unwrap().send(PWEvent::Retargeted { stream, target });
+        Ok(())
+    }
+
+    /// Move the stream `stream` to the node `target` as soon as possible.
+    ///
+    /// Right after connecting, the stream has no node yet and the `default` metadata may not
+    /// be bound. The move is kept pending until both are there instead of failing.
+    pub fn follow(&self, stream: StreamId, target: u32) {
+        self.pending_targets.lock().unwrap().insert(stream, target);
+        self.apply_pending_targets();
+    }
+
+    /// Move the streams with a pending target that can be moved now.
+    fn apply_pending_targets(&self) {
+        let ready: Vec<(StreamId, u32)> = {
+            let streams = self.streams.lock().unwrap();
+            let has_metadata = self.metadata.lock().unwrap().is_some();
+            let mut pending = self.pending_targets.lock().unwrap();
+            let ready: Vec<(StreamId, u32)> = pending
+                .iter()
+                .filter(|(stream, _)| {
+                    has_metadata && streams.get(stream).map_or(false, |running| running.stream.has_node())
+                })
+                .map(|(stream, target)| (*stream, *target))
+                .collect();
+            for (stream, _) in &ready {
+                pending.remove(stream);
+            }
+            ready
+        };
+        for (stream, target) in ready {
+            if let Err(error) = self.retarget(stream, target) {
+                self.report(error);
+            }
+        }
+    }
+
+    /// The `node.name` of the node `id`, or its `object.serial` if it has no name.
+    fn logical_target(&self, id: u32) -> Option<String> {
+        let name = self.node_names.lock().unwrap().get(&id).cloned();
+        name.or_else(|| self.serials.lock().unwrap().get(&id).cloned())
+    }
+
+    /// The id of the node named `target` by its `node.name` or `object.serial`.
+    fn resolve_target(&self, target: &str) -> Option<u32> {
+        let find = |ids: &HashMap<u32, String>| {
+            ids.iter().find(|(_, value)| *value == target).map(|(id, _)| *id)
+        };
+        let id = find(&self.node_names.lock().unwrap());
+        id.or_else(|| find(&self.serials.lock().unwrap()))
+    }
+
+    /// Remember the node `id` as the logical target of the streams configured to connect to
+    /// it, once the registry announced it.
+    fn remember_targets(&self, id: u32) {
+        let Some(logical) = self.logical_target(id) else {
+            return;
+        };
+        let mut targets = self.targets.lock().unwrap();
+        for (stream, (config, _)) in self.stream_configs.lock().unwrap().iter() {
+            if config.target == Some(id) {
+                targets.entry(*stream).or_insert_with(|| logical.clone());
+            }
+        }
+    }
+
+    /// Start every configured stream on a new connection, once the registry announced the
+    /// nodes present.
+    ///
+    /// The target ids of the configs belong to the previous connection. Streams go to the
+    /// node their logical target resolves to now, or are left to the session manager.
+    fn start_streams(&self) {
+        let mut stream_configs = self.stream_configs.lock().unwrap();
+        for (stream, (config, io)) in stream_configs.iter_mut() {
+            // Created since the connection came up.
+            if self.streams.lock().unwrap().contains_key(stream) {
+                continue;
+            }
+            if let Some(logical) = self.targets.lock().unwrap().get(stream) {
+                config.target = self.resolve_target(logical);
+            }
+            // A stream that fails to start is reported, it must not take the connection and
+            // the others with it.
+            if let Err(error) = self.start_stream(*stream, config, io) {
+                self.report(error);
+            }
+        }
+    }
+
+    /// Add the stream `stream`, creating it right away when connected.
+    pub fn create_stream(
+        &self,
+        stream: StreamId,
+        config: EStreamConfig,
+        io: StreamIo,
+    ) -> Result<(), EasyWireError> {
+        let mut stream_configs = self.stream_configs.lock().unwrap();
+        if stream_configs.contains_key(&stream) {
+            return Err(EasyWireError::StreamExists(stream));
+        }
+        if self.core.lock().unwrap().is_some() {
+            self.start_stream(stream, &config, &io)?;
+        }
+        let target = config.target;
+        stream_configs.insert(stream, (config, io));
+        drop(stream_configs);
+        if let Some(target) = target {
+            self.remember_targets(target);
+        }
+        Ok(())
+    }
+
+    /// Remove the stream `stream` and disconnect it.
+    pub fn destroy_stream(&self, stream: StreamId) -> Result<(), EasyWireError> {
+        self.stream_configs
+            .lock()
+            .unwrap()
+            .remove(&stream)
+            .ok_or(EasyWireError::UnknownStream(stream))?;
+        self.pending_targets.lock().unwrap().remove(&stream);
+        self.targets.lock().unwrap().remove(&stream);
+        if self.streams.lock().unwrap().remove(&stream).is_some() {
+            let _ = self.sender.lock().unwrap().send(PWEvent::StreamDestroyed(stream));
+        }
+        Ok(())
+    }
+
+    /// Create and connect a stream on the current connection.
+    fn start_stream(
+        &self,
+        stream: StreamId,
+        config: &EStreamConfig,
+        io: &StreamIo,
+    ) -> Result<(), EasyWireError> {
+        let mut e_stream = EStream::new(stream, StreamCore {
+            core: self.core.clone(),
+            io: io.clone(),
+            events: Arc::clone(&self.sender),
+        }, config.clone())?;
+        let listener = e_stream.create_stream()?;
+        self.streams.lock().unwrap().insert(stream, RunningStream {
+            _listener: listener,
+            stream: e_stream,
+        });
+        let _ = self.sender.lock().unwrap().send(PWEvent::StreamCreated(stream));
+        Ok(())
+    }
+
+    /// Add the virtual device `device`, creating it right away when connected.
+    ///
+    /// Before the registry announced the factory for virtual devices, the device is created
+    /// once it does.
+    pub fn create_virtual_device(
+        &self,
+        device: VirtualDeviceId,
+        config: VirtualDeviceConfig,
+    ) -> Result<(), EasyWireError> {
+        // Catch a bad config now rather than on the next connection.
+        config.properties()?;
+        let mut device_configs = self.device_configs.lock().unwrap();
+        if self.has_factory(virtual_device::FACTORY) {
+            self.start_virtual_device(device, &config)?;
+        }
+        device_configs.insert(device, config);
+        Ok(())
+    }
+
+    /// Forget the virtual device `device` and remove it from the server.
+    pub fn destroy_virtual_device(&self, device: VirtualDeviceId) -> Result<(), EasyWireError> {
+        self.device_configs
+            .lock()
+            .unwrap()
+            .remove(&device)
+            .ok_or(EasyWireError::UnknownVirtualDevice(device))?;
+        if self.devices.lock().unwrap().remove(&device).is_some() {
+            let _ = self.sender.lock().unwrap().send(PWEvent::VirtualDeviceDestroyed(device));
+        }
+        Ok(())
+    }
+
+    /// Create the virtual device `device` on the current connection.
+    fn start_virtual_device(
+        &self,
+        device: VirtualDeviceId,
+        config: &VirtualDeviceConfig,
+    ) -> Result<(), EasyWireError> {
+        let core = self.core.lock().unwrap();
+        let core = core.as_ref().ok_or(EasyWireError::NotRunning)?;
+        let sender = Arc::clone(&self.sender);
+        let virtual_device = VirtualDevice::create(core, config, move |node| {
+            let _ = sender.lock().unwrap().send(PWEvent::VirtualDeviceCreated { device, node });
+        })?;
+        self.devices.lock().unwrap().insert(device, virtual_device);
+        Ok(())
+    }
+
+    /// Create the virtual devices that do not exist on the current connection yet.
+    fn start_virtual_devices(&self) {
+        for (device, config) in self.device_configs.lock().unwrap().iter() {
+            if self.devices.lock().unwrap().contains_key(device) {
+                continue;
+            }
+            if let Err(error) = self.start_virtual_device(*device, config) {
+                self.report(error);
+            }
+        }
+    }
+
+    /// Whether the registry announced the factory `name` on the current connection.
+    fn has_factory(&self, name: &str) -> bool {
+        self.factories.lock().unwrap().values().any(|factory| factory == name)
+    }
+
+    /// Make the node named `name` the default `key`, or remove the default with `None`.
+    ///
+    /// See [`DefaultMetadata::set`].
+    pub fn set_default(&self, key: DefaultKey, name: Option<&str>) -> Result<(), EasyWireError> {
+        let metadata = self.metadata.lock().unwrap();
+        metadata.as_ref().ok_or(EasyWireError::NoDefaultMetadata)?.set(key, name);
+        Ok(())
+    }
+
+    /// Link the node `output` to the node `input` according to `policy`.
+    ///
+    /// `reply` gets the outcome once every link is active, or as soon as one of them fails.
+    pub fn link_nodes(
+        &self,
+        links: NodeLinksId,
+        output: u32,
+        input: u32,
+        policy: &LinkPolicy,
+        reply: Reply,
+    ) {
+        match self.create_links(links, output, input, policy) {
+            Ok(node_links) => {
+                self.node_links.lock().unwrap().insert(links, RunningLinks {
+                    active: vec![false; node_links.len()],
+                    links: node_links,
+                    output,
+                    input,
+                    reply: Some(reply),
+                });
+            }
+            Err(error) => reply.send(Err(error)),
+        }
+    }
+
+    /// Remove the links `links` from the server.
+    pub fn unlink_nodes(&self, links: NodeLinksId) -> Result<(), EasyWireError> {
+        let running = self
+            .node_links
+            .lock()
+            .unwrap()
+            .remove(&links)
+            .ok_or(EasyWireError::UnknownLinks(links))?;
+        match self.core.lock().unwrap().as_ref() {
+            Some(core) => running.links.destroy(core),
+            // The links went away with the connection.
+            None => Ok(()),
+        }
+    }
+
+    /// Pair the ports of the nodes `output` and `input` and create a link for every pair.
+    fn create_links(
+        &self,
+        links: NodeLinksId,
+        output: u32,
+        input: u32,
+        policy: &LinkPolicy,
+    ) -> Result<NodeLinks, EasyWireError> {
+        let pairs = {
+            let ports = self.ports.lock().unwrap();
+            let ports_of = |node_id: u32, direction: Direction| {
+                let mut ports: Vec<&GraphPort> = ports
+                    .values()
+                    .filter(|port| port.node_id() == Some(node_id) && port.direction == direction)
+                    .collect();
+                ports.sort_by_key(|port| port.id);
+                ports
+            };
+            node_links::pair_ports(&ports_of(output, Direction::Output), &ports_of(input, Direction::Input), policy)
+        };
+        if pairs.is_empty() {
+            return Err(EasyWireError::Link {
+                output,
+                input,
+                reason: "no ports to pair".to_string(),
+            });
+        }
+
+        let core = self.core.lock().unwrap();
+        let core = core.as_ref().ok_or(EasyWireError::NotRunning)?;
+        let node_links = Arc::clone(&self.node_links);
+        let sender = Arc::clone(&self.sender);
+        NodeLinks::create(core, output, input, &pairs, policy, move |index, state| {
+            link_state_changed(&node_links, &sender, links, index, state);
         })
     }
 
+    /// Send the events queued by the process callbacks of all streams to the manager.
+    ///
+    /// Also moves the streams whose node showed up since the last time.
+    fn forward_stream_events(&self) {
+        for running in self.streams.lock().unwrap().values_mut() {
+            running.stream.forward_events();
+        }
+        if !self.pending_targets.lock().unwrap().is_empty() {
+            self.apply_pending_targets();
+        }
+    }
+
+    /// Send `error` to the manager.
+    pub fn report(&self, error: EasyWireError) {
+        report(&self.sender, error);
+    }
+
+    /// Connect to PipeWire and run the main loop until the manager asks to terminate.
+    ///
+    /// When the connection is lost, or can not be established, it is retried according to
+    /// `reconnect`. Without a policy this returns as soon as the connection is gone.
+    ///
+    /// Errors while setting up are returned, errors reported by PipeWire while running are sent
+    /// to the manager as [`PWEvent::Error`].
     pub fn setup_main(&mut self,
                       remote: Option<String>,
                       has_listener: bool,
-                      mut scd: Option<StreamCoreData<T>>,
-    ) {
-        let main_loop = Arc::new(Mutex::new(Some(main_loop::MainLoop::new().expect("Failed to create main loop"))));
-        let mut main_loop: main_loop::MainLoop = main_loop.clone().lock().unwrap().clone().unwrap();
+                      scd: Option<StreamCoreData>,
+                      control: channel::Receiver<IncomingEvent>,
+                      reconnect: Option<ReconnectPolicy>,
+    ) -> Result<(), EasyWireError> {
+        let main_loop = main_loop::MainLoop::new().map_err(EasyWireError::Connection)?;
+        self.main_loop = Arc::new(Mutex::new(Some(main_loop.clone())));
 
-        //println!("main_loop: {:?}", main_loop);
+        // The manager asks us to quit through the control channel, there is no other way out.
+        let terminated = Rc::new(Cell::new(false));
         let main_loop_weak = main_loop.downgrade();
-        let _sig_int = main_loop.add_signal_local(Signal::SIGINT, move || {
-            if let Some(main_loop) = main_loop_weak.upgrade() {
-                main_loop.quit();
+        let _control = control.attach(&main_loop, {
+            let terminated = Rc::clone(&terminated);
+            let pipe_wire = self.clone();
+            move |event| match event {
+                IncomingEvent::Terminate => {
+                    terminated.set(true);
+                    if let Some(main_loop) = main_loop_weak.upgrade() {
+                        main_loop.quit();
+                    }
+                }
+                IncomingEvent::UpdateObjID(stream, target) => pipe_wire.follow(stream, target),
+                IncomingEvent::Retarget { stream, target, reply } => {
+                    reply.send(pipe_wire.retarget(stream, target));
+                }
+                IncomingEvent::CreateStream { stream, config, io, reply } => {
+                    reply.send(pipe_wire.create_stream(stream, config, io));
+                }
+                IncomingEvent::DestroyStream { stream, reply } => {
+                    reply.send(pipe_wire.destroy_stream(stream));
+                }
+                IncomingEvent::CreateVirtualDevice { device, config, reply } => {
+                    reply.send(pipe_wire.create_virtual_device(device, config));
+                }
+                IncomingEvent::DestroyVirtualDevice { device, reply } => {
+                    reply.send(pipe_wire.destroy_virtual_device(device));
+                }
+                IncomingEvent::SetDefault { key, name, reply } => {
+                    reply.send(pipe_wire.set_default(key, name.as_deref()));
+                }
+                IncomingEvent::LinkNodes { links, output, input, policy, reply } => {
+                    pipe_wire.link_nodes(links, output, input, &policy, reply);
+                }
+                IncomingEvent::UnlinkNodes { links, reply } => {
+                    reply.send(pipe_wire.unlink_nodes(links));
+                }
             }
         });
-        let main_loop_weak = main_loop.downgrade();
-        let _sig_term = main_loop.add_signal_local(Signal::SIGTERM, move || {
-            if let Some(main_loop) = main_loop_weak.upgrade() {
-                main_loop.quit();
-            }
+
+        // The process callbacks queue their events without waking this thread, collect them
+        // regularly instead.
+        let forward_timer = main_loop.add_timer({
+            let pipe_wire = self.clone();
+            move |_| pipe_wire.forward_stream_events()
         });
+        if let Err(error) = forward_timer.update_timer(Some(FORWARD_INTERVAL), Some(FORWARD_INTERVAL)).into_result() {
+            self.report(EasyWireError::PipeWire(error.into()));
+        }
+
+        if let Some(scd_value) = scd {
+            let sink = scd_value.sink.map(|sink| Arc::new(Mutex::new(sink)));
+            self.stream_configs.lock().unwrap().insert(StreamId::MAIN, (scd_value.config, StreamIo::Capture(sink)));
+        }
+
+        let mut attempts = 0;
+        let mut disconnected = false;
+        loop {
+            let reconnected = disconnected.then_some(attempts);
+            let result = self.run_connection(&main_loop, &remote, has_listener, reconnected);
+            if terminated.get() {
+                return result;
+            }
+            let Some(policy) = reconnect.as_ref() else {
+                return result;
+            };
 
-        let context = context::Context::new(&main_loop.clone()).expect("Failed to create context");
+            match result {
+                // The connection was up until now.
+                Ok(()) => {
+                    attempts = 0;
+                    disconnected = true;
+                    let _ = self.sender.lock().unwrap().send(PWEvent::Disconnected);
+                }
+                Err(error) => self.report(error),
+            }
+
+            attempts += 1;
+            if policy.max_attempts.map_or(false, |max| attempts > max) {
+                return Err(EasyWireError::ReconnectFailed(attempts - 1));
+            }
+            wait(&main_loop, policy.delay(attempts));
+            if terminated.get() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Connect once and run the main loop until it is quit.
+    ///
+    /// `reconnected` holds the number of attempts it took if this connection replaces one that
+    /// was lost.
+    fn run_connection(
+        &mut self,
+        main_loop: &main_loop::MainLoop,
+        remote: &Option<String>,
+        has_listener: bool,
+        reconnected: Option<u32>,
+    ) -> Result<(), EasyWireError> {
+        let context = context::Context::new(main_loop).map_err(EasyWireError::Connection)?;
         let props = remote.clone().map(|remote| {
             properties! {
             *keys::REMOTE_NAME => remote,
         }
         });
-        let core = context.connect(props).expect("Failed to connect to PipeWire");
-        self.core = Arc::new(Mutex::new(Some(core)));
-        self.main_loop = Arc::new(Mutex::new(Some(main_loop.clone())));
-        let proxies_clone = Arc::clone(&self.proxies);
-        let mut main_loop: main_loop::MainLoop = self.main_loop.clone().lock().unwrap().clone().unwrap();
-
-        let _listener = match self.core.clone().lock().unwrap().as_ref().unwrap() {
-            (ref s) => {
-                let main_loop_weak = main_loop.downgrade();
-                let reet = s
-                    .add_listener_local()
-                    .info(|info| {})
-                    .done(|_id, _seq| {})
-                    .error(move |id, seq, res, message| {
-                        if id == 0 {
-                            if let Some(main_loop) = main_loop_weak.upgrade() {
-                                main_loop.quit();
-                            }
+        let core = context.connect(props).map_err(EasyWireError::Connection)?;
+        *self.core.lock().unwrap() = Some(core.clone());
+        if let Some(attempts) = reconnected {
+            let _ = self.sender.lock().unwrap().send(PWEvent::Reconnected { attempts });
+        }
+
+        let result = self.run_core(main_loop, &core, has_listener);
+
+        // Ids are only valid on the connection they were announced on.
+        for (config, _) in self.stream_configs.lock().unwrap().values_mut() {
+            config.target = None;
+        }
+        self.pending_targets.lock().unwrap().clear();
+
+        // Tear down in dependency order: streams and proxies go before the core they belong to.
+        self.streams.lock().unwrap().clear();
+        self.devices.lock().unwrap().clear();
+        self.node_links.lock().unwrap().clear();
+        self.ports.lock().unwrap().clear();
+        self.metadata.lock().unwrap().take();
+        self.serials.lock().unwrap().clear();
+        self.node_names.lock().unwrap().clear();
+        self.factories.lock().unwrap().clear();
+        self.proxies.lock().unwrap().clear();
+        self.core.lock().unwrap().take();
+        result
+    }
+
+    /// Set up listeners and streams on `core` and run the main loop until it is quit.
+    fn run_core(
+        &mut self,
+        main_loop: &main_loop::MainLoop,
+        core: &crate::core::Core,
+        has_listener: bool,
+    ) -> Result<(), EasyWireError> {
+        let _listener = {
+            let main_loop_weak = main_loop.downgrade();
+            let sender = Arc::clone(&self.sender);
+            core
+                .add_listener_local()
+                .error(move |id, seq, res, message| {
+                    report(&sender, EasyWireError::Remote {
+                        id,
+                        seq,
+                        res,
+                        message: message.to_string(),
+                    });
+                    // An error on the core itself means the connection is gone.
+                    if id == 0 {
+                        if let Some(main_loop) = main_loop_weak.upgrade() {
+                            main_loop.quit();
                         }
-                    })
-                    .register();
-                reet
-            },
+                    }
+                })
+                .register()
         };
 
-        //let _a;
         let _registry_listener;
-        let registry = Rc::new(self.core.lock().unwrap().clone().unwrap().get_registry().expect("Failed to get registry"));
+        let registry = Rc::new(core.get_registry()?);
         let registry_weak = Rc::downgrade(&registry);
         let tx_lock = Arc::clone(&self.sender);
         let tx_remove = Arc::clone(&self.sender);
         if has_listener {
             _registry_listener = self.setup_listener(registry.clone(), registry_weak.clone(), tx_lock.clone(), tx_remove.clone());
         }
+        let _target_listener = self.setup_target_listener(registry.clone(), registry_weak.clone());
 
-        let _b: EStream<T>;
-        let receiver;
-        let data;
-        let stream_listener;
-        if let Some(scd_value) = scd {
-            receiver = scd_value.receiver;
+        // The registry announces the nodes present before the server answers this sync, start
+        // the streams then so that their targets can be resolved. Streams live in
+        // `self.streams` so they outlive the main loop run below.
+        let initial_sync = core.sync(0)?;
+        let _sync_listener = {
+            let pipe_wire = self.clone();
+            core
+                .add_listener_local()
+                .done(move |id, seq| {
+                    if id == crate::core::PW_ID_CORE && seq == initial_sync {
+                        pipe_wire.start_streams();
+                    }
+                })
+                .register()
+        };
+        main_loop.run();
+        Ok(())
+    }
+}
 
-            // Prepare the data for the final move
-            data = scd_value.data.into(); // `data` is moved here
+impl PipeWire {
+    /// Track the globals the other requests depend on:
+    /// - the `default` metadata, whose changes are reported to the manager,
+    /// - the serials of all nodes, to retarget streams,
+    /// - the ports, to link nodes,
+    /// - the factories, creating the virtual devices once theirs shows up.
+    fn setup_target_listener(&self, registry: Rc<Registry>, registry_weak: Weak<Registry>) -> registry::Listener {
+        let metadata = Arc::clone(&self.metadata);
+        let serials = Arc::clone(&self.serials);
+        let serials_remove = Arc::clone(&self.serials);
+        let node_names_remove = Arc::clone(&self.node_names);
+        let pending_remove = Arc::clone(&self.pending_targets);
+        let factories_remove = Arc::clone(&self.factories);
+        let ports = Arc::clone(&self.ports);
+        let ports_remove = Arc::clone(&self.ports);
+        let sender = Arc::clone(&self.sender);
+        let pipe_wire = self.clone();
 
-            _b = EStream::new(StreamCore {
-                core: self.core.clone(),
-                receiver,
-                data, // Use the moved `data` here
-            });
+        registry
+            .add_listener_local()
+            .global(move |obj| {
+                let Some(props) = obj.props else {
+                    return;
+                };
+                match obj.type_ {
+                    ObjectType::Node => {
+                        if let Some(serial) = props.get("object.serial") {
+                            serials.lock().unwrap().insert(obj.id, serial.to_string());
+                        }
+                        if let Some(name) = props.get(*keys::NODE_NAME) {
+                            pipe_wire.node_names.lock().unwrap().insert(obj.id, name.to_string());
+                        }
+                        pipe_wire.remember_targets(obj.id);
+                        pipe_wire.apply_pending_targets();
+                    }
+                    ObjectType::Metadata if DefaultMetadata::is_default(obj) => {
+                        if let Some(registry) = registry_weak.upgrade() {
+                            let events = Arc::clone(&sender);
+                            *metadata.lock().unwrap() = DefaultMetadata::bind(&registry, obj, move |change| {
+                                let _ = events.lock().unwrap().send(PWEvent::DefaultChanged(change.clone()));
+                            })
+                            .map_err(|source| report(&sender, EasyWireError::Bind { id: obj.id, source }))
+                            .ok();
+                            pipe_wire.apply_pending_targets();
+                        }
+                    }
+                    ObjectType::Port => {
+                        let direction = match props.get(*keys::PORT_DIRECTION) {
+                            Some("in") => Direction::Input,
+                            Some("out") => Direction::Output,
+                            _ => return,
+                        };
+                        ports.lock().unwrap().insert(obj.id, GraphPort {
+                            id: obj.id,
+                            direction,
+                            props: props_from_dict(Some(props)),
+                        });
+                    }
+                    ObjectType::Factory => {
+                        let Some(name) = props.get(*keys::FACTORY_NAME) else {
+                            return;
+                        };
+                        pipe_wire.factories.lock().unwrap().insert(obj.id, name.to_string());
+                        if name == virtual_device::FACTORY {
+                            pipe_wire.start_virtual_devices();
+                        }
+                    }
+                    _ => {}
+                }
+            })
+            .global_remove(move |id| {
+                serials_remove.lock().unwrap().remove(&id);
+                node_names_remove.lock().unwrap().remove(&id);
+                pending_remove.lock().unwrap().retain(|_, target| *target != id);
+                factories_remove.lock().unwrap().remove(&id);
+                ports_remove.lock().unwrap().remove(&id);
+            })
+            .register()
+    }
+}
+
+/// A stream connected on the current connection.
+struct RunningStream {
+    // Declared first so the listener is removed before the stream is destroyed.
+    _listener: StreamListener<Userdata>,
+    stream: EStream,
+}
 
-            if let Some(mut e_stream) = Some(_b) {//self.e_stream.lock().unwrap().as_mut() {
-                stream_listener = e_stream.create_stream(None);
+/// Links created on the current connection.
+struct RunningLinks {
+    links: NodeLinks,
+    output: u32,
+    input: u32,
+    /// Which of the links are active.
+    active: Vec<bool>,
+    /// Where to send the outcome, until every link became active or one failed.
+    reply: Option<Reply>,
+}
+
+/// Track the state of link `index` of the links `links`.
+///
+/// Once every link is active the requester is told so, a link failing is sent to the
+/// requester while it waits and reported to the manager afterwards.
+fn link_state_changed(
+    node_links: &Mutex<HashMap<NodeLinksId, RunningLinks>>,
+    sender: &Mutex<mpsc::Sender<PWEvent>>,
+    links: NodeLinksId,
+    index: usize,
+    state: LinkState,
+) {
+    let mut node_links = node_links.lock().unwrap();
+    let Some(running) = node_links.get_mut(&links) else {
+        return;
+    };
+    match state {
+        LinkState::Active => {
+            running.active[index] = true;
+            if running.active.iter().all(|active| *active) {
+                if let Some(reply) = running.reply.take() {
+                    reply.send(Ok(()));
+                }
             }
         }
-        self.main_loop.lock().unwrap().clone().unwrap().run();
+        LinkState::Error(message) => {
+            let error = EasyWireError::Link {
+                output: running.output,
+                input: running.input,
+                reason: message.to_string(),
+            };
+            match running.reply.take() {
+                Some(reply) => reply.send(Err(error)),
+                None => report(sender, error),
+            }
+        }
+        _ => running.active[index] = false,
     }
 }
 
+/// Run `main_loop` for `delay`, or until it is quit by someone else.
+fn wait(main_loop: &main_loop::MainLoop, delay: Duration) {
+    let main_loop_weak = main_loop.downgrade();
+    let timer = main_loop.add_timer(move |_| {
+        if let Some(main_loop) = main_loop_weak.upgrade() {
+            main_loop.quit();
+        }
+    });
+    if timer.update_timer(Some(delay), None).into_result().is_ok() {
+        main_loop.run();
+    }
+}
+
+/// Send `error` to the manager.
+///
+/// Nobody is listening anymore once the manager is gone, the error is dropped then.
+pub(crate) fn report(sender: &Mutex<mpsc::Sender<PWEvent>>, error: EasyWireError) {
+    let _ = sender.lock().unwrap().send(PWEvent::Error(error));
+}
 
+/// Bind `obj`, reporting a failure to the manager instead of panicking.
+fn bind<T: ProxyT>(
+    registry: &Registry,
+    obj: &GlobalObject<&DictRef>,
+    sender: &Mutex<mpsc::Sender<PWEvent>>,
+) -> Option<T> {
+    registry
+        .bind(obj)
+        .map_err(|source| report(sender, EasyWireError::Bind { id: obj.id, source }))
+        .ok()
+}
 
 pub trait EListener {
     fn setup_listener(
@@ -195,7 +966,7 @@ pub trait EListener {
     ) -> registry::Listener;
 }
 
-impl <T>EListener for PipeWire<T> {
+impl EListener for PipeWire {
     fn setup_listener(
         &mut self,
         registry:  Rc<Registry>,
@@ -212,46 +983,106 @@ impl <T>EListener for PipeWire<T> {
                 if let Some(registry) = registry_weak.upgrade() {
                     let p: Option<(Box<dyn ProxyT>, Box<dyn Listener>)> = match obj.type_ {
                         ObjectType::Port => {
-                            let port: Port = registry.bind(obj).unwrap();
+                            let Some(port) = bind::<Port>(&registry, obj, &tx_lock) else { return };
                             let tx_lock = tx_lock.clone();
 
                             let obj_listener = port
                                 .add_listener_local()
                                 .info(move |info| {
-                                    // add info to the stored port
-                                    let props: &DictRef = info.clone().props().unwrap();
-                                    match props.get(*keys::NODE_ID) {
-                                        None => {},
-                                        Some(_) => {
-                                            let node_event: NodeEvent = NodeEvent {
-                                                proxy_id: info.id().clone(),
-                                                node_id: props.get(*keys::NODE_ID).unwrap().parse::<u32>().expect("Failed to parse node id"),
-                                                port_id: props.get(*keys::PORT_ID).unwrap().parse::<u32>().expect("Failed to parse port id"),
-                                                channel: props.get(*keys::AUDIO_CHANNEL).unwrap_or("Test").to_string(),
-                                                object_path: props.get(*keys::OBJECT_PATH).unwrap_or("Test").to_string(),
-                                                port_name: props.get(*keys::PORT_NAME).unwrap_or("Test").to_string(),
-                                                direction: props.get(*keys::PORT_DIRECTION).unwrap_or("Test").to_string(),
-                                                format: props.get(*keys::FORMAT_DSP).unwrap_or("Test").to_string(),
-                                            };
-                                            tx_lock.lock().unwrap().send(PWEvent::Node(node_event)).expect("TODO: panic message");
-                                        }
+                                    let port = GraphPort {
+                                        id: info.id(),
+                                        direction: info.direction(),
+                                        props: props_from_dict(info.props()),
                                     };
+                                    let _ = tx_lock.lock().unwrap().send(PWEvent::Object(GraphObject::Port(port)));
                                 })
-                                .param(|seq, id, index, next, param| {})
                                 .register();
                             Some((Box::new(port), Box::new(obj_listener)))
                         }
+                        ObjectType::Node => {
+                            let Some(node) = bind::<Node>(&registry, obj, &tx_lock) else { return };
+                            let tx_lock = tx_lock.clone();
+
+                            let obj_listener = node
+                                .add_listener_local()
+                                .info(move |info| {
+                                    let node = GraphNode {
+                                        id: info.id(),
+                                        state: info.state().into(),
+                                        n_input_ports: info.n_input_ports(),
+                                        n_output_ports: info.n_output_ports(),
+                                        props: props_from_dict(info.props()),
+                                    };
+                                    let _ = tx_lock.lock().unwrap().send(PWEvent::Object(GraphObject::Node(node)));
+                                })
+                                .register();
+                            Some((Box::new(node), Box::new(obj_listener)))
+                        }
+                        ObjectType::Link => {
+                            let Some(link) = bind::<Link>(&registry, obj, &tx_lock) else { return };
+                            let tx_lock = tx_lock.clone();
+
+                            let obj_listener = link
+                                .add_listener_local()
+                                .info(move |info| {
+                                    let link = GraphLink {
+                                        id: info.id(),
+                                        output_node: info.output_node_id(),
+                                        output_port: info.output_port_id(),
+                                        input_node: info.input_node_id(),
+                                        input_port: info.input_port_id(),
+                                        state: info.state().into(),
+                                        props: props_from_dict(info.props()),
+                                    };
+                                    let _ = tx_lock.lock().unwrap().send(PWEvent::Object(GraphObject::Link(link)));
+                                })
+                                .register();
+                            Some((Box::new(link), Box::new(obj_listener)))
+                        }
+                        ObjectType::Device => {
+                            let Some(device) = bind::<Device>(&registry, obj, &tx_lock) else { return };
+                            let tx_lock = tx_lock.clone();
+
+                            let obj_listener = device
+                                .add_listener_local()
+                                .info(move |info| {
+                                    let device = GraphDevice {
+                                        id: info.id(),
+                                        props: props_from_dict(info.props()),
+                                    };
+                                    let _ = tx_lock.lock().unwrap().send(PWEvent::Object(GraphObject::Device(device)));
+                                })
+                                .register();
+                            Some((Box::new(device), Box::new(obj_listener)))
+                        }
+                        ObjectType::Client => {
+                            let Some(client) = bind::<Client>(&registry, obj, &tx_lock) else { return };
+                            let tx_lock = tx_lock.clone();
+
+                            let obj_listener = client
+                                .add_listener_local()
+                                .info(move |info| {
+                                    let client = GraphClient {
+                                        id: info.id(),
+                                        props: props_from_dict(info.props()),
+                                    };
+                                    let _ = tx_lock.lock().unwrap().send(PWEvent::Object(GraphObject::Client(client)));
+                                })
+                                .register();
+                            Some((Box::new(client), Box::new(obj_listener)))
+                        }
                         _ => { None }
                     };
 
                     if let Some((proxy_spe, listener_spe)) = p {
                         let proxy = proxy_spe.upcast_ref();
                         let proxy_id = proxy.id().clone();
+                        let global_id = obj.id;
                         let tx_remove = tx_remove.clone();
                         let listener = proxy
                             .add_listener_local()
                             .removed(move || {
-                                tx_remove.lock().unwrap().send(PWEvent::RemoveNode(proxy_id)).expect("TODO: panic message");
+                                let _ = tx_remove.lock().unwrap().send(PWEvent::GlobalRemoved(global_id));
                                 // TODO: implement this otherwise we will have dead proxies
                                 //proxies_weak.remove(&proxy_id);
                             })
@@ -264,7 +1095,6 @@ impl <T>EListener for PipeWire<T> {
             })
             .global_remove(|id| {})
             .register();
-        println!("setup_listener: ");
         //let obj_listener_boxed: Box<dyn Listener + 'static> = Box::new(_registry_listener) as Box<dyn Listener + 'static>;
         _registry_listener
         //self.main_loop.lock().unwrap().clone().unwrap().run();
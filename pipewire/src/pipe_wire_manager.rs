@@ -1,90 +1,739 @@
 use std::collections::HashMap;
 use std::sync::{Arc, mpsc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::mpsc::Receiver;
 use std::thread;
-use crate::e_stream::{StreamCore, StreamCoreData, StreamEvent};
-use crate::pipe_wire;
-use crate::pipe_wire::{PWEvent, NodeEvent, IncomingEvent};
+use std::time::Duration;
+use spa::param::audio::AudioInfoRaw;
+use crate::app_selector::AppSelector;
+use crate::e_stream::{EStreamConfig, SampleSink, SampleSource, StreamCoreData, StreamId, StreamIo};
+use crate::{channel, pipe_wire, EasyWireError};
+use crate::meter::MeterEvent;
+use crate::graph::{Graph, GraphEvent, GraphNode, GraphObject};
+use crate::async_stream::{sample_stream, EventStream, SampleStream};
+use futures::StreamExt;
+use crate::pipe_wire::{PWEvent, IncomingEvent, Reply};
+use crate::virtual_device::{VirtualDeviceConfig, VirtualDeviceId};
+use crate::node_links::{LinkPolicy, NodeLinksId};
+use crate::default_metadata::{DefaultChange, DefaultKey};
 
 
-pub struct PipeWireManager<T> {
-    pipe_wire: Arc<Mutex<pipe_wire::PipeWire<T>>>,
-    // TODO: I don't believe we need to store this information. Let's just implement a get method.
-    pub node_info: Arc<Mutex<HashMap<u32, NodeEvent>>>,
-    rx: Arc<Mutex<mpsc::Receiver<PWEvent>>>,
-    tx: Option<Arc<Mutex<mpsc::Sender<StreamEvent>>>>,
-    receiver: Arc<Mutex<Receiver<u32>>>,
+/// Events reported by the manager to its subscribers, see [`PipeWireManager::subscribe`].
+#[derive(Debug, Clone)]
+pub enum ManagerEvent {
+    /// The graph of PipeWire objects changed.
+    Graph(GraphEvent),
+    /// Something failed on the PipeWire thread.
+    Error(Arc<EasyWireError>),
+    /// The connection to PipeWire was lost.
+    ///
+    /// The graph is cleared, it is rebuilt from scratch once the manager reconnected.
+    Disconnected,
+    /// The connection was established again after `attempts` attempts.
+    Reconnected { attempts: u32 },
+    /// The stream `stream` was moved to the node `target`.
+    Retargeted { stream: StreamId, target: u32 },
+    /// The stream `stream` was connected, also after every reconnect.
+    StreamCreated(StreamId),
+    /// The stream `stream` was destroyed.
+    StreamDestroyed(StreamId),
+    /// The stream `stream` negotiated the format `info` with the graph.
+    ///
+    /// This happens once the stream is linked, and again whenever the format changes, for
+    /// example after the stream was moved to another node.
+    FormatNegotiated { stream: StreamId, info: AudioInfoRaw },
+    /// The meter of the stream `stream` measured levels or detected silence or activity, see
+    /// [`EStreamConfig::meter`].
+    Meter { stream: StreamId, event: MeterEvent },
+    /// The stream `stream` had no buffer to process in `count` cycles since the last report.
+    ///
+    /// Reported at most every few milliseconds, the cycles are counted in between.
+    Underrun { stream: StreamId, count: u64 },
+    /// A default device or the target of a stream changed, see
+    /// [`PipeWireManager::default_node`].
+    DefaultChanged(DefaultChange),
+    /// The virtual device `device` was created as the node `node`, also after every reconnect.
+    VirtualDeviceCreated { device: VirtualDeviceId, node: u32 },
+    /// The virtual device `device` was removed.
+    VirtualDeviceDestroyed(VirtualDeviceId),
 }
 
-impl <T>PipeWireManager<T> {
-    pub fn new(receive: Receiver<u32>) -> Self {
-        let (s_pw_event, r_pwm_process) = mpsc::channel();
-        // let (s_pwm_event, r_pw_process) = mpsc::channel();
-        let manager = Arc::new(Mutex::new(pipe_wire::PipeWire::new(s_pw_event).unwrap()));
+/// How the manager reconnects after the connection to PipeWire was lost.
+///
+/// The delay before attempt `n` is `initial_delay * multiplier^(n - 1)`, capped at `max_delay`.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Delay before the first attempt.
+    pub initial_delay: Duration,
+    /// Upper bound for the delay between two attempts.
+    pub max_delay: Duration,
+    /// Factor the delay grows by after every failed attempt.
+    pub multiplier: f64,
+    /// Give up after this many failed attempts in a row, `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
         Self {
-            pipe_wire: manager,
-            node_info: Arc::new(Mutex::new(HashMap::new())),
-            rx: Arc::new(Mutex::new(r_pwm_process)),
-            tx: None, // Arc::new(Mutex::new(s_pwm_event)),
-            receiver: Arc::new(Mutex::new(receive)),
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            max_attempts: None,
         }
     }
+}
 
-    pub fn setup_main(&mut self, name: Option<String>, has_listener: bool, stream_core: Option<T>) {
-        let pipe_wire = self.pipe_wire.clone();
-        let (s_pwm_event, r_pw_process) = mpsc::channel();
-        self.tx = Some(Arc::new(Mutex::new(s_pwm_event)));
-        if has_listener {
-            self.event_loop();
+impl ReconnectPolicy {
+    /// The delay before attempt `attempt`, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent);
+        if delay.is_finite() && delay < self.max_delay.as_secs_f64() {
+            Duration::from_secs_f64(delay.max(0.0))
+        } else {
+            self.max_delay
         }
-        if stream_core.is_some() {
-            self.ev();
+    }
+}
+
+/// Tracks which node of the graph the capture follows.
+#[derive(Default)]
+struct AppCapture {
+    selector: Option<AppSelector>,
+    target: Option<u32>,
+}
+
+impl AppCapture {
+    /// Pick the node the capture should be connected to.
+    ///
+    /// The current target is kept as long as it still matches, otherwise the matching
+    /// output node with the lowest id is chosen.
+    fn select_target(&self, graph: &Graph) -> Option<u32> {
+        let selector = self.selector.as_ref()?;
+        if let Some(target) = self.target {
+            if graph.node(target).map_or(false, |node| selector.matches_output(node)) {
+                return Some(target);
+            }
         }
-        pipe_wire.lock().unwrap().setup_main(name, has_listener,
-                                             Some(StreamCoreData {
-                                                 receiver: r_pw_process,
-                                                 data: stream_core.unwrap(),
-                                             })
-        );
+        graph
+            .nodes()
+            .filter(|node| selector.matches_output(node))
+            .map(|node| node.id)
+            .min()
     }
 
-    fn ev(&mut self) {
-        let rx = Arc::clone(&self.receiver);
-        let tx = Arc::clone(&self.tx.clone().unwrap());
-        thread::spawn(move || {
-            for event in rx.lock().unwrap().iter() {
-                match event {
-                    x => {
-                        //println!("Node event: {:?}", x);
-                        tx.lock().unwrap().send(StreamEvent::UpdateObjID(x)).unwrap();
-                    },
-                    _ => {
-                        println!("None");
-                    },
+    /// Recompute the target and tell the stream when it moved to another node.
+    ///
+    /// The PipeWire thread keeps the move pending until the stream can be moved, it is not
+    /// lost when the stream has no node yet, for example right after a reconnect.
+    fn update_target(&mut self, stream: StreamId, graph: &Graph, tx: &Option<channel::Sender<IncomingEvent>>) {
+        let target = self.select_target(graph);
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        if let (Some(node_id), Some(tx)) = (target, tx) {
+            let _ = tx.send(IncomingEvent::UpdateObjID(stream, node_id));
+        }
+    }
+}
+
+/// Update the targets of all application captures after the graph changed.
+fn update_captures(
+    captures: &Mutex<HashMap<StreamId, AppCapture>>,
+    graph: &Graph,
+    tx: &Option<channel::Sender<IncomingEvent>>,
+) {
+    for (stream, capture) in captures.lock().unwrap().iter_mut() {
+        capture.update_target(*stream, graph, tx);
+    }
+}
+
+pub struct PipeWireManager {
+    graph: Arc<Mutex<Graph>>,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    captures: Arc<Mutex<HashMap<StreamId, AppCapture>>>,
+    /// The default devices from the `default` metadata, by node name.
+    defaults: Arc<Mutex<HashMap<DefaultKey, String>>>,
+    reconnect: Option<ReconnectPolicy>,
+    tx: Option<channel::Sender<IncomingEvent>>,
+}
+
+impl Default for PipeWireManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PipeWireManager {
+    pub fn new() -> Self {
+        Self {
+            graph: Arc::new(Mutex::new(Graph::new())),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            captures: Arc::new(Mutex::new(HashMap::new())),
+            defaults: Arc::new(Mutex::new(HashMap::new())),
+            reconnect: None,
+            tx: None,
+        }
+    }
+
+    /// Capture the audio output of the application described by `selector`.
+    ///
+    /// The manager follows the application's output nodes as they appear and disappear in
+    /// the registry and moves the [main](StreamId::MAIN) capture stream to one of them.
+    /// Calling this again replaces the previous selector.
+    pub fn capture_application(&mut self, selector: AppSelector) {
+        self.capture_application_on(StreamId::MAIN, selector);
+    }
+
+    /// Like [`capture_application()`](Self::capture_application), but moves the stream `stream`.
+    pub fn capture_application_on(&mut self, stream: StreamId, selector: AppSelector) {
+        // Same lock order as the event loop: graph first.
+        let graph = self.graph.lock().unwrap();
+        let mut captures = self.captures.lock().unwrap();
+        let capture = captures.entry(stream).or_default();
+        capture.selector = Some(selector);
+        capture.target = None;
+        capture.update_target(stream, &graph, &self.tx);
+    }
+
+    /// Stop following an application with the stream `stream`.
+    pub fn stop_application_capture(&mut self, stream: StreamId) {
+        self.captures.lock().unwrap().remove(&stream);
+    }
+
+    /// Reconnect according to `policy` when the connection to PipeWire is lost.
+    ///
+    /// Without a policy, which is the default, the PipeWire thread finishes once the connection
+    /// is gone. Only takes effect for the next [`setup_main()`](Self::setup_main).
+    pub fn set_reconnect_policy(&mut self, policy: Option<ReconnectPolicy>) {
+        self.reconnect = policy;
+    }
+
+    /// The graph of nodes, ports, links, devices and clients known to the manager.
+    ///
+    /// The graph is only populated while the manager runs with `has_listener` set.
+    pub fn graph(&self) -> Arc<Mutex<Graph>> {
+        Arc::clone(&self.graph)
+    }
+
+    /// Receive every [`ManagerEvent`] from now on.
+    ///
+    /// Subscribers that dropped their receiver are forgotten on the next event.
+    pub fn subscribe(&self) -> Receiver<ManagerEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(Subscriber::Blocking(tx));
+        rx
+    }
+
+    /// Receive every [`ManagerEvent`] from now on as an async stream.
+    ///
+    /// The async counterpart of [`subscribe()`](Self::subscribe), usable from any runtime.
+    pub fn events(&self) -> EventStream {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        self.subscribers.lock().unwrap().push(Subscriber::Async(tx));
+        EventStream { receiver: rx }
+    }
+
+    /// Wait until the graph has a node matched by `selector` and return it.
+    ///
+    /// Returns right away if such a node is already known. The graph is only populated while
+    /// the manager runs with `has_listener` set, use `tokio::time::timeout` to give up waiting.
+    /// Returns `None` if the manager stops reporting events.
+    pub async fn wait_for_node(&self, selector: &AppSelector) -> Option<GraphNode> {
+        // Subscribe first so a node added in between is not missed.
+        let mut events = self.events();
+        let node = self.graph.lock().unwrap().nodes().find(|node| selector.matches(node)).cloned();
+        if node.is_some() {
+            return node;
+        }
+
+        while let Some(event) = events.next().await {
+            if let ManagerEvent::Graph(GraphEvent::Added(GraphObject::Node(node)) | GraphEvent::Changed(GraphObject::Node(node))) = event {
+                if selector.matches(&node) {
+                    return Some(node);
                 }
             }
-        });
+        }
+        None
+    }
+
+    /// The name of the node that is the default `key`, e.g. the default sink.
+    ///
+    /// Known once the PipeWire thread bound the `default` metadata, changes are reported as
+    /// [`ManagerEvent::DefaultChanged`].
+    pub fn default_node(&self, key: DefaultKey) -> Option<String> {
+        self.defaults.lock().unwrap().get(&key).cloned()
+    }
+
+    /// The node the application capture of the main stream is currently following, if any.
+    pub fn captured_node(&self) -> Option<u32> {
+        self.captured_node_of(StreamId::MAIN)
     }
 
-    fn event_loop(&mut self) {
-        let node_info = Arc::clone(&self.node_info);
-        let rx = Arc::clone(&self.rx); // Clone the receiver before moving into the thread
+    /// The node the application capture of the stream `stream` is currently following, if any.
+    pub fn captured_node_of(&self, stream: StreamId) -> Option<u32> {
+        self.captures.lock().unwrap().get(&stream).and_then(|capture| capture.target)
+    }
+
+    /// Connect to PipeWire and run its main loop on a dedicated thread.
+    ///
+    /// When a `sink` is given, the [main](StreamId::MAIN) capture stream is created and every
+    /// block of captured frames is handed to it together with the negotiated format. More
+    /// streams can be added through [`ManagerHandle::create_stream`].
+    ///
+    /// This returns as soon as the thread is spawned. The returned [`ManagerHandle`] stops
+    /// the thread and tears down every PipeWire object it owns when dropped.
+    ///
+    /// Failures on the thread, including failing to connect, are reported as
+    /// [`ManagerEvent::Error`] to every [subscriber](Self::subscribe).
+    pub fn setup_main(&mut self, name: Option<String>, has_listener: bool, sink: Option<Box<dyn SampleSink>>) -> Result<ManagerHandle, EasyWireError> {
+        let (s_pw_event, r_pwm_process) = mpsc::channel();
+        let (control, r_control) = channel::channel();
+        let reconnect = self.reconnect.clone();
+        self.tx = Some(control.clone());
+        self.event_loop(r_pwm_process);
+        let thread = thread::Builder::new()
+            .name("pipewire".to_string())
+            .spawn(move || {
+                let mut pipe_wire = pipe_wire::PipeWire::new(s_pw_event);
+                let result = pipe_wire.setup_main(name, has_listener,
+                                     sink.map(|sink| StreamCoreData {
+                                         config: EStreamConfig::default(),
+                                         sink: Some(sink),
+                                     }),
+                                     r_control,
+                                     reconnect,
+                );
+                if let Err(error) = result {
+                    pipe_wire.report(error);
+                }
+            })
+            .map_err(EasyWireError::Thread)?;
+
+        Ok(ManagerHandle {
+            control,
+            next_stream: AtomicU32::new(StreamId::MAIN.0 + 1),
+            next_device: AtomicU32::new(0),
+            next_links: AtomicU32::new(0),
+            thread: Some(thread),
+        })
+    }
+
+    fn event_loop(&mut self, rx: mpsc::Receiver<PWEvent>) {
+        let graph = Arc::clone(&self.graph);
+        let subscribers = Arc::clone(&self.subscribers);
+        let captures = Arc::clone(&self.captures);
+        let defaults = Arc::clone(&self.defaults);
+        let tx = self.tx.clone();
         thread::spawn(move || {
-            for event in rx.lock().unwrap().iter() {
-                match event {
-                    PWEvent::Node(node_event) => {
-                        println!("Node event: {:?}", node_event);
-                        // Find node with object_path with something about google
-                        let node = node_info.lock().unwrap().iter().find(|a| a.1.object_path.find("google").is_some());
-                        // Now we lock node_info to insert the data
-                        node_info.lock().unwrap().insert(node_event.node_id.clone(), node_event);
-                    },
-                    PWEvent::RemoveNode(node_id) => {
-                        node_info.lock().unwrap().remove(&node_id);
-                    },
-                    // Handle other event variants here
+            // Ends once the PipeWire thread is gone and dropped its sender.
+            for event in rx.iter() {
+                let mut graph = graph.lock().unwrap();
+                let graph_event = match event {
+                    PWEvent::Object(object) => Some(graph.update(object)),
+                    PWEvent::GlobalRemoved(id) => graph.remove(id),
+                    PWEvent::Error(error) => {
+                        drop(graph);
+                        broadcast(&subscribers, ManagerEvent::Error(Arc::new(error)));
+                        continue;
+                    }
+                    PWEvent::Disconnected => {
+                        // The ids of the old connection mean nothing to the next one.
+                        graph.clear();
+                        defaults.lock().unwrap().clear();
+                        update_captures(&captures, &graph, &tx);
+                        drop(graph);
+                        broadcast(&subscribers, ManagerEvent::Disconnected);
+                        continue;
+                    }
+                    PWEvent::Reconnected { attempts } => {
+                        drop(graph);
+                        broadcast(&subscribers, ManagerEvent::Reconnected { attempts });
+                        continue;
+                    }
+                    PWEvent::Retargeted { stream, target } => {
+                        drop(graph);
+                        broadcast(&subscribers, ManagerEvent::Retargeted { stream, target });
+                        continue;
+                    }
+                    PWEvent::StreamCreated(stream) => {
+                        drop(graph);
+                        broadcast(&subscribers, ManagerEvent::StreamCreated(stream));
+                        continue;
+                    }
+                    PWEvent::StreamDestroyed(stream) => {
+                        drop(graph);
+                        broadcast(&subscribers, ManagerEvent::StreamDestroyed(stream));
+                        continue;
+                    }
+                    PWEvent::FormatNegotiated { stream, info } => {
+                        drop(graph);
+                        broadcast(&subscribers, ManagerEvent::FormatNegotiated { stream, info });
+                        continue;
+                    }
+                    PWEvent::Meter { stream, event } => {
+                        drop(graph);
+                        broadcast(&subscribers, ManagerEvent::Meter { stream, event });
+                        continue;
+                    }
+                    PWEvent::Underrun { stream, count } => {
+                        drop(graph);
+                        broadcast(&subscribers, ManagerEvent::Underrun { stream, count });
+                        continue;
+                    }
+                    PWEvent::DefaultChanged(change) => {
+                        drop(graph);
+                        update_defaults(&defaults, &change);
+                        broadcast(&subscribers, ManagerEvent::DefaultChanged(change));
+                        continue;
+                    }
+                    PWEvent::VirtualDeviceCreated { device, node } => {
+                        drop(graph);
+                        broadcast(&subscribers, ManagerEvent::VirtualDeviceCreated { device, node });
+                        continue;
+                    }
+                    PWEvent::VirtualDeviceDestroyed(device) => {
+                        drop(graph);
+                        broadcast(&subscribers, ManagerEvent::VirtualDeviceDestroyed(device));
+                        continue;
+                    }
+                };
+                update_captures(&captures, &graph, &tx);
+                drop(graph);
+
+                if let Some(graph_event) = graph_event {
+                    broadcast(&subscribers, ManagerEvent::Graph(graph_event));
                 }
             }
         });
     }
 }
+
+/// Keep the default devices in `defaults` in line with `change`.
+fn update_defaults(defaults: &Mutex<HashMap<DefaultKey, String>>, change: &DefaultChange) {
+    let mut defaults = defaults.lock().unwrap();
+    match change {
+        DefaultChange::Default { key, name: Some(name) } => {
+            defaults.insert(*key, name.clone());
+        }
+        DefaultChange::Default { key, name: None } => {
+            defaults.remove(key);
+        }
+        DefaultChange::Cleared => defaults.clear(),
+        DefaultChange::Target { .. } => {}
+    }
+}
+
+/// A receiver of [`ManagerEvent`]s.
+enum Subscriber {
+    Blocking(mpsc::Sender<ManagerEvent>),
+    Async(tokio::sync::mpsc::UnboundedSender<ManagerEvent>),
+}
+
+impl Subscriber {
+    /// Send `event`, returns `false` if the receiver went away.
+    fn send(&self, event: ManagerEvent) -> bool {
+        match self {
+            Subscriber::Blocking(sender) => sender.send(event).is_ok(),
+            Subscriber::Async(sender) => sender.send(event).is_ok(),
+        }
+    }
+}
+
+/// Send `event` to every subscriber, dropping the ones that went away.
+fn broadcast(subscribers: &Mutex<Vec<Subscriber>>, event: ManagerEvent) {
+    subscribers
+        .lock()
+        .unwrap()
+        .retain(|subscriber| subscriber.send(event.clone()));
+}
+
+/// Handle to the PipeWire thread started by [`PipeWireManager::setup_main`].
+///
+/// Dropping the handle stops the thread and waits for it to finish.
+pub struct ManagerHandle {
+    control: channel::Sender<IncomingEvent>,
+    next_stream: AtomicU32,
+    next_device: AtomicU32,
+    next_links: AtomicU32,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ManagerHandle {
+    /// Ask the PipeWire thread to quit its main loop.
+    ///
+    /// This does not wait for the thread, use [`join()`](Self::join) for that.
+    pub fn stop(&self) {
+        // The send only fails once the thread is already gone.
+        let _ = self.control.send(IncomingEvent::Terminate);
+    }
+
+    /// Move the stream `stream` to the node `target` without reconnecting it.
+    ///
+    /// Blocks until the PipeWire thread handled the request. Note that an application capture
+    /// moves the stream on its own whenever the followed application changes its nodes.
+    pub fn retarget(&self, stream: StreamId, target: u32) -> Result<(), EasyWireError> {
+        self.request(|reply| IncomingEvent::Retarget { stream, target, reply })
+    }
+
+    /// Create a new stream described by `config`, handing its frames to `sink`.
+    ///
+    /// The stream is connected right away and again after every reconnect, until it is
+    /// [destroyed](Self::destroy_stream). Blocks until the PipeWire thread handled the request.
+    pub fn create_stream(
+        &self,
+        config: EStreamConfig,
+        sink: Option<Box<dyn SampleSink>>,
+    ) -> Result<StreamId, EasyWireError> {
+        let sink = sink.map(|sink| Arc::new(Mutex::new(sink)));
+        self.add_stream(config, StreamIo::Capture(sink))
+    }
+
+    /// Create a new playback stream described by `config`, playing what `source` provides.
+    ///
+    /// A [`RingConsumer`](crate::ring_buffer::RingConsumer) can be used as the source to push
+    /// samples into the stream from any thread. Otherwise like
+    /// [`create_stream()`](Self::create_stream).
+    pub fn create_playback_stream(
+        &self,
+        config: EStreamConfig,
+        source: Box<dyn SampleSource>,
+    ) -> Result<StreamId, EasyWireError> {
+        self.add_stream(config, StreamIo::Playback(Arc::new(Mutex::new(source))))
+    }
+
+    /// Create a new capture stream described by `config`, yielding its frames as an async stream.
+    ///
+    /// Up to `capacity` chunks are buffered, see [`SampleStream`]. Unlike
+    /// [`create_stream()`](Self::create_stream) this does not block while the PipeWire thread
+    /// handles the request.
+    ///
+    /// # Panics
+    /// If `capacity` is 0.
+    pub async fn capture_stream(
+        &self,
+        config: EStreamConfig,
+        capacity: usize,
+    ) -> Result<(StreamId, SampleStream), EasyWireError> {
+        let (sink, samples) = sample_stream(capacity)?;
+        let sink: Box<dyn SampleSink> = Box::new(sink);
+        let io = StreamIo::Capture(Some(Arc::new(Mutex::new(sink))));
+        let stream = self.next_stream_id();
+        self.request_async(|reply| IncomingEvent::CreateStream { stream, config, io, reply }).await?;
+        Ok((stream, samples))
+    }
+
+    fn add_stream(&self, config: EStreamConfig, io: StreamIo) -> Result<StreamId, EasyWireError> {
+        let stream = self.next_stream_id();
+        self.request(|reply| IncomingEvent::CreateStream { stream, config, io, reply })?;
+        Ok(stream)
+    }
+
+    fn next_stream_id(&self) -> StreamId {
+        StreamId(self.next_stream.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Disconnect and forget the stream `stream`.
+    pub fn destroy_stream(&self, stream: StreamId) -> Result<(), EasyWireError> {
+        self.request(|reply| IncomingEvent::DestroyStream { stream, reply })
+    }
+
+    /// Create the virtual sink or source described by `config`.
+    ///
+    /// The device is created once the server announced the factory for it, and again after
+    /// every reconnect, each time reported as [`ManagerEvent::VirtualDeviceCreated`] with the
+    /// id of its node. Dropping the returned handle removes the device. Blocks until the
+    /// PipeWire thread handled the request.
+    pub fn create_virtual_device(
+        &self,
+        config: VirtualDeviceConfig,
+    ) -> Result<VirtualDeviceHandle, EasyWireError> {
+        let device = VirtualDeviceId(self.next_device.fetch_add(1, Ordering::Relaxed));
+        self.request(|reply| IncomingEvent::CreateVirtualDevice { device, config, reply })?;
+        Ok(VirtualDeviceHandle {
+            device,
+            control: self.control.clone(),
+        })
+    }
+
+    /// Make the node named `name` the default `key`, or remove the default with `None`.
+    ///
+    /// Set a configured key like [`DefaultKey::ConfiguredAudioSink`] to change a default
+    /// device, the session manager then updates the current one. Blocks until the PipeWire
+    /// thread handled the request, fails while the `default` metadata is not bound.
+    pub fn set_default(&self, key: DefaultKey, name: Option<&str>) -> Result<(), EasyWireError> {
+        let name = name.map(str::to_string);
+        self.request(|reply| IncomingEvent::SetDefault { key, name, reply })
+    }
+
+    /// Link the node `output` to the node `input`, pairing their ports according to `policy`.
+    ///
+    /// The ports are paired by channel, see [`node_links`](crate::node_links). Blocks until
+    /// every link is active, and fails if one of them reports an error or
+    /// [`LinkPolicy::timeout`] passed first. Dropping the returned handle removes the links.
+    ///
+    /// The links belong to the current connection, they are gone after a reconnect.
+    pub fn link_nodes(
+        &self,
+        output: u32,
+        input: u32,
+        policy: LinkPolicy,
+    ) -> Result<NodeLinksHandle, EasyWireError> {
+        let links = NodeLinksId(self.next_links.fetch_add(1, Ordering::Relaxed));
+        // Created first, so that links that did not become active are removed again.
+        let handle = NodeLinksHandle {
+            links,
+            control: Some(self.control.clone()),
+        };
+        let (reply, result) = mpsc::channel();
+        let timeout = policy.timeout;
+        self.control
+            .send(IncomingEvent::LinkNodes {
+                links,
+                output,
+                input,
+                policy,
+                reply: Reply::Blocking(reply),
+            })
+            .map_err(|_| EasyWireError::NotRunning)?;
+        match result.recv_timeout(timeout) {
+            Ok(result) => result.map(|()| handle),
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(EasyWireError::Link {
+                output,
+                input,
+                reason: format!("the links did not become active within {:?}", timeout),
+            }),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(EasyWireError::NotRunning),
+        }
+    }
+
+    /// Send the request built by `event` to the PipeWire thread and wait for its reply.
+    fn request<F>(&self, event: F) -> Result<(), EasyWireError>
+    where
+        F: FnOnce(Reply) -> IncomingEvent,
+    {
+        let (reply, result) = mpsc::channel();
+        self.control
+            .send(event(Reply::Blocking(reply)))
+            .map_err(|_| EasyWireError::NotRunning)?;
+        result.recv().map_err(|_| EasyWireError::NotRunning)?
+    }
+
+    /// Like [`request()`](Self::request), but awaits the reply.
+    async fn request_async<F>(&self, event: F) -> Result<(), EasyWireError>
+    where
+        F: FnOnce(Reply) -> IncomingEvent,
+    {
+        let (reply, result) = tokio::sync::oneshot::channel();
+        self.control
+            .send(event(Reply::Async(reply)))
+            .map_err(|_| EasyWireError::NotRunning)?;
+        result.await.map_err(|_| EasyWireError::NotRunning)?
+    }
+
+    /// Wait for the PipeWire thread to finish.
+    ///
+    /// The thread only finishes after [`stop()`](Self::stop) was called.
+    pub fn join(mut self) -> thread::Result<()> {
+        self.thread.take().map_or(Ok(()), |thread| thread.join())
+    }
+}
+
+impl Drop for ManagerHandle {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.stop();
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A virtual device hosted by the PipeWire thread, see
+/// [`ManagerHandle::create_virtual_device`].
+///
+/// Dropping the handle removes the device, without waiting for the PipeWire thread.
+pub struct VirtualDeviceHandle {
+    device: VirtualDeviceId,
+    control: channel::Sender<IncomingEvent>,
+}
+
+impl VirtualDeviceHandle {
+    pub fn id(&self) -> VirtualDeviceId {
+        self.device
+    }
+}
+
+impl Drop for VirtualDeviceHandle {
+    fn drop(&mut self) {
+        // Nobody waits for the reply, and the device is gone anyway once the thread is.
+        let (reply, _) = mpsc::channel();
+        let _ = self.control.send(IncomingEvent::DestroyVirtualDevice {
+            device: self.device,
+            reply: Reply::Blocking(reply),
+        });
+    }
+}
+
+/// Links between two nodes created by the PipeWire thread, see [`ManagerHandle::link_nodes`].
+///
+/// Dropping the handle removes the links, without waiting for the PipeWire thread.
+pub struct NodeLinksHandle {
+    links: NodeLinksId,
+    /// Taken once the links were removed.
+    control: Option<channel::Sender<IncomingEvent>>,
+}
+
+impl NodeLinksHandle {
+    pub fn id(&self) -> NodeLinksId {
+        self.links
+    }
+
+    /// Remove the links and wait for the PipeWire thread to do so.
+    pub fn unlink(mut self) -> Result<(), EasyWireError> {
+        let control = self.control.take().ok_or(EasyWireError::UnknownLinks(self.links))?;
+        let (reply, result) = mpsc::channel();
+        control
+            .send(IncomingEvent::UnlinkNodes {
+                links: self.links,
+                reply: Reply::Blocking(reply),
+            })
+            .map_err(|_| EasyWireError::NotRunning)?;
+        result.recv().map_err(|_| EasyWireError::NotRunning)?
+    }
+}
+
+impl Drop for NodeLinksHandle {
+    fn drop(&mut self) {
+        // Nobody waits for the reply, and the links are gone anyway once the thread is.
+        if let Some(control) = self.control.take() {
+            let (reply, _) = mpsc::channel();
+            let _ = control.send(IncomingEvent::UnlinkNodes {
+                links: self.links,
+                reply: Reply::Blocking(reply),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconnect_delay_backs_off() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            max_attempts: None,
+        };
+
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(4), Duration::from_millis(800));
+        assert_eq!(policy.delay(5), Duration::from_secs(1));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,632 @@
+//! Record the audio of a capture stream to files.
+//!
+//! A [`Recorder`] is used as the [`SampleSink`] of a stream. It copies every block of frames
+//! into a [`ring_buffer`] emptied by a writer thread, so the realtime thread never waits on the
+//! disk or allocates. The writer thread stores the samples as WAV or headerless PCM and starts
+//! a new file when the format changes or a [`Rotation`] limit is reached. Stopping through the
+//! [`RecorderHandle`] finalizes the last file.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use spa::param::audio::{AudioFormat, AudioInfoRaw, AudioInfoRawFlags, MAX_CHANNELS};
+
+use crate::e_stream::{frame_size, Frames, SampleSink};
+use crate::ring_buffer::{ring_buffer, RingConsumer, RingProducer};
+use crate::EasyWireError;
+
+/// How often the writer thread empties the ring buffer.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(50);
+/// How many bytes the writer thread moves from the ring buffer to the file at once.
+const DRAIN_CHUNK: usize = 64 * 1024;
+
+/// The kind of files written by a [`Recorder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    /// RIFF WAVE, upgraded to RF64 when the file outgrows 4 GiB.
+    ///
+    /// Only little endian formats and `U8` can be stored.
+    Wav,
+    /// The samples exactly as negotiated, without any header.
+    Raw,
+}
+
+/// When a [`Recorder`] starts a new file.
+///
+/// Files are always split at frame boundaries. Without any limit, which is the default, a new
+/// file is only started when the format changes.
+#[derive(Debug, Clone, Default)]
+pub struct Rotation {
+    /// Start a new file once this many bytes of samples were written, headers excluded.
+    pub max_bytes: Option<u64>,
+    /// Start a new file once this much audio was written.
+    pub max_duration: Option<Duration>,
+}
+
+/// Describes what a [`Recorder`] writes.
+#[derive(Debug, Clone)]
+pub struct RecorderConfig {
+    /// The first file. Following files get a number appended to the file stem, `take.wav`
+    /// is followed by `take-1.wav`, `take-2.wav` and so on.
+    pub path: PathBuf,
+    pub container: Container,
+    pub rotation: Rotation,
+    /// Size in bytes of the ring buffer between the realtime thread and the writer thread.
+    ///
+    /// Blocks that do not fit because the writer thread fell behind are dropped and counted,
+    /// see [`RecorderHandle::overruns`]. The default of 4 MiB holds about 10 seconds of stereo
+    /// `F32LE` at 48 kHz.
+    pub buffer_size: usize,
+}
+
+impl RecorderConfig {
+    pub fn new(path: impl Into<PathBuf>, container: Container) -> Self {
+        Self {
+            path: path.into(),
+            container,
+            rotation: Rotation::default(),
+            buffer_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+enum Message {
+    /// The samples following the first `at` bytes ever written to the ring are in `info`.
+    Format { info: AudioInfoRaw, at: u64 },
+    Stop,
+}
+
+/// A [`SampleSink`] recording everything its stream captures.
+///
+/// Planar formats are not recorded, request an interleaved one in the
+/// [`EStreamConfig`](crate::e_stream::EStreamConfig) of the stream.
+pub struct Recorder {
+    sender: mpsc::Sender<Message>,
+    ring: RingProducer,
+    /// Bytes ever written to the ring.
+    written: u64,
+    overruns: Arc<AtomicU64>,
+}
+
+impl Recorder {
+    /// Start the writer thread for `config`.
+    ///
+    /// The first file is created once the stream negotiated its format.
+    ///
+    /// # Panics
+    /// If [`RecorderConfig::buffer_size`] is 0.
+    pub fn new(config: RecorderConfig) -> Result<(Recorder, RecorderHandle), EasyWireError> {
+        let (sender, receiver) = mpsc::channel();
+        let (producer, consumer) = ring_buffer(config.buffer_size);
+        let path = config.path.clone();
+        let thread = thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || Writer::new(config, consumer).run(receiver))
+            .map_err(|source| recording_error(&path, source))?;
+
+        let overruns = Arc::new(AtomicU64::new(0));
+        Ok((
+            Recorder {
+                sender: sender.clone(),
+                ring: producer,
+                written: 0,
+                overruns: Arc::clone(&overruns),
+            },
+            RecorderHandle {
+                sender,
+                thread: Some(thread),
+                overruns,
+            },
+        ))
+    }
+}
+
+impl SampleSink for Recorder {
+    fn format_changed(&mut self, info: &AudioInfoRaw) {
+        let _ = self.sender.send(Message::Format {
+            info: *info,
+            at: self.written,
+        });
+    }
+
+    fn process(&mut self, frames: &Frames) {
+        if frames.plane().is_some() {
+            return;
+        }
+        // Only whole blocks go into the ring, so that the files never get a partial frame.
+        let data = frames.data();
+        if self.ring.available() < data.len() {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.written += self.ring.write(data) as u64;
+    }
+}
+
+/// Moves the samples from the ring buffer into the files, on the writer thread.
+struct Writer {
+    files: FileSet,
+    ring: RingConsumer,
+    /// Bytes ever read from the ring.
+    read: u64,
+    chunk: Vec<u8>,
+}
+
+impl Writer {
+    fn new(config: RecorderConfig, ring: RingConsumer) -> Self {
+        Self {
+            files: FileSet::new(config),
+            ring,
+            read: 0,
+            chunk: vec![0; DRAIN_CHUNK],
+        }
+    }
+
+    fn run(mut self, receiver: mpsc::Receiver<Message>) -> Result<Vec<PathBuf>, EasyWireError> {
+        let result = self.record(&receiver);
+        result.and(self.files.close()).map(|_| self.files.written)
+    }
+
+    fn record(&mut self, receiver: &mpsc::Receiver<Message>) -> Result<(), EasyWireError> {
+        loop {
+            let message = receiver.recv_timeout(DRAIN_INTERVAL);
+            let mut stop = matches!(message, Err(mpsc::RecvTimeoutError::Disconnected));
+            // A format change is sent before the samples following it are written, so every
+            // change within `end` is among the messages handled below.
+            let end = self.read + self.ring.available() as u64;
+            for message in message.ok().into_iter().chain(receiver.try_iter()) {
+                match message {
+                    Message::Format { info, at } => {
+                        self.drain(at)?;
+                        self.files.set_format(info)?;
+                    }
+                    Message::Stop => {
+                        stop = true;
+                        break;
+                    }
+                }
+            }
+            self.drain(end)?;
+            if stop {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Write the samples in the ring to the files, up to the first `end` bytes ever written.
+    fn drain(&mut self, end: u64) -> Result<(), EasyWireError> {
+        loop {
+            let len = (end.saturating_sub(self.read) as usize).min(DRAIN_CHUNK);
+            if len == 0 {
+                return Ok(());
+            }
+            let len = self.ring.read(&mut self.chunk[..len]);
+            self.read += len as u64;
+            self.files.write(&self.chunk[..len])?;
+        }
+    }
+}
+
+/// Controls the writer thread of a [`Recorder`].
+///
+/// Dropping the handle stops the recording like [`stop()`](Self::stop), ignoring errors.
+pub struct RecorderHandle {
+    sender: mpsc::Sender<Message>,
+    thread: Option<thread::JoinHandle<Result<Vec<PathBuf>, EasyWireError>>>,
+    overruns: Arc<AtomicU64>,
+}
+
+impl RecorderHandle {
+    /// The number of blocks dropped so far because the ring buffer was full, see
+    /// [`RecorderConfig::buffer_size`].
+    pub fn overruns(&self) -> u64 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Stop recording, finalize the current file and return every file written.
+    ///
+    /// Blocks until the writer thread wrote everything captured so far. Fails with the first
+    /// error of the writer thread, which stops recording at that point.
+    pub fn stop(mut self) -> Result<Vec<PathBuf>, EasyWireError> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<Vec<PathBuf>, EasyWireError> {
+        let _ = self.sender.send(Message::Stop);
+        match self.thread.take() {
+            Some(thread) => thread.join().map_err(|_| EasyWireError::NotRunning)?,
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+impl Drop for RecorderHandle {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+enum Output {
+    Wav(WavWriter<BufWriter<File>>),
+    Raw(BufWriter<File>),
+}
+
+/// The files of a recording, written on the writer thread.
+struct FileSet {
+    config: RecorderConfig,
+    info: Option<AudioInfoRaw>,
+    output: Option<(PathBuf, Output)>,
+    /// Bytes of samples in the current file.
+    len: u64,
+    written: Vec<PathBuf>,
+}
+
+impl FileSet {
+    fn new(config: RecorderConfig) -> Self {
+        Self {
+            config,
+            info: None,
+            output: None,
+            len: 0,
+            written: Vec::new(),
+        }
+    }
+
+    fn set_format(&mut self, info: AudioInfoRaw) -> Result<(), EasyWireError> {
+        self.close()?;
+        self.info = Some(info);
+        Ok(())
+    }
+
+    fn write(&mut self, mut data: &[u8]) -> Result<(), EasyWireError> {
+        let Some(info) = self.info else {
+            return Ok(());
+        };
+        let limit = self.limit(&info);
+
+        while !data.is_empty() {
+            if self.output.is_none() {
+                self.open(&info)?;
+            }
+            let len = match limit {
+                Some(limit) => data.len().min(limit.saturating_sub(self.len) as usize),
+                None => data.len(),
+            };
+            let (path, output) = self.output.as_mut().unwrap();
+            match output {
+                Output::Wav(writer) => writer.write(&data[..len]),
+                Output::Raw(writer) => writer.write_all(&data[..len]),
+            }
+            .map_err(|source| recording_error(path, source))?;
+
+            self.len += len as u64;
+            data = &data[len..];
+            if limit.map_or(false, |limit| self.len >= limit) {
+                self.close()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Bytes of samples per file, a whole number of frames and at least one.
+    fn limit(&self, info: &AudioInfoRaw) -> Option<u64> {
+        let frame_size = frame_size(info) as u64;
+        let rotation = &self.config.rotation;
+        let duration = rotation
+            .max_duration
+            .map(|duration| (duration.as_secs_f64() * info.rate() as f64) as u64 * frame_size);
+        let limit = match (rotation.max_bytes, duration) {
+            (Some(bytes), Some(duration)) => bytes.min(duration),
+            (bytes, duration) => bytes.or(duration)?,
+        };
+        let frame_size = frame_size.max(1);
+        Some((limit - limit % frame_size).max(frame_size))
+    }
+
+    fn open(&mut self, info: &AudioInfoRaw) -> Result<(), EasyWireError> {
+        let path = numbered_path(&self.config.path, self.written.len());
+        let file = File::create(&path)
+            .map(BufWriter::new)
+            .map_err(|source| recording_error(&path, source))?;
+        let output = match self.config.container {
+            Container::Wav => Output::Wav(
+                WavWriter::new(file, info).map_err(|source| recording_error(&path, source))?,
+            ),
+            Container::Raw => Output::Raw(file),
+        };
+        self.written.push(path.clone());
+        self.output = Some((path, output));
+        self.len = 0;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), EasyWireError> {
+        let Some((path, output)) = self.output.take() else {
+            return Ok(());
+        };
+        let mut file = match output {
+            Output::Wav(writer) => writer.finalize(),
+            Output::Raw(file) => Ok(file),
+        }
+        .map_err(|source| recording_error(&path, source))?;
+        file.flush().map_err(|source| recording_error(&path, source))
+    }
+}
+
+fn recording_error(path: &Path, source: io::Error) -> EasyWireError {
+    EasyWireError::Recording {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// `path` for the first file, with `-<index>` appended to the stem for the following ones.
+fn numbered_path(path: &Path, index: usize) -> PathBuf {
+    if index == 0 {
+        return path.to_path_buf();
+    }
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("-{}", index));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// The tail shared by the `KSDATAFORMAT_SUBTYPE_*` GUIDs, following the format tag.
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+/// Size of the `ds64` chunk, reserved as `JUNK` until the file needs RF64.
+const DS64_LEN: u32 = 28;
+
+/// The `WAVEFORMATEXTENSIBLE` speaker bits of the SPA channel positions.
+const SPEAKER_BITS: [(u32, u32); 18] = [
+    (spa::sys::SPA_AUDIO_CHANNEL_FL, 0x1),
+    (spa::sys::SPA_AUDIO_CHANNEL_FR, 0x2),
+    (spa::sys::SPA_AUDIO_CHANNEL_FC, 0x4),
+    (spa::sys::SPA_AUDIO_CHANNEL_LFE, 0x8),
+    (spa::sys::SPA_AUDIO_CHANNEL_RL, 0x10),
+    (spa::sys::SPA_AUDIO_CHANNEL_RR, 0x20),
+    (spa::sys::SPA_AUDIO_CHANNEL_FLC, 0x40),
+    (spa::sys::SPA_AUDIO_CHANNEL_FRC, 0x80),
+    (spa::sys::SPA_AUDIO_CHANNEL_RC, 0x100),
+    (spa::sys::SPA_AUDIO_CHANNEL_SL, 0x200),
+    (spa::sys::SPA_AUDIO_CHANNEL_SR, 0x400),
+    (spa::sys::SPA_AUDIO_CHANNEL_TC, 0x800),
+    (spa::sys::SPA_AUDIO_CHANNEL_TFL, 0x1000),
+    (spa::sys::SPA_AUDIO_CHANNEL_TFC, 0x2000),
+    (spa::sys::SPA_AUDIO_CHANNEL_TFR, 0x4000),
+    (spa::sys::SPA_AUDIO_CHANNEL_TRL, 0x8000),
+    (spa::sys::SPA_AUDIO_CHANNEL_TRC, 0x10000),
+    (spa::sys::SPA_AUDIO_CHANNEL_TRR, 0x20000),
+];
+
+/// The WAV channel mask of the positions of `info`, 0 if they cannot be described.
+///
+/// WAV files order their channels by speaker bit, so the positions have to be in that order.
+pub fn channel_mask(info: &AudioInfoRaw) -> u32 {
+    if info.flags().contains(AudioInfoRawFlags::UNPOSITIONED) {
+        return 0;
+    }
+    let channels = (info.channels() as usize).min(MAX_CHANNELS);
+    let mut mask = 0;
+    for position in &info.position()[..channels] {
+        match SPEAKER_BITS.iter().find(|(spa, _)| spa == position) {
+            Some(&(_, bit)) if bit > mask => mask |= bit,
+            _ => return 0,
+        }
+    }
+    mask
+}
+
+/// Format tag, container bits and valid bits of `format` in a WAV file.
+fn wav_format(format: AudioFormat) -> Option<(u16, u16, u16)> {
+    Some(match format {
+        AudioFormat::U8 => (WAVE_FORMAT_PCM, 8, 8),
+        AudioFormat::S16LE => (WAVE_FORMAT_PCM, 16, 16),
+        AudioFormat::S24LE => (WAVE_FORMAT_PCM, 24, 24),
+        AudioFormat::S24_32LE => (WAVE_FORMAT_PCM, 32, 24),
+        AudioFormat::S32LE => (WAVE_FORMAT_PCM, 32, 32),
+        AudioFormat::F32LE => (WAVE_FORMAT_IEEE_FLOAT, 32, 32),
+        AudioFormat::F64LE => (WAVE_FORMAT_IEEE_FLOAT, 64, 64),
+        _ => return None,
+    })
+}
+
+/// Writes samples into a WAV file.
+///
+/// The header is written up front with empty sizes, [`finalize()`](Self::finalize) patches
+/// them. Files with more than 4 GiB are turned into RF64 files at that point.
+pub struct WavWriter<W: Write + Seek> {
+    inner: W,
+    /// Offset of the size of the `data` chunk.
+    data_size_offset: u64,
+    block_align: u64,
+    len: u64,
+    /// Largest RIFF size that does not need RF64.
+    riff_limit: u64,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Write the header for samples described by `info` to `inner`.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if WAV cannot store `info`.
+    pub fn new(mut inner: W, info: &AudioInfoRaw) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let (tag, bits, valid_bits) = wav_format(info.format())
+            .ok_or_else(|| invalid(format!("{:?} cannot be stored in a WAV file", info.format())))?;
+        let channels = u16::try_from(info.channels())
+            .ok()
+            .filter(|channels| *channels > 0)
+            .ok_or_else(|| invalid(format!("invalid channel count {}", info.channels())))?;
+        let block_align = channels * bits / 8;
+        let extensible = channels > 2 || bits > 16 || bits != valid_bits;
+
+        let mut header = Vec::with_capacity(80);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(b"JUNK");
+        header.extend_from_slice(&DS64_LEN.to_le_bytes());
+        header.extend_from_slice(&[0; DS64_LEN as usize]);
+
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&(if extensible { 40u32 } else { 16 }).to_le_bytes());
+        header.extend_from_slice(&(if extensible { WAVE_FORMAT_EXTENSIBLE } else { tag }).to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&info.rate().to_le_bytes());
+        header.extend_from_slice(&(info.rate() * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&bits.to_le_bytes());
+        if extensible {
+            header.extend_from_slice(&22u16.to_le_bytes());
+            header.extend_from_slice(&valid_bits.to_le_bytes());
+            header.extend_from_slice(&channel_mask(info).to_le_bytes());
+            header.extend_from_slice(&tag.to_le_bytes());
+            header.extend_from_slice(&SUBFORMAT_GUID_TAIL);
+        }
+
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        inner.write_all(&header)?;
+
+        Ok(Self {
+            inner,
+            data_size_offset: header.len() as u64 - 4,
+            block_align: block_align as u64,
+            len: 0,
+            riff_limit: u32::MAX as u64,
+        })
+    }
+
+    /// Append `data`, which has to be whole frames of the format given to [`new()`](Self::new).
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner.write_all(data)?;
+        self.len += data.len() as u64;
+        Ok(())
+    }
+
+    /// Patch the sizes into the header and return the inner writer.
+    pub fn finalize(mut self) -> io::Result<W> {
+        // Chunks are padded to an even size.
+        let pad = self.len % 2;
+        if pad != 0 {
+            self.inner.write_all(&[0])?;
+        }
+        let riff_len = self.data_size_offset + 4 + self.len + pad - 8;
+
+        if riff_len <= self.riff_limit {
+            self.inner.seek(SeekFrom::Start(4))?;
+            self.inner.write_all(&(riff_len as u32).to_le_bytes())?;
+            self.inner.seek(SeekFrom::Start(self.data_size_offset))?;
+            self.inner.write_all(&(self.len as u32).to_le_bytes())?;
+        } else {
+            let mut header = Vec::with_capacity(12 + 8 + DS64_LEN as usize);
+            header.extend_from_slice(b"RF64");
+            header.extend_from_slice(&u32::MAX.to_le_bytes());
+            header.extend_from_slice(b"WAVE");
+            header.extend_from_slice(b"ds64");
+            header.extend_from_slice(&DS64_LEN.to_le_bytes());
+            header.extend_from_slice(&riff_len.to_le_bytes());
+            header.extend_from_slice(&self.len.to_le_bytes());
+            header.extend_from_slice(&(self.len / self.block_align).to_le_bytes());
+            header.extend_from_slice(&0u32.to_le_bytes());
+            self.inner.seek(SeekFrom::Start(0))?;
+            self.inner.write_all(&header)?;
+            self.inner.seek(SeekFrom::Start(self.data_size_offset))?;
+            self.inner.write_all(&u32::MAX.to_le_bytes())?;
+        }
+
+        self.inner.seek(SeekFrom::End(0))?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn info(format: AudioFormat, positions: &[u32]) -> AudioInfoRaw {
+        let mut info = AudioInfoRaw::new();
+        info.set_format(format);
+        info.set_rate(48000);
+        info.set_channels(positions.len() as u32);
+        let mut position = [0; 64];
+        position[..positions.len()].copy_from_slice(positions);
+        info.set_position(position);
+        info
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn wav_header() {
+        let stereo = info(AudioFormat::S16LE, &[spa::sys::SPA_AUDIO_CHANNEL_FL, spa::sys::SPA_AUDIO_CHANNEL_FR]);
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), &stereo).unwrap();
+        writer.write(&[0; 12]).unwrap();
+        let bytes = writer.finalize().unwrap().into_inner();
+
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[12..16], b"JUNK");
+        // fmt chunk after the reserved ds64 space, plain PCM.
+        assert_eq!(&bytes[48..52], b"fmt ");
+        assert_eq!(u32_at(&bytes, 52), 16);
+        assert_eq!(&bytes[72..76], b"data");
+        assert_eq!(u32_at(&bytes, 76), 12);
+        assert_eq!(bytes.len(), 80 + 12);
+
+        let surround = [
+            spa::sys::SPA_AUDIO_CHANNEL_FL,
+            spa::sys::SPA_AUDIO_CHANNEL_FR,
+            spa::sys::SPA_AUDIO_CHANNEL_FC,
+            spa::sys::SPA_AUDIO_CHANNEL_LFE,
+            spa::sys::SPA_AUDIO_CHANNEL_SL,
+            spa::sys::SPA_AUDIO_CHANNEL_SR,
+        ];
+        assert_eq!(channel_mask(&info(AudioFormat::F32LE, &surround)), 0x60F);
+        // Out of speaker bit order.
+        assert_eq!(channel_mask(&info(AudioFormat::F32LE, &[spa::sys::SPA_AUDIO_CHANNEL_FR, spa::sys::SPA_AUDIO_CHANNEL_FL])), 0);
+        assert!(WavWriter::new(Cursor::new(Vec::new()), &info(AudioFormat::S16BE, &surround)).is_err());
+    }
+
+    #[test]
+    fn rf64_upgrade() {
+        let mono = info(AudioFormat::F32LE, &[spa::sys::SPA_AUDIO_CHANNEL_MONO]);
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), &mono).unwrap();
+        writer.riff_limit = 64;
+        writer.write(&[0; 40]).unwrap();
+        let bytes = writer.finalize().unwrap().into_inner();
+
+        assert_eq!(&bytes[..4], b"RF64");
+        assert_eq!(u32_at(&bytes, 4), u32::MAX);
+        assert_eq!(&bytes[12..16], b"ds64");
+        let data_offset = bytes.len() - 40;
+        assert_eq!(u32_at(&bytes, data_offset - 4), u32::MAX);
+        // ds64: riff size, data size, sample count.
+        assert_eq!(u32_at(&bytes, 20) as usize, bytes.len() - 8);
+        assert_eq!(u32_at(&bytes, 28), 40);
+        assert_eq!(u32_at(&bytes, 36), 10);
+    }
+
+    #[test]
+    fn numbered_paths() {
+        assert_eq!(numbered_path(Path::new("/tmp/take.wav"), 0), Path::new("/tmp/take.wav"));
+        assert_eq!(numbered_path(Path::new("/tmp/take.wav"), 2), Path::new("/tmp/take-2.wav"));
+        assert_eq!(numbered_path(Path::new("take"), 1), Path::new("take-1"));
+    }
+}
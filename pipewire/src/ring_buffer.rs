@@ -0,0 +1,198 @@
+//! A single producer, single consumer byte ring buffer.
+//!
+//! This is the simplest way to feed a playback [`EStream`](crate::e_stream::EStream): the
+//! application writes interleaved samples into the [`RingProducer`] from any thread, and the
+//! [`RingConsumer`] is handed to the stream as its [`SampleSource`]. Neither side ever blocks.
+//!
+//! The ring holds one stream of interleaved frames, so a stream fed by it has to negotiate an
+//! interleaved format. With a planar format the consumer plays silence.
+
+use std::cell::UnsafeCell;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use spa::param::audio::AudioInfoRaw;
+
+use crate::e_stream::{FramesMut, SampleSource};
+
+struct Ring {
+    buf: Box<[UnsafeCell<u8>]>,
+    /// Total number of bytes ever written, only advanced by the producer.
+    head: AtomicUsize,
+    /// Total number of bytes ever read, only advanced by the consumer.
+    tail: AtomicUsize,
+}
+
+// SAFETY: the producer only writes the free part of `buf` and the consumer only reads the
+// filled part, `head` and `tail` hand bytes over between them.
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn ptr(&self) -> *mut u8 {
+        UnsafeCell::raw_get(self.buf.as_ptr())
+    }
+}
+
+/// Create a ring buffer holding up to `capacity` bytes.
+///
+/// # Panics
+/// If `capacity` is 0.
+pub fn ring_buffer(capacity: usize) -> (RingProducer, RingConsumer) {
+    assert!(capacity > 0, "ring buffer capacity must not be 0");
+    let ring = Arc::new(Ring {
+        buf: (0..capacity).map(|_| UnsafeCell::new(0)).collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+
+    (
+        RingProducer {
+            ring: Arc::clone(&ring),
+        },
+        RingConsumer {
+            ring,
+            planar: false,
+        },
+    )
+}
+
+/// The writing half of a [`ring_buffer`].
+pub struct RingProducer {
+    ring: Arc<Ring>,
+}
+
+impl RingProducer {
+    /// The number of bytes that can be written right now.
+    pub fn available(&self) -> usize {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        self.ring.capacity() - head.wrapping_sub(tail)
+    }
+
+    /// Write as much of `data` as fits and return the number of bytes written.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let len = data.len().min(self.available());
+        let capacity = self.ring.capacity();
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let start = head % capacity;
+        let first = len.min(capacity - start);
+
+        // SAFETY: the `len` bytes after `head` are free, the consumer does not read them.
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), self.ring.ptr().add(start), first);
+            ptr::copy_nonoverlapping(data.as_ptr().add(first), self.ring.ptr(), len - first);
+        }
+        self.ring.head.store(head.wrapping_add(len), Ordering::Release);
+        len
+    }
+
+    /// Write as many of `samples` as fit and return the number of samples written.
+    ///
+    /// The samples are written in native endianness, which is what `F32LE` means on little
+    /// endian machines.
+    pub fn write_f32(&mut self, samples: &[f32]) -> usize {
+        let size = std::mem::size_of::<f32>();
+        let count = samples.len().min(self.available() / size);
+        // SAFETY: any f32 can be viewed as its bytes.
+        let bytes =
+            unsafe { std::slice::from_raw_parts(samples.as_ptr() as *const u8, count * size) };
+        self.write(bytes) / size
+    }
+}
+
+/// The reading half of a [`ring_buffer`], usable as the [`SampleSource`] of a playback stream.
+pub struct RingConsumer {
+    ring: Arc<Ring>,
+    /// Whether the stream negotiated a planar format, which the ring can not feed.
+    planar: bool,
+}
+
+impl RingConsumer {
+    /// The number of bytes that can be read right now.
+    pub fn available(&self) -> usize {
+        let head = self.ring.head.load(Ordering::Acquire);
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        head.wrapping_sub(tail)
+    }
+
+    /// Read up to `out.len()` bytes into `out` and return the number of bytes read.
+    pub fn read(&mut self, out: &mut [u8]) -> usize {
+        let len = out.len().min(self.available());
+        let capacity = self.ring.capacity();
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        let start = tail % capacity;
+        let first = len.min(capacity - start);
+
+        // SAFETY: the `len` bytes after `tail` were written, the producer does not touch them.
+        unsafe {
+            ptr::copy_nonoverlapping(self.ring.ptr().add(start), out.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(self.ring.ptr(), out.as_mut_ptr().add(first), len - first);
+        }
+        self.ring.tail.store(tail.wrapping_add(len), Ordering::Release);
+        len
+    }
+}
+
+impl SampleSource for RingConsumer {
+    fn format_changed(&mut self, info: &AudioInfoRaw) {
+        // Every plane would read the next bytes of the interleaved ring and scramble the
+        // channels, so nothing is read until the format is interleaved again.
+        self.planar = info.format().is_planar();
+    }
+
+    fn fill(&mut self, frames: &mut FramesMut) -> usize {
+        let frame_size = frames.frame_size();
+        if self.planar || frame_size == 0 {
+            return 0;
+        }
+        // Only hand out whole frames, a partial one stays for the next block.
+        let count = frames.n_frames().min(self.available() / frame_size);
+        self.read(&mut frames.data_mut()[..count * frame_size]) / frame_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spa::param::audio::AudioFormat;
+
+    #[test]
+    fn wraps_around() {
+        let (mut producer, mut consumer) = ring_buffer(4);
+        let mut out = [0; 4];
+
+        assert_eq!(producer.write(&[1, 2, 3]), 3);
+        assert_eq!(consumer.read(&mut out[..2]), 2);
+        assert_eq!(out[..2], [1, 2]);
+
+        assert_eq!(producer.write(&[4, 5, 6, 7]), 3);
+        assert_eq!(producer.available(), 0);
+        assert_eq!(consumer.read(&mut out), 4);
+        assert_eq!(out, [3, 4, 5, 6]);
+        assert_eq!(consumer.available(), 0);
+    }
+
+    #[test]
+    fn rejects_planar_formats() {
+        let (mut producer, mut consumer) = ring_buffer(64);
+        producer.write_f32(&[0.5; 8]);
+
+        let mut info = AudioInfoRaw::new();
+        info.set_format(AudioFormat::F32P);
+        info.set_channels(2);
+        consumer.format_changed(&info);
+        let mut data = [0; 16];
+        assert_eq!(consumer.fill(&mut FramesMut::new(&info, &mut data, Some(0))), 0);
+        assert_eq!(consumer.available(), 32);
+
+        info.set_format(AudioFormat::F32LE);
+        consumer.format_changed(&info);
+        assert_eq!(consumer.fill(&mut FramesMut::new(&info, &mut data, None)), 2);
+        assert_eq!(consumer.available(), 16);
+    }
+}
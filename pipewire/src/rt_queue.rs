@@ -0,0 +1,333 @@
+//! Wait-free queues into and out of a realtime `process` callback.
+//!
+//! The `process` callback of a stream connected with
+//! [`StreamFlags::RT_PROCESS`](crate::stream::StreamFlags::RT_PROCESS) runs on the realtime data
+//! thread and must never take a lock, block or allocate. A [`queue`] hands values between one
+//! producer and one consumer thread with a fixed number of slots, and neither side ever waits
+//! on the other. A [`command_queue`] pairs two of them: commands go into the callback, which
+//! drains them at the start of every cycle, and replies come back out.
+//!
+//! Like a PipeWire `pw_loop_invoke`, the consuming end can also be attached to a loop. The loop
+//! then runs a callback for every value on its own thread, which is how replies from the
+//! realtime thread get handled on the main loop, and how commands for a stream that is not
+//! processing get applied on the data loop.
+//!
+//! # Examples
+//! ```no_run
+//! use pipewire::rt_queue::command_queue;
+//!
+//! enum Command {
+//!     SetGain(f32),
+//! }
+//!
+//! let (mut controller, mut processor) = command_queue::<Command, f32>(16).unwrap();
+//! let mut gain = 1.0;
+//!
+//! // In the process callback, on the realtime thread.
+//! for command in processor.commands.drain() {
+//!     match command {
+//!         Command::SetGain(new_gain) => {
+//!             // Hand the old value back, the callback must not drop anything that frees memory.
+//!             let _ = processor.replies.push(gain);
+//!             gain = new_gain;
+//!         }
+//!     }
+//! }
+//!
+//! // Anywhere else.
+//! if controller.commands.push(Command::SetGain(0.5)).is_err() {
+//!     eprintln!("the process callback is falling behind");
+//! }
+//! while let Some(old_gain) = controller.replies.pop() {
+//!     println!("gain was {}", old_gain);
+//! }
+//! ```
+
+use std::cell::{RefCell, UnsafeCell};
+use std::ffi::c_void;
+use std::mem::MaybeUninit;
+use std::os::unix::prelude::*;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use spa::support::system::IoFlags;
+
+use crate::loop_::{IoSource, LoopRef};
+use crate::Error;
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// Total number of values ever pushed, only advanced by the producer.
+    head: AtomicUsize,
+    /// Total number of values ever popped, only advanced by the consumer.
+    tail: AtomicUsize,
+    /// Set while no wakeup has to be sent, that is until the consumer is attached to a loop and
+    /// again from the first push after a wakeup until the loop handles it.
+    signaled: AtomicBool,
+    /// Written to wake the loop the consumer is attached to.
+    eventfd: RawFd,
+}
+
+// SAFETY: the producer only writes free slots and the consumer only reads filled ones, `head`
+// and `tail` hand the slots over between them.
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn slot(&self, index: usize) -> *mut MaybeUninit<T> {
+        self.slots[index % self.slots.len()].get()
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let head = *self.head.get_mut();
+        let mut tail = *self.tail.get_mut();
+        while tail != head {
+            // SAFETY: the slots between `tail` and `head` hold values nobody popped.
+            unsafe { (*self.slot(tail)).assume_init_drop() };
+            tail = tail.wrapping_add(1);
+        }
+        unsafe {
+            // Nothing is lost if closing fails, and there is no way to handle it here.
+            libc::close(self.eventfd);
+        }
+    }
+}
+
+/// Create a queue holding up to `capacity` values.
+///
+/// Fails if no eventfd can be created.
+///
+/// # Panics
+/// If `capacity` is 0.
+pub fn queue<T: Send>(capacity: usize) -> Result<(Producer<T>, Consumer<T>), Error> {
+    assert!(capacity > 0, "queue capacity must not be 0");
+
+    // Non-blocking, so that a wakeup never stalls the realtime thread.
+    let eventfd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+    if eventfd == -1 {
+        return Err(Error::CreationFailed);
+    }
+
+    let ring = Arc::new(Ring {
+        slots: (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        signaled: AtomicBool::new(true),
+        eventfd,
+    });
+
+    Ok((
+        Producer {
+            ring: Arc::clone(&ring),
+        },
+        Consumer { ring },
+    ))
+}
+
+/// The pushing half of a [`queue`].
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T: Send> Producer<T> {
+    /// Push `value`, or hand it back if the queue is full.
+    ///
+    /// This never blocks. If the consumer is attached to a loop, the first push after the loop
+    /// last ran writes to an eventfd to wake it.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        if head.wrapping_sub(tail) == self.ring.slots.len() {
+            return Err(value);
+        }
+
+        // SAFETY: the slot at `head` is free, the consumer does not read it.
+        unsafe { (*self.ring.slot(head)).write(value) };
+        self.ring.head.store(head.wrapping_add(1), Ordering::Release);
+
+        if !self.ring.signaled.swap(true, Ordering::AcqRel) {
+            wake(self.ring.eventfd);
+        }
+        Ok(())
+    }
+
+    /// Whether a push would fail right now.
+    pub fn is_full(&self) -> bool {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        head.wrapping_sub(tail) == self.ring.slots.len()
+    }
+}
+
+/// The popping half of a [`queue`].
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T: Send> Consumer<T> {
+    /// Pop the oldest value, `None` if the queue is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        let head = self.ring.head.load(Ordering::Acquire);
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        if head == tail {
+            return None;
+        }
+
+        // SAFETY: the slot at `tail` was written, the producer does not touch it until `tail`
+        // moves past it.
+        let value = unsafe { (*self.ring.slot(tail)).assume_init_read() };
+        self.ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    /// Pop the values that are in the queue.
+    ///
+    /// The iterator ends once the queue is empty, values pushed meanwhile are included.
+    pub fn drain(&mut self) -> Drain<'_, T> {
+        Drain { consumer: self }
+    }
+
+    /// Whether there is nothing to pop right now.
+    pub fn is_empty(&self) -> bool {
+        let head = self.ring.head.load(Ordering::Acquire);
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        head == tail
+    }
+}
+
+impl<T: Send + 'static> Consumer<T> {
+    /// Attach the consumer to a loop with a callback.
+    ///
+    /// The loop calls the callback with every value pushed from now on, and with the values
+    /// already waiting.
+    #[must_use]
+    pub fn attach<F>(self, loop_: &LoopRef, callback: F) -> AttachedConsumer<'_, T>
+    where
+        F: Fn(T) + 'static,
+    {
+        let eventfd = self.ring.eventfd;
+        let consumer = RefCell::new(self);
+
+        let source = loop_.add_io(eventfd, IoFlags::IN, move |_| {
+            let mut consumer = consumer.borrow_mut();
+            unsafe {
+                let mut _eventnum: u64 = 0;
+                libc::read(
+                    eventfd,
+                    &mut _eventnum as *mut u64 as *mut c_void,
+                    std::mem::size_of::<u64>(),
+                );
+            }
+            // Re-arm before draining, so that a value pushed while draining wakes the loop
+            // again instead of waiting for the next one.
+            consumer.ring.signaled.store(false, Ordering::Release);
+            consumer.drain().for_each(&callback);
+        });
+
+        // Handle what was pushed before attaching.
+        wake(eventfd);
+
+        AttachedConsumer {
+            _source: source,
+            _values: std::marker::PhantomData,
+        }
+    }
+}
+
+/// Make the loop waiting on `eventfd` run its callback.
+fn wake(eventfd: RawFd) {
+    unsafe {
+        libc::write(
+            eventfd,
+            &1u64 as *const u64 as *const c_void,
+            std::mem::size_of::<u64>(),
+        );
+    }
+}
+
+/// A [`Consumer`] that has been attached to a loop.
+///
+/// Dropping this will cause it to be detached from the loop, the values still waiting are
+/// dropped with the queue.
+pub struct AttachedConsumer<'l, T: 'static> {
+    _source: IoSource<'l, RawFd>,
+    _values: std::marker::PhantomData<T>,
+}
+
+/// An iterator popping the values of a [`Consumer`], see [`Consumer::drain`].
+pub struct Drain<'a, T> {
+    consumer: &'a mut Consumer<T>,
+}
+
+impl<T: Send> Iterator for Drain<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.consumer.pop()
+    }
+}
+
+/// The side of a [`command_queue`] that sends commands and receives replies.
+pub struct Controller<C, R> {
+    pub commands: Producer<C>,
+    pub replies: Consumer<R>,
+}
+
+/// The side of a [`command_queue`] that is owned by the `process` callback.
+pub struct Processor<C, R> {
+    pub commands: Consumer<C>,
+    pub replies: Producer<R>,
+}
+
+/// Create a queue of up to `capacity` commands into a `process` callback, and one of up to
+/// `capacity` replies back out, see the [module documentation](self).
+///
+/// Fails if no eventfd can be created.
+///
+/// # Panics
+/// If `capacity` is 0.
+pub fn command_queue<C: Send, R: Send>(
+    capacity: usize,
+) -> Result<(Controller<C, R>, Processor<C, R>), Error> {
+    let (command_producer, command_consumer) = queue(capacity)?;
+    let (reply_producer, reply_consumer) = queue(capacity)?;
+    Ok((
+        Controller {
+            commands: command_producer,
+            replies: reply_consumer,
+        },
+        Processor {
+            commands: command_consumer,
+            replies: reply_producer,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_and_pop() {
+        let (mut producer, mut consumer) = queue(2).unwrap();
+        assert_eq!(consumer.pop(), None::<String>);
+
+        assert_eq!(producer.push("a".to_string()), Ok(()));
+        assert_eq!(producer.push("b".to_string()), Ok(()));
+        assert!(producer.is_full());
+        assert_eq!(producer.push("c".to_string()), Err("c".to_string()));
+
+        assert_eq!(consumer.pop().as_deref(), Some("a"));
+        assert_eq!(producer.push("d".to_string()), Ok(()));
+        assert_eq!(consumer.drain().collect::<Vec<_>>(), ["b", "d"]);
+        assert!(consumer.is_empty());
+
+        // Values nobody popped are dropped with the queue.
+        let value = Arc::new(());
+        let (mut producer, consumer) = queue(1).unwrap();
+        assert!(producer.push(Arc::clone(&value)).is_ok());
+        drop((producer, consumer));
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
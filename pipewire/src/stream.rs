@@ -7,6 +7,7 @@ use crate::buffer::Buffer;
 use crate::{
     core::Core,
     error::Error,
+    keys,
     properties::{Properties, PropertiesRef},
 };
 use bitflags::bitflags;
@@ -20,10 +21,68 @@ use std::{
 };
 use std::ptr::NonNull;
 use std::rc::Rc;
+#[cfg(feature = "v0_3_50")]
 use pw_sys::pw_time;
 use crate::context::{Context, ContextInner, create_context_from_loop};
 use crate::core::create_core_inner;
 use crate::loop_::{loop_from_ptr, LoopInner, LoopRef};
+use crate::thread_loop::{ThreadLoopInner, ThreadLoopLockGuard};
+
+/// The values of the `media.category` property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaCategory {
+    Playback,
+    Capture,
+    Duplex,
+    Monitor,
+    Manager,
+}
+
+impl MediaCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Playback => "Playback",
+            Self::Capture => "Capture",
+            Self::Duplex => "Duplex",
+            Self::Monitor => "Monitor",
+            Self::Manager => "Manager",
+        }
+    }
+}
+
+/// The values of the `media.role` property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaRole {
+    Movie,
+    Music,
+    Camera,
+    Screen,
+    Communication,
+    Game,
+    Notification,
+    Dsp,
+    Production,
+    Accessibility,
+    Test,
+}
+
+impl MediaRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Movie => "Movie",
+            Self::Music => "Music",
+            Self::Camera => "Camera",
+            Self::Screen => "Screen",
+            Self::Communication => "Communication",
+            Self::Game => "Game",
+            Self::Notification => "Notification",
+            Self::Dsp => "DSP",
+            Self::Production => "Production",
+            Self::Accessibility => "Accessibility",
+            Self::Test => "Test",
+        }
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub enum StreamState {
@@ -139,6 +198,28 @@ impl StreamRef {
         self.add_local_listener_with_user_data(Default::default())
     }
 
+    /// Add a listener builder for a stream running on a [`ThreadLoop`](crate::thread_loop::ThreadLoop)
+    ///
+    /// Unlike [`add_local_listener_with_user_data`](Self::add_local_listener_with_user_data),
+    /// the callbacks and user data have to be `Send` and the resulting
+    /// [`ThreadLoopStreamListener`] can be moved to and used from other threads.
+    #[must_use = "Fluent builder API"]
+    pub fn add_listener_with_user_data<D: Send + 'static>(
+        &self,
+        user_data: D,
+    ) -> ListenerBuilder<'_, D> {
+        ListenerBuilder {
+            inner: self.add_local_listener_with_user_data(user_data),
+        }
+    }
+
+    /// Add a listener builder for a stream running on a [`ThreadLoop`](crate::thread_loop::ThreadLoop).
+    /// User data is initialized with its default value
+    #[must_use = "Fluent builder API"]
+    pub fn add_listener<D: Default + Send + 'static>(&self) -> ListenerBuilder<'_, D> {
+        self.add_listener_with_user_data(Default::default())
+    }
+
     /// Connect the stream
     ///
     /// Tries to connect to the node `id` in the given `direction`. If no node
@@ -185,17 +266,66 @@ impl StreamRef {
         Ok(())
     }
 
-    // pub fn update_properties(&self, properties: Properties) -> Result<(), Error> {
-    //     let r = unsafe {
-    //         pw_sys::pw_stream_update_properties(
-    //             self.as_raw_ptr(),
-    //             properties.into_raw(),
-    //         )
-    //     };
-    //
-    //     SpaResult::from_c(r).into_sync_result()?;
-    //     Ok(())
-    // }
+    /// Update the properties of the stream
+    ///
+    /// Every key of `properties` is added to the properties or replaces its current value. Returns
+    /// the number of properties that changed.
+    pub fn update_properties(
+        &self,
+        properties: &spa::utils::dict::DictRef,
+    ) -> Result<u32, Error> {
+        let r = unsafe {
+            pw_sys::pw_stream_update_properties(self.as_raw_ptr(), properties.as_raw_ptr())
+        };
+
+        let changed = SpaResult::from_c(r).into_sync_result()?;
+        Ok(changed as u32)
+    }
+
+    /// Set the property `key` of the stream to `value`.
+    fn update_property(&self, key: &str, value: &str) -> Result<(), Error> {
+        let mut properties = Properties::new();
+        properties.insert(key, value);
+        self.update_properties(properties.dict())?;
+        Ok(())
+    }
+
+    /// Set the `media.name` of the stream, a human readable name of what it plays or captures.
+    pub fn set_media_name(&self, name: &str) -> Result<(), Error> {
+        self.update_property(*keys::MEDIA_NAME, name)
+    }
+
+    /// Set the `node.description` of the stream, a human readable name of its node.
+    pub fn set_node_description(&self, description: &str) -> Result<(), Error> {
+        self.update_property(*keys::NODE_DESCRIPTION, description)
+    }
+
+    /// Set the `media.class` of the stream, like `Stream/Output/Audio`.
+    pub fn set_media_class(&self, class: &str) -> Result<(), Error> {
+        self.update_property(*keys::MEDIA_CLASS, class)
+    }
+
+    /// Set the `media.category` of the stream.
+    pub fn set_media_category(&self, category: MediaCategory) -> Result<(), Error> {
+        self.update_property(*keys::MEDIA_CATEGORY, category.as_str())
+    }
+
+    /// Set the `media.role` of the stream, which session managers use for routing and policy.
+    pub fn set_media_role(&self, role: MediaRole) -> Result<(), Error> {
+        self.update_property(*keys::MEDIA_ROLE, role.as_str())
+    }
+
+    /// Request a latency of `quantum` samples at `rate` through `node.latency`.
+    pub fn set_latency(&self, quantum: u32, rate: u32) -> Result<(), Error> {
+        self.update_property(*keys::NODE_LATENCY, &format!("{}/{}", quantum, rate))
+    }
+
+    /// Set the `target.object` of the stream, the `object.serial` or name of the node the
+    /// session manager should link it to.
+    #[cfg(feature = "v0_3_44")]
+    pub fn set_target_object(&self, target: &str) -> Result<(), Error> {
+        self.update_property(*keys::TARGET_OBJECT, target)
+    }
 
     /// Activate or deactivate the stream
     pub fn set_active(&self, active: bool) -> Result<(), Error> {
@@ -327,6 +457,20 @@ impl StreamRef {
         Ok(())
     }
 
+    /// Whether the stream runs on `thread_loop`, that is its context was created with the loop
+    /// of `thread_loop`.
+    fn runs_on(&self, thread_loop: &ThreadLoopInner) -> bool {
+        unsafe {
+            let core = pw_sys::pw_stream_get_core(self.as_raw_ptr());
+            if core.is_null() {
+                return false;
+            }
+            let context = pw_sys::pw_core_get_context(core);
+            !context.is_null()
+                && ptr::eq(pw_sys::pw_context_get_main_loop(context), thread_loop.as_raw())
+        }
+    }
+
     // TODO: test this function.
     // Do we have some of the information available to us???
     pub fn get_core(&self) -> Result<Core, Error> {
@@ -363,12 +507,83 @@ impl StreamRef {
         Ok(core)
     }
 
-    // TODO: pw_stream_get_time_n()
-    // Implement this after we have a way to get the time from the core and made stream manager
+    /// Query the timing information of the stream.
+    ///
+    /// This is safe to call from the process callback, which is where the values are the most
+    /// accurate.
+    #[cfg(feature = "v0_3_50")]
+    pub fn time(&self) -> Result<StreamTime, Error> {
+        let mut time = mem::MaybeUninit::<pw_time>::zeroed();
+        let r = unsafe {
+            pw_sys::pw_stream_get_time_n(
+                self.as_raw_ptr(),
+                time.as_mut_ptr(),
+                mem::size_of::<pw_time>(),
+            )
+        };
+        SpaResult::from_c(r).into_result()?;
 
-    // pub fn get_time(&self) -> Result<i32, Error> {
-    //     unsafe { pw_sys::pw_stream_get_time_n(self.as_raw_ptr(), , ) as i32 }
-    // }
+        // SAFETY: the struct was zeroed and the fields the library knows about were filled.
+        Ok(StreamTime::from_raw(unsafe { time.assume_init() }))
+    }
+}
+
+/// Timing information of a stream, see [`StreamRef::time()`].
+#[cfg(feature = "v0_3_50")]
+#[derive(Debug, Clone, Copy)]
+pub struct StreamTime {
+    /// The monotonic time in nanoseconds when this information was updated.
+    pub now: i64,
+    /// The rate of `ticks` and `delay`, usually `1/<sample rate>`.
+    pub rate: spa::utils::Fraction,
+    /// The ticks of the graph clock at `now`, increasing monotonically.
+    pub ticks: u64,
+    /// Delay in `rate` units until the data of the current cycle reaches the device, or
+    /// since it left the device for capture streams.
+    pub delay: i64,
+    /// Data queued in the stream but not yet processed, the sum of the buffer sizes.
+    pub queued: u64,
+    /// For raw audio, the samples buffered in the resampler of the stream.
+    pub buffered: u64,
+    /// The number of buffers queued in the stream.
+    pub queued_buffers: u32,
+    /// The number of buffers that can be dequeued.
+    pub avail_buffers: u32,
+    /// For raw audio playback, the suggested number of samples to fill in the next cycle.
+    #[cfg(feature = "v1_1")]
+    pub size: u64,
+}
+
+#[cfg(feature = "v0_3_50")]
+impl StreamTime {
+    fn from_raw(time: pw_time) -> Self {
+        Self {
+            now: time.now,
+            rate: time.rate,
+            ticks: time.ticks,
+            delay: time.delay,
+            queued: time.queued,
+            buffered: time.buffered,
+            queued_buffers: time.queued_buffers,
+            avail_buffers: time.avail_buffers,
+            #[cfg(feature = "v1_1")]
+            size: time.size,
+        }
+    }
+
+    /// Convert `ticks` in `rate` units to nanoseconds, `None` if the rate is not known yet.
+    pub fn to_nsec(&self, ticks: i64) -> Option<i64> {
+        if self.rate.denom == 0 {
+            return None;
+        }
+        let nsec = ticks as i128 * 1_000_000_000 * self.rate.num as i128 / self.rate.denom as i128;
+        Some(nsec as i64)
+    }
+
+    /// [`delay`](Self::delay) in nanoseconds, `None` if the rate is not known yet.
+    pub fn delay_nsec(&self) -> Option<i64> {
+        self.to_nsec(self.delay)
+    }
 }
 
 type ParamChangedCB<D> = dyn FnMut(&StreamRef, &mut D, u32, Option<&spa::pod::Pod>);
@@ -725,6 +940,180 @@ impl<D> std::ops::Drop for StreamListener<D> {
     }
 }
 
+/// A builder for a listener whose callbacks can run on the thread of a
+/// [`ThreadLoop`](crate::thread_loop::ThreadLoop), see
+/// [`StreamRef::add_listener_with_user_data`].
+#[must_use]
+pub struct ListenerBuilder<'a, D> {
+    inner: ListenerLocalBuilder<'a, D>,
+}
+
+impl<'a, D: Send + 'static> ListenerBuilder<'a, D> {
+    /// Set the callback for the `state_changed` event.
+    pub fn state_changed<F>(self, callback: F) -> Self
+    where
+        F: FnMut(&StreamRef, &mut D, StreamState, StreamState) + Send + 'static,
+    {
+        Self {
+            inner: self.inner.state_changed(callback),
+        }
+    }
+
+    /// Set the callback for the `control_info` event.
+    pub fn control_info<F>(self, callback: F) -> Self
+    where
+        F: FnMut(&StreamRef, &mut D, u32, *const pw_sys::pw_stream_control) + Send + 'static,
+    {
+        Self {
+            inner: self.inner.control_info(callback),
+        }
+    }
+
+    /// Set the callback for the `io_changed` event.
+    pub fn io_changed<F>(self, callback: F) -> Self
+    where
+        F: FnMut(&StreamRef, &mut D, u32, *mut os::raw::c_void, u32) + Send + 'static,
+    {
+        Self {
+            inner: self.inner.io_changed(callback),
+        }
+    }
+
+    /// Set the callback for the `param_changed` event.
+    pub fn param_changed<F>(self, callback: F) -> Self
+    where
+        F: FnMut(&StreamRef, &mut D, u32, Option<&spa::pod::Pod>) + Send + 'static,
+    {
+        Self {
+            inner: self.inner.param_changed(callback),
+        }
+    }
+
+    /// Set the callback for the `add_buffer` event.
+    pub fn add_buffer<F>(self, callback: F) -> Self
+    where
+        F: FnMut(&StreamRef, &mut D, *mut pw_sys::pw_buffer) + Send + 'static,
+    {
+        Self {
+            inner: self.inner.add_buffer(callback),
+        }
+    }
+
+    /// Set the callback for the `remove_buffer` event.
+    pub fn remove_buffer<F>(self, callback: F) -> Self
+    where
+        F: FnMut(&StreamRef, &mut D, *mut pw_sys::pw_buffer) + Send + 'static,
+    {
+        Self {
+            inner: self.inner.remove_buffer(callback),
+        }
+    }
+
+    /// Set the callback for the `process` event.
+    ///
+    /// With [`StreamFlags::RT_PROCESS`] the callback runs on the realtime data thread and is
+    /// not serialized by the thread loop lock, see [`ThreadLoopStreamListener`].
+    pub fn process<F>(self, callback: F) -> Self
+    where
+        F: FnMut(&StreamRef, &mut D) + Send + 'static,
+    {
+        Self {
+            inner: self.inner.process(callback),
+        }
+    }
+
+    /// Set the callback for the `drained` event.
+    pub fn drained<F>(self, callback: F) -> Self
+    where
+        F: FnMut(&StreamRef, &mut D) + Send + 'static,
+    {
+        Self {
+            inner: self.inner.drained(callback),
+        }
+    }
+
+    /// Register the Callbacks
+    ///
+    /// Returns a [`ThreadLoopStreamListener`] handle that will un-register the listener on
+    /// drop, and that borrows the thread loop so that it cannot outlive it.
+    ///
+    /// # Panics
+    /// If `lock` does not lock the thread loop the stream runs on.
+    pub fn register<'l>(
+        self,
+        lock: &ThreadLoopLockGuard<'l>,
+    ) -> Result<ThreadLoopStreamListener<'l, D>, Error> {
+        let thread_loop = lock.thread_loop();
+        assert!(
+            self.inner.stream.runs_on(thread_loop),
+            "Locked a different thread loop than the stream runs on"
+        );
+        let listener = self.inner.register()?;
+        Ok(ThreadLoopStreamListener {
+            listener: mem::ManuallyDrop::new(listener),
+            thread_loop,
+        })
+    }
+}
+
+/// A stream listener registered through a [`ThreadLoop`](crate::thread_loop::ThreadLoop) lock
+///
+/// The listener can be moved to other threads that do not outlive the thread loop, such as
+/// [scoped threads](std::thread::scope). Its user data is reachable with
+/// [`user_data`](Self::user_data) while the thread loop is locked, and dropping it takes the
+/// lock to un-register the callbacks.
+///
+/// # Realtime processing
+/// Holding the thread loop lock keeps every callback from running except `process` of a
+/// stream connected with [`StreamFlags::RT_PROCESS`], which runs on the data thread at any
+/// time. That is why [`user_data`](Self::user_data) is unsafe. Commands for a running stream
+/// are sent through a [`command_queue`](crate::rt_queue::command_queue) instead: the user data
+/// owns the processing side, which the process callback drains at the start of every cycle,
+/// while other threads keep the controlling side.
+pub struct ThreadLoopStreamListener<'l, D> {
+    listener: mem::ManuallyDrop<StreamListener<D>>,
+    thread_loop: &'l ThreadLoopInner,
+}
+
+// SAFETY: The builder only accepts `Send` callbacks and user data, and they are only accessed
+// from the loop thread or with the thread loop locked. Locking and unlocking the thread loop
+// is allowed from any thread.
+unsafe impl<D: Send> Send for ThreadLoopStreamListener<'_, D> {}
+
+impl<D> ThreadLoopStreamListener<'_, D> {
+    /// Access the user data of the listener
+    ///
+    /// # Safety
+    /// The `process` callback must not run while the returned reference is alive. The lock
+    /// only guarantees this if the stream was not connected with [`StreamFlags::RT_PROCESS`],
+    /// otherwise the stream has to be inactive or disconnected.
+    ///
+    /// # Panics
+    /// If `lock` does not lock the thread loop the listener was registered with.
+    pub unsafe fn user_data<'a>(&'a mut self, lock: &'a ThreadLoopLockGuard) -> &'a mut D {
+        assert!(
+            ptr::eq(lock.thread_loop(), self.thread_loop),
+            "Locked a different thread loop than the listener was registered with"
+        );
+        &mut self.listener._data.user_data
+    }
+
+    /// Stop the listener from receiving any events
+    ///
+    /// Removes the listener registration and cleans up allocated resources.
+    pub fn unregister(self) {
+        // do nothing, drop will clean up.
+    }
+}
+
+impl<D> std::ops::Drop for ThreadLoopStreamListener<'_, D> {
+    fn drop(&mut self) {
+        // The lock is recursive, so this is fine from the loop thread and with the lock held.
+        let _lock = self.thread_loop.lock();
+        unsafe { mem::ManuallyDrop::drop(&mut self.listener) };
+    }
+}
+
 bitflags! {
     /// Extra flags that can be used in [`Stream::connect()`]
     #[derive(Debug, PartialEq, Eq, Clone, Copy)]
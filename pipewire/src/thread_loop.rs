@@ -94,6 +94,11 @@ impl<'a> ThreadLoopLockGuard<'a> {
         ThreadLoopLockGuard { thread_loop }
     }
 
+    /// The locked thread loop.
+    pub(crate) fn thread_loop(&self) -> &'a ThreadLoopInner {
+        self.thread_loop
+    }
+
     /// Unlock the loop
     ///
     /// Unlocking the loop will call `drop()`
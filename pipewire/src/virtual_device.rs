@@ -0,0 +1,246 @@
+//! Virtual sinks and sources.
+//!
+//! A [`VirtualDevice`] is a node the server creates through the `adapter` factory around the
+//! `support.null-audio-sink` plugin. It shows up in the graph like a hardware device:
+//! applications play into a virtual sink and everything played into it can be captured from
+//! its monitor ports, a virtual source is recorded from like a microphone. The node is removed
+//! from the server once its handle is dropped.
+//!
+//! Loopback devices are made of two streams set up by `libpipewire-module-loopback`, which is a
+//! module loaded into a context and not a factory, so they can not be created through
+//! [`Core::create_object`]. A virtual sink captured by a stream covers the same needs.
+//!
+//! The [`PipeWireManager`](crate::pipe_wire_manager::PipeWireManager) keeps virtual devices
+//! across reconnects, see
+//! [`ManagerHandle::create_virtual_device`](crate::pipe_wire_manager::ManagerHandle::create_virtual_device).
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::core::Core;
+use crate::node::Node;
+use crate::properties::Properties;
+use crate::proxy::{ProxyListener, ProxyT};
+use crate::{keys, EasyWireError};
+
+/// The factory virtual devices are created with.
+pub const FACTORY: &str = "adapter";
+
+/// The plugin the [`FACTORY`] wraps into a virtual device.
+const NULL_AUDIO_SINK: &str = "support.null-audio-sink";
+
+/// The short names of the SPA channel positions, as understood by `audio.position`.
+const POSITION_NAMES: [(u32, &str); 27] = [
+    (spa::sys::SPA_AUDIO_CHANNEL_MONO, "MONO"),
+    (spa::sys::SPA_AUDIO_CHANNEL_FL, "FL"),
+    (spa::sys::SPA_AUDIO_CHANNEL_FR, "FR"),
+    (spa::sys::SPA_AUDIO_CHANNEL_FC, "FC"),
+    (spa::sys::SPA_AUDIO_CHANNEL_LFE, "LFE"),
+    (spa::sys::SPA_AUDIO_CHANNEL_SL, "SL"),
+    (spa::sys::SPA_AUDIO_CHANNEL_SR, "SR"),
+    (spa::sys::SPA_AUDIO_CHANNEL_FLC, "FLC"),
+    (spa::sys::SPA_AUDIO_CHANNEL_FRC, "FRC"),
+    (spa::sys::SPA_AUDIO_CHANNEL_RC, "RC"),
+    (spa::sys::SPA_AUDIO_CHANNEL_RL, "RL"),
+    (spa::sys::SPA_AUDIO_CHANNEL_RR, "RR"),
+    (spa::sys::SPA_AUDIO_CHANNEL_TC, "TC"),
+    (spa::sys::SPA_AUDIO_CHANNEL_TFL, "TFL"),
+    (spa::sys::SPA_AUDIO_CHANNEL_TFC, "TFC"),
+    (spa::sys::SPA_AUDIO_CHANNEL_TFR, "TFR"),
+    (spa::sys::SPA_AUDIO_CHANNEL_TRL, "TRL"),
+    (spa::sys::SPA_AUDIO_CHANNEL_TRC, "TRC"),
+    (spa::sys::SPA_AUDIO_CHANNEL_TRR, "TRR"),
+    (spa::sys::SPA_AUDIO_CHANNEL_RLC, "RLC"),
+    (spa::sys::SPA_AUDIO_CHANNEL_RRC, "RRC"),
+    (spa::sys::SPA_AUDIO_CHANNEL_FLW, "FLW"),
+    (spa::sys::SPA_AUDIO_CHANNEL_FRW, "FRW"),
+    (spa::sys::SPA_AUDIO_CHANNEL_LFE2, "LFE2"),
+    (spa::sys::SPA_AUDIO_CHANNEL_FLH, "FLH"),
+    (spa::sys::SPA_AUDIO_CHANNEL_FCH, "FCH"),
+    (spa::sys::SPA_AUDIO_CHANNEL_FRH, "FRH"),
+];
+
+/// Identifies a virtual device hosted by the
+/// [`PipeWireManager`](crate::pipe_wire_manager::PipeWireManager).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VirtualDeviceId(pub(crate) u32);
+
+impl VirtualDeviceId {
+    pub fn as_raw(&self) -> u32 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for VirtualDeviceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Whether a virtual device is played into or recorded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtualDeviceKind {
+    /// Applications play into it, `Audio/Sink`.
+    Sink,
+    /// Applications record from it, `Audio/Source/Virtual`.
+    Source,
+}
+
+impl VirtualDeviceKind {
+    /// The `media.class` of devices of this kind.
+    pub fn media_class(&self) -> &'static str {
+        match self {
+            VirtualDeviceKind::Sink => "Audio/Sink",
+            VirtualDeviceKind::Source => "Audio/Source/Virtual",
+        }
+    }
+}
+
+/// Describes a virtual device to create.
+#[derive(Debug, Clone)]
+pub struct VirtualDeviceConfig {
+    /// The `node.name` of the device.
+    pub name: String,
+    /// The `node.description` shown to users, `None` uses the name.
+    pub description: Option<String>,
+    pub kind: VirtualDeviceKind,
+    /// The position of every channel, as `SPA_AUDIO_CHANNEL_*` values.
+    pub positions: Vec<u32>,
+    /// The sample rate of the device, `None` follows the graph.
+    pub rate: Option<u32>,
+}
+
+impl VirtualDeviceConfig {
+    /// A stereo device of the kind `kind`.
+    pub fn new(name: &str, kind: VirtualDeviceKind) -> Self {
+        Self {
+            name: name.to_string(),
+            description: None,
+            kind,
+            positions: vec![spa::sys::SPA_AUDIO_CHANNEL_FL, spa::sys::SPA_AUDIO_CHANNEL_FR],
+            rate: None,
+        }
+    }
+
+    /// A stereo virtual sink.
+    pub fn sink(name: &str) -> Self {
+        Self::new(name, VirtualDeviceKind::Sink)
+    }
+
+    /// A stereo virtual source.
+    pub fn source(name: &str) -> Self {
+        Self::new(name, VirtualDeviceKind::Source)
+    }
+
+    /// The properties to create the device with through the [`FACTORY`].
+    pub fn properties(&self) -> Result<Properties, EasyWireError> {
+        if self.positions.is_empty() {
+            return Err(EasyWireError::VirtualDevice(format!("{} has no channels", self.name)));
+        }
+        let positions = self
+            .positions
+            .iter()
+            .map(|&position| {
+                position_name(position).ok_or_else(|| {
+                    EasyWireError::VirtualDevice(format!("unknown channel position {}", position))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut properties = Properties::new();
+        properties.insert(*keys::FACTORY_NAME, NULL_AUDIO_SINK);
+        properties.insert(*keys::NODE_NAME, self.name.as_str());
+        properties.insert(
+            *keys::NODE_DESCRIPTION,
+            self.description.as_deref().unwrap_or(&self.name),
+        );
+        properties.insert(*keys::MEDIA_CLASS, self.kind.media_class());
+        properties.insert(*keys::AUDIO_CHANNELS, positions.len().to_string());
+        properties.insert("audio.position", positions.join(","));
+        if let Some(rate) = self.rate {
+            properties.insert(*keys::AUDIO_RATE, rate.to_string());
+        }
+        // Remove the node with the proxy instead of leaving it behind on the server.
+        properties.insert(*keys::OBJECT_LINGER, "false");
+        Ok(properties)
+    }
+}
+
+/// The `audio.position` name of the SPA channel position `position`.
+fn position_name(position: u32) -> Option<String> {
+    if let Some((_, name)) = POSITION_NAMES.iter().find(|(spa, _)| *spa == position) {
+        return Some(name.to_string());
+    }
+    position
+        .checked_sub(spa::sys::SPA_AUDIO_CHANNEL_AUX0)
+        .filter(|aux| *aux < 64)
+        .map(|aux| format!("AUX{}", aux))
+}
+
+/// A virtual device on the server, see the [module documentation](self).
+///
+/// Dropping this removes the device.
+pub struct VirtualDevice {
+    // Declared first so the listener is removed before the proxy is destroyed.
+    _listener: ProxyListener,
+    node: Node,
+    id: Rc<Cell<Option<u32>>>,
+}
+
+impl VirtualDevice {
+    /// Ask the server to create the device described by `config`.
+    ///
+    /// The server needs to offer the [`FACTORY`], which is looked up through the registry
+    /// like any other global. The device gets its global id once the server bound it, `bound`
+    /// is called with it then.
+    pub fn create<F>(core: &Core, config: &VirtualDeviceConfig, bound: F) -> Result<Self, EasyWireError>
+    where
+        F: Fn(u32) + 'static,
+    {
+        let node: Node = core.create_object(FACTORY, &config.properties()?)?;
+        let id = Rc::new(Cell::new(None));
+        let listener = node
+            .upcast_ref()
+            .add_listener_local()
+            .bound({
+                let id = Rc::clone(&id);
+                move |global_id| {
+                    id.set(Some(global_id));
+                    bound(global_id);
+                }
+            })
+            .register();
+
+        Ok(Self {
+            _listener: listener,
+            node,
+            id,
+        })
+    }
+
+    /// The global id of the device's node, `None` until the server bound it.
+    pub fn id(&self) -> Option<u32> {
+        self.id.get()
+    }
+
+    /// The proxy of the device's node.
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position_names() {
+        assert_eq!(position_name(spa::sys::SPA_AUDIO_CHANNEL_FL).as_deref(), Some("FL"));
+        assert_eq!(position_name(spa::sys::SPA_AUDIO_CHANNEL_AUX0 + 3).as_deref(), Some("AUX3"));
+        assert_eq!(position_name(spa::sys::SPA_AUDIO_CHANNEL_UNKNOWN), None);
+
+        let mut config = VirtualDeviceConfig::source("mic");
+        config.positions.clear();
+        assert!(config.properties().is_err());
+    }
+}